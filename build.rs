@@ -0,0 +1,29 @@
+//! Build script embedding the git commit into the binary.
+//!
+//! The commit is taken from the `DEEPCLAUDE_GIT_SHA` environment variable
+//! when set (e.g. in Docker builds without a `.git` directory), otherwise
+//! from `git rev-parse`, falling back to `unknown`.
+
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=DEEPCLAUDE_GIT_SHA");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+
+    let sha = std::env::var("DEEPCLAUDE_GIT_SHA")
+        .ok()
+        .filter(|sha| !sha.is_empty())
+        .or_else(|| {
+            Command::new("git")
+                .args(["rev-parse", "--short=12", "HEAD"])
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .and_then(|output| String::from_utf8(output.stdout).ok())
+                .map(|sha| sha.trim().to_string())
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=DEEPCLAUDE_GIT_SHA={}", sha);
+}
@@ -0,0 +1,496 @@
+//! Interactive terminal chat client for a DeepClaude server.
+//!
+//! Streams each reply through [`deepclaude::sdk`], showing extended thinking
+//! dimmed above the answer, and prints the cost of every turn. The
+//! conversation is kept for the whole session, so every message is sent
+//! with the ones before it.
+//!
+//! Lines starting with `/` are commands:
+//!
+//! - `/system [PROMPT|off]`: show, set or clear the system prompt
+//! - `/budget [TOKENS]`: show or set the thinking budget; `0` disables thinking
+//! - `/model [NAME]`: show or select the Claude model
+//! - `/thinking [show|collapse]`: stream thinking, or collapse it to one line
+//! - `/expand`: show the thinking of the last reply
+//! - `/save PATH`: save the conversation as a request body for the server
+//! - `/clear`: start a new conversation
+//! - `/help`, `/quit`
+//!
+//! Usage: `deepclaude-cli [--url URL] [--api-key KEY] [--model NAME] [--budget TOKENS]`
+//!
+//! Built with the `cli` feature: `cargo run --features cli --bin deepclaude-cli`.
+
+use clap::Parser;
+use deepclaude::{
+    models::{ApiRequest, CombinedUsage, ContentBlock, Message, Role, StreamEvent},
+    pricing::format_cost,
+    sdk::{ChatRequest, DeepClaudeClient},
+};
+use futures::StreamExt;
+use rustyline::{error::ReadlineError, DefaultEditor};
+use std::io::{IsTerminal, Write};
+
+/// Tokens reserved for the answer on top of the thinking budget, since
+/// Anthropic counts thinking against `max_tokens`.
+const MIN_ANSWER_TOKENS: u32 = 1024;
+
+/// Interactive terminal chat with a DeepClaude server.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+    /// URL of the DeepClaude server
+    #[arg(long, env = "DEEPCLAUDE_URL", default_value = "http://127.0.0.1:1337")]
+    url: String,
+
+    /// Anthropic API key to send with each request
+    #[arg(long, env = "ANTHROPIC_API_KEY", hide_env_values = true)]
+    api_key: String,
+
+    /// Project to bill requests to
+    #[arg(long, env = "DEEPCLAUDE_PROJECT")]
+    project: Option<String>,
+
+    /// Claude model [default: the server's default]
+    #[arg(short, long)]
+    model: Option<String>,
+
+    /// System prompt
+    #[arg(short, long)]
+    system: Option<String>,
+
+    /// Extended thinking budget in tokens; 0 disables thinking
+    #[arg(short, long, default_value_t = 4000)]
+    budget: u32,
+
+    /// Maximum number of tokens per reply, including thinking
+    #[arg(long, default_value_t = 8192)]
+    max_tokens: u32,
+
+    /// Collapse thinking to a single line instead of streaming it
+    #[arg(long)]
+    collapse_thinking: bool,
+}
+
+/// Terminal styling, disabled when stdout is not a terminal or `NO_COLOR`
+/// is set.
+#[derive(Debug, Clone, Copy)]
+struct Style {
+    color: bool,
+}
+
+impl Style {
+    fn detect() -> Self {
+        Self {
+            color: std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+        }
+    }
+
+    fn dim(&self, text: &str) -> String {
+        self.wrap("\x1b[2m", text)
+    }
+
+    fn bold(&self, text: &str) -> String {
+        self.wrap("\x1b[1m", text)
+    }
+
+    fn error(&self, text: &str) -> String {
+        self.wrap("\x1b[31m", text)
+    }
+
+    /// Returns to the start of the line, clearing it where supported.
+    fn rewind(&self) -> &'static str {
+        if self.color {
+            "\r\x1b[2K"
+        } else {
+            "\r"
+        }
+    }
+
+    fn wrap(&self, code: &str, text: &str) -> String {
+        if self.color {
+            format!("{code}{text}\x1b[0m")
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+/// Which part of a reply is being printed.
+#[derive(Debug, PartialEq)]
+enum Section {
+    None,
+    Thinking,
+    Text,
+}
+
+/// Prints a streamed reply as its deltas arrive and keeps its thinking
+/// and text.
+struct Renderer {
+    style: Style,
+    collapse: bool,
+    section: Section,
+    thinking: String,
+    text: String,
+}
+
+impl Renderer {
+    fn new(style: Style, collapse: bool) -> Self {
+        Self {
+            style,
+            collapse,
+            section: Section::None,
+            thinking: String::new(),
+            text: String::new(),
+        }
+    }
+
+    /// Prints one streamed content delta.
+    fn block(&mut self, block: ContentBlock) {
+        if let Some(thinking) = block.thinking {
+            self.thinking(&thinking);
+        } else if !block.text.is_empty() {
+            self.text(&block.text);
+        }
+        let _ = std::io::stdout().flush();
+    }
+
+    fn thinking(&mut self, delta: &str) {
+        if self.section != Section::Thinking {
+            self.section = Section::Thinking;
+            if !self.collapse {
+                println!("{}", self.style.dim("▾ thinking"));
+            }
+        }
+        self.thinking.push_str(delta);
+
+        if self.collapse {
+            print!(
+                "{}{}",
+                self.style.rewind(),
+                self.style.dim(&self.summary("thinking…"))
+            );
+        } else {
+            print!("{}", self.style.dim(delta));
+        }
+    }
+
+    fn text(&mut self, delta: &str) {
+        if self.section == Section::Thinking {
+            self.end_thinking();
+        }
+        self.section = Section::Text;
+        self.text.push_str(delta);
+        print!("{delta}");
+    }
+
+    fn end_thinking(&self) {
+        if self.collapse {
+            println!(
+                "{}{}",
+                self.style.rewind(),
+                self.style.dim(&self.summary("thought for"))
+            );
+        } else {
+            println!("\n");
+        }
+    }
+
+    /// Ends the reply's output on a new line.
+    fn finish(&self) {
+        match self.section {
+            Section::None => {}
+            Section::Thinking => self.end_thinking(),
+            Section::Text => println!(),
+        }
+    }
+
+    fn summary(&self, verb: &str) -> String {
+        format!(
+            "▸ {} {} words (/expand to show)",
+            verb,
+            self.thinking.split_whitespace().count()
+        )
+    }
+}
+
+/// A chat session: the conversation so far and the settings for the next
+/// request.
+struct Session {
+    client: DeepClaudeClient,
+    style: Style,
+    system: Option<String>,
+    model: Option<String>,
+    budget: u32,
+    max_tokens: u32,
+    collapse_thinking: bool,
+    messages: Vec<Message>,
+    last_thinking: String,
+    total_cost: f64,
+}
+
+impl Session {
+    fn new(args: Args) -> Self {
+        let mut client = DeepClaudeClient::new(args.url, args.api_key);
+        if let Some(project) = args.project {
+            client = client.with_project(project);
+        }
+
+        Self {
+            client,
+            style: Style::detect(),
+            system: args.system,
+            model: args.model,
+            budget: args.budget,
+            max_tokens: args.max_tokens,
+            collapse_thinking: args.collapse_thinking,
+            messages: Vec::new(),
+            last_thinking: String::new(),
+            total_cost: 0.0,
+        }
+    }
+
+    /// Builds a request for the conversation so far with the current
+    /// settings.
+    fn request(&self) -> ApiRequest {
+        let mut request = ChatRequest::new();
+        if let Some(system) = &self.system {
+            request = request.system(system.as_str());
+        }
+        for message in &self.messages {
+            request = request.message(message.role.clone(), message.content.clone());
+        }
+        if let Some(model) = &self.model {
+            request = request.model(model);
+        }
+        if self.budget > 0 {
+            request = request
+                .thinking_budget(self.budget)
+                .max_tokens(self.max_tokens.max(self.budget + MIN_ANSWER_TOKENS));
+        } else {
+            request = request.max_tokens(self.max_tokens);
+        }
+        request.build()
+    }
+
+    /// Sends a user message and streams the reply.
+    ///
+    /// The message and reply are added to the conversation only if the
+    /// reply completes; `Ctrl-C` abandons the turn.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request is rejected or the stream fails
+    async fn turn(&mut self, input: String) -> anyhow::Result<()> {
+        self.messages.push(Message {
+            role: Role::User,
+            content: input.into(),
+        });
+        let result = self.stream_reply().await;
+        if !matches!(result, Ok(true)) {
+            self.messages.pop();
+        }
+        result.map(|_| ())
+    }
+
+    /// Streams the reply to the conversation so far.
+    ///
+    /// # Returns
+    ///
+    /// * `anyhow::Result<bool>` - Whether the reply completed, rather than
+    ///   being interrupted
+    async fn stream_reply(&mut self) -> anyhow::Result<bool> {
+        let mut events = self.client.chat_stream(self.request()).await?;
+        let mut renderer = Renderer::new(self.style, self.collapse_thinking);
+        let mut usage = None;
+
+        loop {
+            let event = tokio::select! {
+                event = events.next() => event,
+                _ = tokio::signal::ctrl_c() => {
+                    renderer.finish();
+                    println!("{}", self.style.dim("[interrupted]"));
+                    return Ok(false);
+                }
+            };
+            match event {
+                None => break,
+                Some(Err(e)) => {
+                    renderer.finish();
+                    return Err(e.into());
+                }
+                Some(Ok(StreamEvent::Content { content })) => {
+                    content.into_iter().for_each(|block| renderer.block(block));
+                }
+                Some(Ok(StreamEvent::Usage { usage: reported })) => usage = Some(reported),
+                Some(Ok(_)) => {}
+            }
+        }
+        renderer.finish();
+
+        if let Some(usage) = usage {
+            self.print_usage(&usage);
+        }
+        self.messages.push(Message {
+            role: Role::Assistant,
+            content: renderer.text.into(),
+        });
+        self.last_thinking = renderer.thinking;
+        Ok(true)
+    }
+
+    fn print_usage(&mut self, usage: &CombinedUsage) {
+        self.total_cost += usage.cost_usd;
+
+        let tokens = &usage.anthropic_usage;
+        println!(
+            "{}",
+            self.style.dim(&format!(
+                "[{} in · {} out · {} · session {}]",
+                tokens.input_tokens,
+                tokens.output_tokens,
+                usage.total_cost,
+                format_cost(self.total_cost)
+            ))
+        );
+    }
+
+    /// Runs a `/` command.
+    ///
+    /// # Returns
+    ///
+    /// * `anyhow::Result<bool>` - Whether the session should continue
+    fn command(&mut self, line: &str) -> anyhow::Result<bool> {
+        let (name, arg) = line.split_once(' ').unwrap_or((line, ""));
+        let arg = arg.trim();
+
+        match name {
+            "/system" => match arg {
+                "" => self.show("system", self.system.as_deref().unwrap_or("(none)")),
+                "off" => {
+                    self.system = None;
+                    self.show("system", "(none)");
+                }
+                prompt => {
+                    self.system = Some(prompt.to_string());
+                    self.show("system", prompt);
+                }
+            },
+            "/budget" => {
+                if !arg.is_empty() {
+                    self.budget = arg
+                        .parse()
+                        .map_err(|_| anyhow::anyhow!("Budget must be a number of tokens"))?;
+                }
+                match self.budget {
+                    0 => self.show("budget", "thinking disabled"),
+                    budget => self.show("budget", &format!("{budget} tokens")),
+                }
+            }
+            "/model" => {
+                if !arg.is_empty() {
+                    self.model = Some(arg.to_string());
+                }
+                self.show("model", self.model.as_deref().unwrap_or("(server default)"));
+            }
+            "/thinking" => {
+                match arg {
+                    "" => {}
+                    "show" => self.collapse_thinking = false,
+                    "collapse" => self.collapse_thinking = true,
+                    _ => anyhow::bail!("Usage: /thinking [show|collapse]"),
+                }
+                let mode = if self.collapse_thinking {
+                    "collapse"
+                } else {
+                    "show"
+                };
+                self.show("thinking", mode);
+            }
+            "/expand" => match self.last_thinking.as_str() {
+                "" => self.show("thinking", "(none)"),
+                thinking => println!("{}", self.style.dim(thinking)),
+            },
+            "/save" => {
+                if arg.is_empty() {
+                    anyhow::bail!("Usage: /save PATH");
+                }
+                std::fs::write(arg, serde_json::to_string_pretty(&self.request())?)
+                    .map_err(|e| anyhow::anyhow!("Failed to save {}: {}", arg, e))?;
+                self.show(
+                    "saved",
+                    &format!("{} messages to {}", self.messages.len(), arg),
+                );
+            }
+            "/clear" => {
+                self.messages.clear();
+                self.last_thinking.clear();
+                self.show("conversation", "cleared");
+            }
+            "/help" => println!("{}", self.style.dim(HELP)),
+            "/quit" | "/exit" => return Ok(false),
+            _ => anyhow::bail!("Unknown command {}; /help lists commands", name),
+        }
+        Ok(true)
+    }
+
+    fn show(&self, setting: &str, value: &str) {
+        println!("{} {}", self.style.bold(&format!("{setting}:")), value);
+    }
+}
+
+const HELP: &str = "\
+/system [PROMPT|off]       show, set or clear the system prompt
+/budget [TOKENS]           show or set the thinking budget (0 disables thinking)
+/model [NAME]              show or select the Claude model
+/thinking [show|collapse]  stream thinking, or collapse it to one line
+/expand                    show the thinking of the last reply
+/save PATH                 save the conversation as a request body
+/clear                     start a new conversation
+/quit                      exit (or Ctrl-D)";
+
+/// Runs the chat loop until `/quit`, `Ctrl-D` or end of input.
+///
+/// # Errors
+///
+/// Returns an error if the terminal cannot be read
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let mut session = Session::new(Args::parse());
+    let mut editor = DefaultEditor::new()?;
+    let style = session.style;
+
+    println!(
+        "{}",
+        style.dim("DeepClaude chat. /help lists commands, Ctrl-D exits.")
+    );
+
+    loop {
+        // Reading the line blocks; keep the runtime's other workers free
+        let line = match tokio::task::block_in_place(|| editor.readline("› ")) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line);
+
+        let result = if line.starts_with('/') {
+            match session.command(line) {
+                Ok(true) => Ok(()),
+                Ok(false) => break,
+                Err(e) => Err(e),
+            }
+        } else {
+            session.turn(line.to_string()).await
+        };
+        if let Err(e) = result {
+            eprintln!("{}", style.error(&e.to_string()));
+        }
+    }
+
+    Ok(())
+}
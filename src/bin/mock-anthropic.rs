@@ -0,0 +1,537 @@
+//! Mock Anthropic API server for local development and tests.
+//!
+//! Implements `POST /v1/messages` with scripted responses, and
+//! `POST /v1/messages/count_tokens` with approximate counts, so that deepclaude
+//! can be run and exercised without network access. Point deepclaude at it
+//! with `[anthropic] base_url = "http://127.0.0.1:4010"`.
+//!
+//! The response is chosen per request by scenario name, taken from the
+//! `x-mock-scenario` request header or a `[mock:<scenario>]` marker in the
+//! last user message:
+//!
+//! - `thinking` (default): a signed thinking block followed by a text block
+//! - `text`: a single text block
+//! - `redacted_thinking`: a redacted thinking block followed by a text block
+//! - `stream_error`: starts streaming, then sends an `error` event
+//! - `slow`: like `thinking`, with a long delay between chunks
+//! - `rate_limit`: `429` with `retry-after` and rate limit headers
+//! - `overloaded`: `529` overloaded error
+//! - `overloaded_thinking`: `529` overloaded error for requests with
+//!   extended thinking, like `thinking` otherwise
+//! - `server_error`: `500` API error
+//! - `bad_request`: `400` invalid request error
+//!
+//! Prompt caching is simulated: the prompt up to the last `cache_control`
+//! breakpoint is reported as written to the cache the first time it is
+//! seen, and as read from it afterwards.
+//!
+//! Streaming responses are split into small chunks that straddle SSE event
+//! boundaries, exercising incremental parsing.
+//!
+//! Requests for a model given with `--overloaded-model` always fail with
+//! `529`, whatever the scenario.
+//!
+//! Usage: `mock-anthropic [--host HOST] [--port PORT] [--chunk-delay-ms MS]
+//! [--overloaded-model MODEL]...`
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use clap::Parser;
+use serde_json::{json, Value};
+use std::{
+    collections::HashSet,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Size of each streamed body chunk in bytes.
+const CHUNK_SIZE: usize = 48;
+
+/// Delay between chunks for the `slow` scenario.
+const SLOW_CHUNK_DELAY: Duration = Duration::from_millis(750);
+
+/// Signature attached to mock thinking blocks.
+const MOCK_SIGNATURE: &str = "EuYBCkQYAiJAmockSignatureForLocalDevelopmentOnly==";
+
+/// Mock server settings, and the prompt prefixes cached so far.
+#[derive(Debug, Parser)]
+#[command(about = "Mock Anthropic API server for local development and tests")]
+struct MockOptions {
+    /// Address to bind the server to
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+
+    /// Port to listen on
+    #[arg(long, default_value_t = 4010)]
+    port: u16,
+
+    /// Delay between streamed chunks, in milliseconds
+    #[arg(
+        long = "chunk-delay-ms",
+        value_name = "MS",
+        default_value = "0",
+        value_parser = parse_millis
+    )]
+    chunk_delay: Duration,
+
+    /// Model whose requests always fail with `529`; may be repeated
+    #[arg(long = "overloaded-model", value_name = "MODEL")]
+    overloaded_models: Vec<String>,
+
+    /// Prompt prefixes cached so far
+    #[arg(skip)]
+    cached_prefixes: Mutex<HashSet<String>>,
+}
+
+fn parse_millis(value: &str) -> Result<Duration, std::num::ParseIntError> {
+    value.parse().map(Duration::from_millis)
+}
+
+/// A scripted upstream behaviour.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scenario {
+    Thinking,
+    Text,
+    RedactedThinking,
+    StreamError,
+    Slow,
+    RateLimit,
+    Overloaded,
+    OverloadedThinking,
+    ServerError,
+    BadRequest,
+}
+
+impl Scenario {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name.trim() {
+            "thinking" => Self::Thinking,
+            "text" => Self::Text,
+            "redacted_thinking" => Self::RedactedThinking,
+            "stream_error" => Self::StreamError,
+            "slow" => Self::Slow,
+            "rate_limit" => Self::RateLimit,
+            "overloaded" => Self::Overloaded,
+            "overloaded_thinking" => Self::OverloadedThinking,
+            "server_error" => Self::ServerError,
+            "bad_request" => Self::BadRequest,
+            _ => return None,
+        })
+    }
+
+    /// Selects the scenario from the request header or message marker.
+    fn select(headers: &HeaderMap, request: &Value) -> Self {
+        if let Some(scenario) = headers
+            .get("x-mock-scenario")
+            .and_then(|v| v.to_str().ok())
+            .and_then(Self::parse)
+        {
+            return scenario;
+        }
+
+        last_user_text(request)
+            .and_then(|text| {
+                let start = text.find("[mock:")? + "[mock:".len();
+                let end = text[start..].find(']')? + start;
+                Self::parse(&text[start..end])
+            })
+            .unwrap_or(Self::Thinking)
+    }
+
+    /// The HTTP error this scenario fails with, if any.
+    fn error(&self) -> Option<(StatusCode, &'static str, &'static str)> {
+        match self {
+            Self::RateLimit => Some((
+                StatusCode::TOO_MANY_REQUESTS,
+                "rate_limit_error",
+                "Number of request tokens has exceeded your per-minute rate limit",
+            )),
+            Self::Overloaded => Some((
+                StatusCode::from_u16(529).unwrap_or(StatusCode::SERVICE_UNAVAILABLE),
+                "overloaded_error",
+                "Overloaded",
+            )),
+            Self::ServerError => Some((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "api_error",
+                "Internal server error",
+            )),
+            Self::BadRequest => Some((
+                StatusCode::BAD_REQUEST,
+                "invalid_request_error",
+                "prompt is too long: 210000 tokens > 200000 maximum",
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the text of the last user message in a request.
+fn last_user_text(request: &Value) -> Option<String> {
+    let message = request["messages"]
+        .as_array()?
+        .iter()
+        .rev()
+        .find(|m| m["role"] == "user")?;
+
+    match &message["content"] {
+        Value::String(text) => Some(text.clone()),
+        Value::Array(blocks) => Some(
+            blocks
+                .iter()
+                .filter_map(|b| b["text"].as_str())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ),
+        _ => None,
+    }
+}
+
+/// Roughly estimates input tokens as one token per four characters.
+fn approximate_input_tokens(request: &Value) -> u32 {
+    (request.to_string().len() as u32).div_ceil(4)
+}
+
+/// Returns the prompt of a request up to its last cache breakpoint,
+/// serialized, or `None` if it has none.
+fn cached_prefix(request: &Value) -> Option<String> {
+    let mut blocks = vec![&request["model"]];
+    match &request["system"] {
+        Value::Array(system) => blocks.extend(system),
+        system => blocks.push(system),
+    }
+    for message in request["messages"].as_array().into_iter().flatten() {
+        match &message["content"] {
+            Value::Array(content) => blocks.extend(content),
+            content => blocks.push(content),
+        }
+    }
+
+    let last = blocks.iter().rposition(|block| block.get("cache_control").is_some())?;
+    Some(blocks[..=last].iter().map(|block| block.to_string()).collect())
+}
+
+/// Builds the input usage of a request, simulating prompt caching.
+fn input_usage(options: &MockOptions, request: &Value) -> Value {
+    let total = approximate_input_tokens(request);
+    let Some(prefix) = cached_prefix(request) else {
+        return json!({
+            "input_tokens": total,
+            "cache_creation_input_tokens": 0,
+            "cache_read_input_tokens": 0,
+        });
+    };
+
+    let cached = (prefix.len() as u32).div_ceil(4).min(total);
+    let hit = !options
+        .cached_prefixes
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(prefix);
+    json!({
+        "input_tokens": total - cached,
+        "cache_creation_input_tokens": if hit { 0 } else { cached },
+        "cache_read_input_tokens": if hit { cached } else { 0 },
+    })
+}
+
+/// Builds the content blocks a scenario responds with.
+fn content_blocks(scenario: Scenario, request: &Value) -> Vec<Value> {
+    let prompt = last_user_text(request).unwrap_or_default();
+    let text = json!({
+        "type": "text",
+        "text": format!("Mock response to: {}", prompt),
+    });
+
+    match scenario {
+        Scenario::Text => vec![text],
+        Scenario::RedactedThinking => vec![
+            json!({"type": "redacted_thinking", "data": "EmwKAhgBEgy3va3pzix/LafPsn4aDFIT2Xlxh0L5L8rLVyIwxtE3rAFBa8cr3qpPkNRj2YfWXGmKDxH4mPnZ5sQ7vB5URj2pabcl6lmVsx3Rn3fs"}),
+            text,
+        ],
+        _ => vec![
+            json!({
+                "type": "thinking",
+                "thinking": format!("The user wrote {} characters. I should answer briefly.", prompt.len()),
+                "signature": MOCK_SIGNATURE,
+            }),
+            text,
+        ],
+    }
+}
+
+/// Counts output tokens for a set of content blocks.
+fn output_tokens(blocks: &[Value]) -> u32 {
+    blocks
+        .iter()
+        .map(|b| {
+            let len = b["text"].as_str().or(b["thinking"].as_str()).map_or(0, str::len);
+            (len as u32).div_ceil(4)
+        })
+        .sum()
+}
+
+/// Headers Anthropic attaches to every response.
+fn upstream_headers(request_id: &str) -> [(&'static str, String); 7] {
+    let reset = (chrono::Utc::now() + chrono::Duration::seconds(60)).to_rfc3339();
+    [
+        ("request-id", request_id.to_string()),
+        ("anthropic-ratelimit-requests-limit", "50".to_string()),
+        ("anthropic-ratelimit-requests-remaining", "49".to_string()),
+        ("anthropic-ratelimit-requests-reset", reset.clone()),
+        ("anthropic-ratelimit-tokens-limit", "80000".to_string()),
+        ("anthropic-ratelimit-tokens-remaining", "79000".to_string()),
+        ("anthropic-ratelimit-tokens-reset", reset),
+    ]
+}
+
+fn error_response(status: StatusCode, error_type: &str, message: &str, request_id: &str) -> Response {
+    let mut response = (
+        status,
+        Json(json!({
+            "type": "error",
+            "error": {"type": error_type, "message": message},
+        })),
+    )
+        .into_response();
+
+    let headers = response.headers_mut();
+    for (name, value) in upstream_headers(request_id) {
+        if let Ok(value) = value.parse() {
+            headers.insert(name, value);
+        }
+    }
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        headers.insert("retry-after", "5".parse().expect("valid header"));
+        headers.insert(
+            "anthropic-ratelimit-tokens-remaining",
+            "0".parse().expect("valid header"),
+        );
+    }
+
+    response
+}
+
+/// Renders a full streaming response as SSE text.
+fn sse_body(scenario: Scenario, request: &Value, mut usage: Value, message_id: &str) -> String {
+    let model = request["model"].as_str().unwrap_or("claude-3-7-sonnet-20250219");
+    usage["output_tokens"] = json!(1);
+    let blocks = content_blocks(scenario, request);
+    let mut events = vec![
+        (
+            "message_start",
+            json!({
+                "type": "message_start",
+                "message": {
+                    "id": message_id,
+                    "type": "message",
+                    "role": "assistant",
+                    "model": model,
+                    "content": [],
+                    "stop_reason": null,
+                    "stop_sequence": null,
+                    "usage": usage,
+                },
+            }),
+        ),
+        ("ping", json!({"type": "ping"})),
+    ];
+
+    for (index, block) in blocks.iter().enumerate() {
+        match block["type"].as_str() {
+            Some("thinking") => {
+                events.push((
+                    "content_block_start",
+                    json!({"type": "content_block_start", "index": index, "content_block": {"type": "thinking", "thinking": ""}}),
+                ));
+                for word in block["thinking"].as_str().unwrap_or_default().split_inclusive(' ') {
+                    events.push((
+                        "content_block_delta",
+                        json!({"type": "content_block_delta", "index": index, "delta": {"type": "thinking_delta", "thinking": word}}),
+                    ));
+                }
+                events.push((
+                    "content_block_delta",
+                    json!({"type": "content_block_delta", "index": index, "delta": {"type": "signature_delta", "signature": MOCK_SIGNATURE}}),
+                ));
+            }
+            Some("redacted_thinking") => {
+                events.push((
+                    "content_block_start",
+                    json!({"type": "content_block_start", "index": index, "content_block": block}),
+                ));
+            }
+            _ => {
+                events.push((
+                    "content_block_start",
+                    json!({"type": "content_block_start", "index": index, "content_block": {"type": "text", "text": ""}}),
+                ));
+                for word in block["text"].as_str().unwrap_or_default().split_inclusive(' ') {
+                    events.push((
+                        "content_block_delta",
+                        json!({"type": "content_block_delta", "index": index, "delta": {"type": "text_delta", "text": word}}),
+                    ));
+                }
+            }
+        }
+
+        if scenario == Scenario::StreamError {
+            events.push((
+                "error",
+                json!({"type": "error", "error": {"type": "overloaded_error", "message": "Overloaded"}}),
+            ));
+            break;
+        }
+
+        events.push((
+            "content_block_stop",
+            json!({"type": "content_block_stop", "index": index}),
+        ));
+    }
+
+    if scenario != Scenario::StreamError {
+        events.push((
+            "message_delta",
+            json!({
+                "type": "message_delta",
+                "delta": {"stop_reason": "end_turn", "stop_sequence": null},
+                "usage": {"output_tokens": output_tokens(&blocks)},
+            }),
+        ));
+        events.push(("message_stop", json!({"type": "message_stop"})));
+    }
+
+    events
+        .into_iter()
+        .map(|(name, data)| format!("event: {}\ndata: {}\n\n", name, data))
+        .collect()
+}
+
+/// Handler for `POST /v1/messages`.
+async fn messages(
+    State(options): State<Arc<MockOptions>>,
+    headers: HeaderMap,
+    Json(request): Json<Value>,
+) -> Response {
+    let scenario = Scenario::select(&headers, &request);
+    let suffix = chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default();
+    let request_id = format!("req_mock_{:x}", suffix);
+    let message_id = format!("msg_mock_{:x}", suffix);
+    tracing::info!(?scenario, %request_id, "POST /v1/messages");
+
+    if headers.get("x-api-key").is_none() {
+        return error_response(
+            StatusCode::UNAUTHORIZED,
+            "authentication_error",
+            "x-api-key header is required",
+            &request_id,
+        );
+    }
+
+    let overloaded = request["model"]
+        .as_str()
+        .is_some_and(|model| options.overloaded_models.iter().any(|overloaded| overloaded == model))
+        || (scenario == Scenario::OverloadedThinking && request["thinking"]["type"] == "enabled");
+    let error = if overloaded {
+        Scenario::Overloaded.error()
+    } else {
+        scenario.error()
+    };
+    if let Some((status, error_type, message)) = error {
+        return error_response(status, error_type, message, &request_id);
+    }
+
+    let mut response = if request["stream"].as_bool().unwrap_or(false) {
+        let usage = input_usage(&options, &request);
+        let body = sse_body(scenario, &request, usage, &message_id).into_bytes();
+        let delay = if scenario == Scenario::Slow {
+            SLOW_CHUNK_DELAY
+        } else {
+            options.chunk_delay
+        };
+
+        let stream = async_stream::stream! {
+            for chunk in body.chunks(CHUNK_SIZE) {
+                if !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
+                }
+                yield Ok::<_, Infallible>(chunk.to_vec());
+            }
+        };
+
+        (
+            [("content-type", "text/event-stream"), ("cache-control", "no-cache")],
+            Body::from_stream(stream),
+        )
+            .into_response()
+    } else {
+        let blocks = content_blocks(scenario, &request);
+        let mut usage = input_usage(&options, &request);
+        usage["output_tokens"] = json!(output_tokens(&blocks));
+        Json(json!({
+            "id": message_id,
+            "type": "message",
+            "role": "assistant",
+            "model": request["model"].as_str().unwrap_or("claude-3-7-sonnet-20250219"),
+            "content": blocks,
+            "stop_reason": "end_turn",
+            "stop_sequence": null,
+            "usage": usage,
+        }))
+        .into_response()
+    };
+
+    let response_headers = response.headers_mut();
+    for (name, value) in upstream_headers(&request_id) {
+        if let Ok(value) = value.parse() {
+            response_headers.insert(name, value);
+        }
+    }
+    response
+}
+
+/// Handler for `POST /v1/messages/count_tokens`.
+async fn count_tokens(headers: HeaderMap, Json(request): Json<Value>) -> Response {
+    tracing::info!("POST /v1/messages/count_tokens");
+    if headers.get("x-api-key").is_none() {
+        return error_response(
+            StatusCode::UNAUTHORIZED,
+            "authentication_error",
+            "x-api-key header is required",
+            "req_mock_count_tokens",
+        );
+    }
+
+    Json(json!({ "input_tokens": approximate_input_tokens(&request) })).into_response()
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "info".into()),
+        )
+        .init();
+
+    let options = Arc::new(MockOptions::parse());
+    let addr: SocketAddr = format!("{}:{}", options.host, options.port).parse()?;
+
+    let app = Router::new()
+        .route("/v1/messages", post(messages))
+        .route("/v1/messages/count_tokens", post(count_tokens))
+        .with_state(options);
+
+    tracing::info!("Mock Anthropic API listening on http://{}", addr);
+    axum::serve(tokio::net::TcpListener::bind(&addr).await?, app).await?;
+
+    Ok(())
+}
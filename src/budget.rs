@@ -0,0 +1,433 @@
+//! Spend budgets and quotas.
+//!
+//! This module accumulates the dollar cost of every request and enforces
+//! daily and monthly budgets per caller key and per project. It provides:
+//! - Persistent spend tracking with automatic day/month rollover
+//! - Pre-flight rejection of requests whose worst-case cost would exceed
+//!   the remaining budget, which reserves that cost until the request is
+//!   settled so that concurrent requests cannot overshoot it together
+//! - Soft-limit warnings once a configurable share of a budget is used
+//!
+//! Spend is persisted by a background thread, off the request path.
+
+use crate::{
+    config::{BudgetLimit, BudgetsConfig},
+    error::{ApiError, Result},
+};
+use axum::http::{HeaderMap, HeaderValue};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, SyncSender},
+        Arc, Mutex,
+    },
+};
+
+/// Thinking budget assumed when a request doesn't configure one.
+pub(crate) const DEFAULT_THINKING_BUDGET: u32 = 16000;
+
+/// Accumulated spend for one budget scope.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Spend {
+    day: String,
+    daily_usd: f64,
+    month: String,
+    monthly_usd: f64,
+}
+
+impl Spend {
+    /// Resets counters whose period has ended.
+    fn roll_over(&mut self, day: &str, month: &str) {
+        if self.day != day {
+            self.day = day.to_string();
+            self.daily_usd = 0.0;
+        }
+        if self.month != month {
+            self.month = month.to_string();
+            self.monthly_usd = 0.0;
+        }
+    }
+}
+
+/// Recorded spend, and the worst-case costs reserved by requests in flight.
+#[derive(Debug, Default)]
+struct Ledger {
+    spend: HashMap<String, Spend>,
+    reserved: HashMap<String, f64>,
+}
+
+impl Ledger {
+    fn reserved(&self, ledger_key: &str) -> f64 {
+        self.reserved.get(ledger_key).copied().unwrap_or(0.0)
+    }
+
+    /// Releases an amount reserved against the given scopes.
+    fn release(&mut self, ledger_keys: &[String], amount: f64) {
+        for ledger_key in ledger_keys {
+            if let Some(reserved) = self.reserved.get_mut(ledger_key) {
+                *reserved -= amount;
+                if *reserved <= f64::EPSILON {
+                    self.reserved.remove(ledger_key);
+                }
+            }
+        }
+    }
+}
+
+/// The worst-case cost of a request, held against its budgets from the
+/// pre-flight check until the request is settled.
+///
+/// Dropping a reservation without settling it releases the held amount,
+/// so requests that fail are not charged.
+#[derive(Debug)]
+pub struct BudgetReservation {
+    ledger: Arc<Mutex<Ledger>>,
+    ledger_keys: Vec<String>,
+    amount: f64,
+}
+
+impl Drop for BudgetReservation {
+    fn drop(&mut self) {
+        if self.amount > 0.0 {
+            lock(&self.ledger).release(&self.ledger_keys, self.amount);
+        }
+    }
+}
+
+/// A budget scope a request is charged against.
+#[derive(Debug, Clone, Copy)]
+enum Scope<'a> {
+    Key(&'a str),
+    Project(&'a str),
+}
+
+impl Scope<'_> {
+    fn ledger_key(&self) -> String {
+        match self {
+            Scope::Key(id) => format!("key:{}", id),
+            Scope::Project(name) => format!("project:{}", name),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Scope::Key(_) => "key".to_string(),
+            Scope::Project(name) => format!("project '{}'", name),
+        }
+    }
+
+    fn limit<'c>(&self, config: &'c BudgetsConfig) -> Option<&'c BudgetLimit> {
+        match self {
+            Scope::Key(id) => config.keys.get(*id).or(config.default_key.as_ref()),
+            Scope::Project(name) => config.projects.get(*name),
+        }
+    }
+}
+
+/// Outcome of a successful budget check.
+///
+/// Describes how much budget remains and whether any soft limits
+/// have been crossed.
+#[derive(Debug, Clone, Default)]
+pub struct BudgetStatus {
+    pub remaining_usd: Option<f64>,
+    pub warnings: Vec<String>,
+}
+
+impl BudgetStatus {
+    /// Writes budget headers describing this status.
+    ///
+    /// Adds `x-budget-remaining-usd` with the smallest remaining budget
+    /// that applies to the request, and one `x-budget-warning` header per
+    /// crossed soft limit.
+    ///
+    /// # Arguments
+    ///
+    /// * `headers` - The response headers to add the budget headers to
+    pub fn apply_headers(&self, headers: &mut HeaderMap) {
+        if let Some(remaining) = self.remaining_usd {
+            if let Ok(value) = HeaderValue::from_str(&format!("{:.4}", remaining)) {
+                headers.insert("x-budget-remaining-usd", value);
+            }
+        }
+
+        for warning in &self.warnings {
+            if let Ok(value) = HeaderValue::from_str(warning) {
+                headers.append("x-budget-warning", value);
+            }
+        }
+    }
+}
+
+/// Tracks spend against configured budgets.
+#[derive(Debug)]
+pub struct BudgetTracker {
+    path: Option<PathBuf>,
+    ledger: Arc<Mutex<Ledger>>,
+    /// Wakes the store writer; at most one write is ever pending
+    writes: Option<SyncSender<()>>,
+    /// Serializes writes to the store
+    store: Arc<Mutex<()>>,
+}
+
+impl BudgetTracker {
+    /// Creates a tracker, restoring previously recorded spend if a store
+    /// path is configured and exists.
+    ///
+    /// With a store path, a background thread writes the spend to it after
+    /// every change.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Budget configuration containing the optional store path
+    ///
+    /// # Returns
+    ///
+    /// * `anyhow::Result<Self>` - The tracker, or an error if an existing
+    ///   store cannot be read or parsed
+    pub fn load(config: &BudgetsConfig) -> anyhow::Result<Self> {
+        let path = config.store_path.as_ref().map(PathBuf::from);
+        let spend = match &path {
+            Some(path) if path.exists() => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+            _ => HashMap::new(),
+        };
+        let ledger = Arc::new(Mutex::new(Ledger {
+            spend,
+            reserved: HashMap::new(),
+        }));
+        let store = Arc::new(Mutex::new(()));
+
+        let writes = match &path {
+            Some(path) => {
+                let (tx, rx) = mpsc::sync_channel::<()>(1);
+                let (path, ledger, store) = (path.clone(), ledger.clone(), store.clone());
+                std::thread::Builder::new()
+                    .name("budget-store".to_string())
+                    .spawn(move || {
+                        while rx.recv().is_ok() {
+                            write_store(&path, &ledger, &store);
+                        }
+                    })?;
+                Some(tx)
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            path,
+            ledger,
+            writes,
+            store,
+        })
+    }
+
+    /// Checks that a request fits within every budget that applies to it,
+    /// and reserves its worst-case cost.
+    ///
+    /// Costs reserved by other requests in flight count as spent.
+    ///
+    /// # Arguments
+    ///
+    /// * `key_id` - Identity of the caller
+    /// * `project` - Project named by the caller, if any
+    /// * `estimated_cost` - Worst-case cost of the request in dollars
+    /// * `config` - The currently configured budgets
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(BudgetStatus, BudgetReservation)>` - Remaining budget and
+    ///   soft-limit warnings, and the reservation to settle with the actual
+    ///   cost
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::BudgetExceeded` if a budget is exhausted or the
+    /// estimated cost would exceed what remains of it
+    pub fn check(
+        &self,
+        key_id: &str,
+        project: Option<&str>,
+        estimated_cost: f64,
+        config: &BudgetsConfig,
+    ) -> Result<(BudgetStatus, BudgetReservation)> {
+        let (day, month) = current_periods();
+        let mut ledger = lock(&self.ledger);
+        let mut status = BudgetStatus::default();
+        let scopes = scopes(key_id, project);
+
+        for scope in &scopes {
+            let Some(limit) = scope.limit(config) else {
+                continue;
+            };
+
+            let ledger_key = scope.ledger_key();
+            let reserved = ledger.reserved(&ledger_key);
+            let mut spend = ledger.spend.get(&ledger_key).cloned().unwrap_or_default();
+            spend.roll_over(&day, &month);
+
+            let periods = [
+                ("daily", limit.daily_usd, spend.daily_usd),
+                ("monthly", limit.monthly_usd, spend.monthly_usd),
+            ];
+            for (period, limit_usd, spent) in periods {
+                let Some(limit_usd) = limit_usd else {
+                    continue;
+                };
+                let remaining = limit_usd - spent - reserved;
+
+                if limit_usd - spent <= 0.0 {
+                    return Err(ApiError::BudgetExceeded {
+                        message: format!(
+                            "The {} {} budget of ${:.2} is exhausted",
+                            scope.describe(),
+                            period,
+                            limit_usd
+                        ),
+                    });
+                }
+                if estimated_cost > remaining {
+                    return Err(ApiError::BudgetExceeded {
+                        message: format!(
+                            "Request could cost up to ${:.4}, but only ${:.4} remains of the {} {} budget",
+                            estimated_cost,
+                            remaining,
+                            scope.describe(),
+                            period
+                        ),
+                    });
+                }
+
+                if limit_usd > 0.0 && spent / limit_usd >= config.soft_limit_ratio {
+                    status.warnings.push(format!(
+                        "{} {} budget {:.0}% used",
+                        scope.describe(),
+                        period,
+                        spent / limit_usd * 100.0
+                    ));
+                }
+                status.remaining_usd =
+                    Some(status.remaining_usd.map_or(remaining, |r: f64| r.min(remaining)));
+            }
+
+            ledger.spend.insert(ledger_key, spend);
+        }
+
+        let ledger_keys = scopes.iter().map(Scope::ledger_key).collect::<Vec<_>>();
+        for ledger_key in &ledger_keys {
+            *ledger.reserved.entry(ledger_key.clone()).or_default() += estimated_cost;
+        }
+
+        Ok((
+            status,
+            BudgetReservation {
+                ledger: self.ledger.clone(),
+                ledger_keys,
+                amount: estimated_cost,
+            },
+        ))
+    }
+
+    /// Records the actual cost of a request admitted by [`Self::check`],
+    /// releasing its reservation.
+    ///
+    /// # Arguments
+    ///
+    /// * `reservation` - The reservation made for the request
+    /// * `cost` - Actual cost of the request in dollars
+    pub fn settle(&self, mut reservation: BudgetReservation, cost: f64) {
+        let mut ledger = lock(&self.ledger);
+        ledger.release(&reservation.ledger_keys, reservation.amount);
+        reservation.amount = 0.0;
+        charge(&mut ledger, &reservation.ledger_keys, cost);
+        drop(ledger);
+        self.schedule_write();
+    }
+
+    /// Records the actual cost of a completed request.
+    ///
+    /// Spend is recorded against the caller's key and project even when no
+    /// budget is configured for them, so that budgets added later start
+    /// from accurate totals.
+    ///
+    /// # Arguments
+    ///
+    /// * `key_id` - Identity of the caller
+    /// * `project` - Project named by the caller, if any
+    /// * `cost` - Actual cost of the request in dollars
+    pub fn record(&self, key_id: &str, project: Option<&str>, cost: f64) {
+        let ledger_keys = scopes(key_id, project)
+            .iter()
+            .map(Scope::ledger_key)
+            .collect::<Vec<_>>();
+        charge(&mut lock(&self.ledger), &ledger_keys, cost);
+        self.schedule_write();
+    }
+
+    /// Writes the recorded spend to the store now, if one is configured.
+    ///
+    /// Used at shutdown, so that no spend recorded since the last
+    /// background write is lost. Blocks on file I/O.
+    pub fn flush(&self) {
+        if let Some(path) = &self.path {
+            write_store(path, &self.ledger, &self.store);
+        }
+    }
+
+    /// Asks the store writer to persist the spend, unless a write is
+    /// already pending.
+    fn schedule_write(&self) {
+        if let Some(writes) = &self.writes {
+            let _ = writes.try_send(());
+        }
+    }
+}
+
+/// Adds a cost to the spend of the given scopes.
+fn charge(ledger: &mut Ledger, ledger_keys: &[String], cost: f64) {
+    let (day, month) = current_periods();
+    for ledger_key in ledger_keys {
+        let spend = ledger.spend.entry(ledger_key.clone()).or_default();
+        spend.roll_over(&day, &month);
+        spend.daily_usd += cost;
+        spend.monthly_usd += cost;
+    }
+}
+
+fn lock(ledger: &Mutex<Ledger>) -> std::sync::MutexGuard<'_, Ledger> {
+    ledger.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Writes a snapshot of the spend to the store, logging failures.
+fn write_store(path: &Path, ledger: &Mutex<Ledger>, store: &Mutex<()>) {
+    let _store = store.lock().unwrap_or_else(|e| e.into_inner());
+    let spend = lock(ledger).spend.clone();
+    if let Err(e) = persist(path, &spend) {
+        tracing::warn!("Failed to persist budget spend to {}: {}", path.display(), e);
+    }
+}
+
+fn scopes<'a>(key_id: &'a str, project: Option<&'a str>) -> Vec<Scope<'a>> {
+    let mut scopes = vec![Scope::Key(key_id)];
+    scopes.extend(project.map(Scope::Project));
+    scopes
+}
+
+fn current_periods() -> (String, String) {
+    let now = Utc::now();
+    (now.format("%Y-%m-%d").to_string(), now.format("%Y-%m").to_string())
+}
+
+/// Writes the ledger atomically by replacing the store with a fully
+/// written temporary file.
+fn persist(path: &Path, ledger: &HashMap<String, Spend>) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(ledger)?)?;
+    std::fs::rename(tmp, path)?;
+    Ok(())
+}
@@ -0,0 +1,57 @@
+//! Command line interface for the server binary.
+//!
+//! Command line flags form the highest-precedence configuration layer,
+//! overriding both the config file and `DEEPCLAUDE_*` environment variables.
+
+use deepclaude::config::{Config, LogFormat};
+use clap::Parser;
+use std::path::PathBuf;
+
+/// DeepClaude API server.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Path to the config file [default: config.toml if present]
+    #[arg(short, long, env = "DEEPCLAUDE_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Address to bind the server to
+    #[arg(long)]
+    pub host: Option<String>,
+
+    /// Port to listen on
+    #[arg(short, long)]
+    pub port: Option<u16>,
+
+    /// Log output format
+    #[arg(long, value_enum)]
+    pub log_format: Option<LogFormat>,
+
+    /// Print the effective configuration, with secrets masked, and exit
+    #[arg(long)]
+    pub print_config: bool,
+}
+
+impl Cli {
+    /// Applies command line overrides on top of loaded configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The configuration to override
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the overridden configuration fails validation
+    pub fn apply(&self, config: &mut Config) -> anyhow::Result<()> {
+        if let Some(host) = &self.host {
+            config.server.host = host.clone();
+        }
+        if let Some(port) = self.port {
+            config.server.port = port;
+        }
+        if let Some(format) = self.log_format {
+            config.logging.format = format;
+        }
+        config.validate()
+    }
+}
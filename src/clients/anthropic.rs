@@ -52,24 +52,86 @@
 //!     let response = client.chat(messages.clone(), None, &config).await?;
 //!
 //!     // Streaming request
-//!     let stream = client.chat_stream(messages, None, &config);
+//!     let stream = client.chat_stream(messages, None, &config).await?.body;
 //!     Ok(())
 //! }
 //! ```
 
+use super::{
+    cassette::{Cassette, CassetteStore},
+    rate_limits::{AnthropicRateLimits, RATE_LIMIT_HEADER_PREFIX},
+};
 use crate::{
     error::{ApiError, Result},
-    models::{ApiConfig, Message, Role},
+    models::{self, ApiConfig, Message, MessageContent, Role, UpstreamTiming},
+    rate_limit::UpstreamLimits,
+    sse::SseBuffer,
+    telemetry::{self, InSpan},
 };
+use bytes::Bytes;
 use futures::Stream;
 use reqwest::{header::HeaderMap, Client};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, pin::Pin};
+use std::{collections::HashMap, pin::Pin, sync::Arc, time::Instant};
 use futures::StreamExt;
 use serde_json;
+use sha2::{Digest, Sha256};
+use tracing::Instrument;
+
+/// Raw response body chunks, from the network or a cassette.
+type ByteStream = Pin<Box<dyn Stream<Item = reqwest::Result<Bytes>> + Send>>;
+
+/// Events parsed from a streaming response.
+pub type EventStream = Pin<Box<dyn Stream<Item = Result<StreamEvent>> + Send>>;
+
+/// Response header carrying the id Anthropic assigned to a request.
+const REQUEST_ID_HEADER: &str = "request-id";
+
+/// Response headers kept from upstream responses, besides rate limit
+/// headers.
+const FORWARDED_HEADERS: [&str; 2] = [REQUEST_ID_HEADER, "retry-after"];
+
+/// Request parameters accepted by the token counting API.
+const COUNT_TOKENS_PARAMS: [&str; 6] = ["model", "messages", "system", "thinking", "tools", "tool_choice"];
+
+pub(crate) const ANTHROPIC_API_BASE_URL: &str = "https://api.anthropic.com";
+pub(crate) const DEFAULT_MODEL: &str = "claude-3-7-sonnet-20250219";
+
+/// Output token limits of known models, by model name prefix; the first
+/// match applies.
+const MAX_OUTPUT_TOKENS: [(&str, u32); 10] = [
+    ("claude-opus-4-5", 64_000),
+    ("claude-opus-4", 32_000),
+    ("claude-sonnet-4", 64_000),
+    ("claude-haiku-4", 64_000),
+    ("claude-3-7-sonnet", 64_000),
+    ("claude-3-5-sonnet", 8192),
+    ("claude-3-5-haiku", 8192),
+    ("claude-3-opus", 4096),
+    ("claude-3-sonnet", 4096),
+    ("claude-3-haiku", 4096),
+];
 
-pub(crate) const ANTHROPIC_API_URL: &str = "https://api.anthropic.com/v1/messages";
-const DEFAULT_MODEL: &str = "claude-3-7-sonnet-20250219";
+/// Returns the `max_tokens` value used when a request doesn't set one.
+///
+/// # Arguments
+///
+/// * `model` - The model the request targets
+pub(crate) fn default_max_tokens(model: &str) -> u32 {
+    max_output_tokens(model).map_or(8192, |limit| limit.min(8192))
+}
+
+/// Returns the most output tokens a model can generate, if it is known.
+///
+/// # Arguments
+///
+/// * `model` - The model the request targets
+pub(crate) fn max_output_tokens(model: &str) -> Option<u32> {
+    MAX_OUTPUT_TOKENS
+        .iter()
+        .find(|(prefix, _)| model.contains(prefix))
+        .map(|&(_, limit)| limit)
+}
 
 /// Client for interacting with Anthropic's Claude models.
 ///
@@ -87,6 +149,34 @@ const DEFAULT_MODEL: &str = "claude-3-7-sonnet-20250219";
 pub struct AnthropicClient {
     pub(crate) client: Client,
     api_token: String,
+    base_url: String,
+    cassettes: Option<CassetteStore>,
+    upstream_limits: Option<(Arc<UpstreamLimits>, String)>,
+}
+
+/// A response from the Anthropic API together with details of the HTTP
+/// response it arrived in.
+pub struct UpstreamResponse<T> {
+    /// HTTP status code of the upstream response
+    pub status: u16,
+    /// Selected response headers: `request-id`, `retry-after` and the
+    /// `anthropic-ratelimit-*` headers, keyed by lowercase name
+    pub headers: HashMap<String, String>,
+    /// How long upstream took to respond
+    pub timing: UpstreamTiming,
+    pub body: T,
+}
+
+impl<T> UpstreamResponse<T> {
+    /// Returns Anthropic's id for the request, from the `request-id` header.
+    pub fn request_id(&self) -> Option<&str> {
+        self.headers.get(REQUEST_ID_HEADER).map(String::as_str)
+    }
+
+    /// Returns the rate limits Anthropic reported for the API key.
+    pub fn rate_limits(&self) -> Option<AnthropicRateLimits> {
+        AnthropicRateLimits::from_headers(&self.headers)
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -116,11 +206,17 @@ pub struct ContentBlock {
     pub data: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+// Streaming `message_delta` events only report the fields that changed,
+// so every field defaults to zero when absent.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct Usage {
+    #[serde(default)]
     pub input_tokens: u32,
+    #[serde(default)]
     pub output_tokens: u32,
+    #[serde(default)]
     pub cache_creation_input_tokens: u32,
+    #[serde(default)]
     pub cache_read_input_tokens: u32,
 }
 
@@ -129,13 +225,19 @@ pub(crate) struct AnthropicRequest {
     messages: Vec<AnthropicMessage>,
     stream: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
-    system: Option<String>,
+    system: Option<serde_json::Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
     thinking: Option<ThinkingConfig>,
     #[serde(flatten)]
     additional_params: serde_json::Value,
 }
 
+/// Response of the token counting API.
+#[derive(Debug, Deserialize)]
+struct TokenCount {
+    input_tokens: u32,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub(crate) struct ThinkingConfig {
     #[serde(rename = "type")]
@@ -146,7 +248,7 @@ pub(crate) struct ThinkingConfig {
 #[derive(Debug, Serialize, Deserialize)]
 pub(crate) struct AnthropicMessage {
     role: String,
-    content: String,
+    content: serde_json::Value,
 }
 
 // Event types for streaming responses
@@ -184,6 +286,18 @@ pub enum StreamEvent {
     MessageStop,
     #[serde(rename = "ping")]
     Ping,
+    #[serde(rename = "error")]
+    Error {
+        error: StreamError,
+    },
+}
+
+/// Error reported by Anthropic in the middle of a stream.
+#[derive(Debug, Deserialize, Clone)]
+pub struct StreamError {
+    #[serde(rename = "type")]
+    pub error_type: String,
+    pub message: String,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -194,7 +308,7 @@ pub struct ContentDelta {
     pub text: String,
     #[serde(default)]
     pub thinking: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(alias = "signature", skip_serializing_if = "Option::is_none")]
     pub signature_delta: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub data: Option<String>,
@@ -221,9 +335,65 @@ impl AnthropicClient {
         Self {
             client: Client::new(),
             api_token,
+            base_url: ANTHROPIC_API_BASE_URL.to_string(),
+            cassettes: None,
+            upstream_limits: None,
         }
     }
 
+    /// Sends requests to a different Anthropic-compatible API.
+    ///
+    /// # Arguments
+    ///
+    /// * `base_url` - Base URL of the API, e.g. `http://127.0.0.1:4010`
+    ///
+    /// # Returns
+    ///
+    /// The client configured with the given base URL
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    /// Returns the URL of the messages endpoint.
+    fn messages_url(&self) -> String {
+        format!("{}/v1/messages", self.base_url.trim_end_matches('/'))
+    }
+
+    /// Returns the URL of the token counting endpoint.
+    fn count_tokens_url(&self) -> String {
+        format!("{}/count_tokens", self.messages_url())
+    }
+
+    /// Records exchanges to, or replays them from, a cassette store.
+    ///
+    /// # Arguments
+    ///
+    /// * `cassettes` - The store to use, or `None` to always use the network
+    ///
+    /// # Returns
+    ///
+    /// The client configured with the given cassette store
+    pub fn with_cassettes(mut self, cassettes: Option<CassetteStore>) -> Self {
+        self.cassettes = cassettes;
+        self
+    }
+
+    /// Reports the rate limits of every upstream response to a tracker.
+    ///
+    /// # Arguments
+    ///
+    /// * `limits` - The tracker to report to
+    /// * `key_id` - Identity of this client's API key in the tracker
+    ///
+    /// # Returns
+    ///
+    /// The client configured to report its rate limits
+    pub fn with_upstream_limits(mut self, limits: Arc<UpstreamLimits>, key_id: String) -> Self {
+        self.upstream_limits = Some((limits, key_id));
+        self
+    }
+
     /// Builds the HTTP headers required for Anthropic API requests.
     ///
     /// # Arguments
@@ -267,8 +437,8 @@ impl AnthropicClient {
         );
         
         // Check if we need to add the extended output beta header
-        if config.body.get("max_tokens").map_or(false, |v| {
-            v.as_u64().map_or(false, |tokens| tokens > 32000)
+        if config.body.get("max_tokens").is_some_and(|v| {
+            v.as_u64().is_some_and(|tokens| tokens > 32000)
         }) {
             headers.insert(
                 "anthropic-beta",
@@ -302,10 +472,11 @@ impl AnthropicClient {
     pub(crate) fn build_request(
         &self,
         messages: Vec<Message>,
-        system: Option<String>,
+        system: Option<MessageContent>,
         stream: bool,
         config: &ApiConfig,
     ) -> AnthropicRequest {
+        let system = system.map(message_content);
         let filtered_messages = messages
             .into_iter()
             .filter(|msg| msg.role != Role::System)
@@ -315,7 +486,7 @@ impl AnthropicClient {
                     Role::Assistant => "assistant".to_string(),
                     Role::System => unreachable!(),
                 },
-                content: msg.content,
+                content: message_content(msg.content),
             })
             .collect();
 
@@ -323,15 +494,7 @@ impl AnthropicClient {
         let default_model = serde_json::json!(DEFAULT_MODEL);
         let model_value = config.body.get("model").unwrap_or(&default_model);
         
-        let default_max_tokens = if let Some(model_str) = model_value.as_str() {
-            if model_str.contains("claude-3-opus") {
-                4096
-            } else {
-                8192
-            }
-        } else {
-            8192
-        };
+        let default_max_tokens = default_max_tokens(model_value.as_str().unwrap_or(DEFAULT_MODEL));
         let default_max_tokens_json = serde_json::json!(default_max_tokens);
 
         let mut request_value = serde_json::json!({
@@ -384,6 +547,24 @@ impl AnthropicClient {
         })
     }
 
+    /// Returns the response cache key of a request: the fingerprint of the
+    /// upstream request, built as a non-streaming request so that streaming
+    /// and non-streaming requests share it.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - Vector of messages for the conversation
+    /// * `system` - Optional system prompt to set context
+    /// * `config` - Configuration options for the request
+    pub(crate) fn cache_key(
+        &self,
+        messages: Vec<Message>,
+        system: Option<MessageContent>,
+        config: &ApiConfig,
+    ) -> String {
+        self.build_request(messages, system, false, config).fingerprint()
+    }
+
     /// Sends a non-streaming chat request to the Anthropic API.
     ///
     /// # Arguments
@@ -394,7 +575,8 @@ impl AnthropicClient {
     ///
     /// # Returns
     ///
-    /// * `Result<AnthropicResponse>` - The model's response on success
+    /// * `Result<UpstreamResponse<AnthropicResponse>>` - The model's response,
+    ///   with details of the upstream HTTP response, on success
     ///
     /// # Errors
     ///
@@ -405,53 +587,52 @@ impl AnthropicClient {
     pub async fn chat(
         &self,
         messages: Vec<Message>,
-        system: Option<String>,
+        system: Option<MessageContent>,
         config: &ApiConfig,
-    ) -> Result<AnthropicResponse> {
+    ) -> Result<UpstreamResponse<AnthropicResponse>> {
         let headers = self.build_headers(Some(&config.headers), config)?;
         let request = self.build_request(messages, system, false, config);
+        let fingerprint = request.fingerprint();
+        let span = telemetry::upstream_span(request.model(), &self.messages_url());
 
-        let response = self
-            .client
-            .post(ANTHROPIC_API_URL)
-            .headers(headers)
-            .json(&request)
-            .send()
-            .await
-            .map_err(|e| ApiError::AnthropicError { 
-                message: format!("Request failed: {}", e),
-                type_: "request_failed".to_string(),
-                param: None,
-                code: None
-            })?;
+        async {
+            let started = Instant::now();
+            let response = self.send(headers, &request, &fingerprint).await?;
+            let status = response.status;
+            let upstream_headers = response.headers;
+            let mut timing = response.timing;
+            let body = read_body(response.body).await?;
+            timing.total_ms = Some(started.elapsed().as_millis() as u64);
+            self.record_cassette(&fingerprint, &request, status, &upstream_headers, vec![body.clone()]);
 
-        if !response.status().is_success() {
-            let error = response
-                .text()
-                .await
-                .unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(ApiError::AnthropicError { 
-                message: error,
-                type_: "api_error".to_string(),
-                param: None,
-                code: None
-            });
-        }
+            if !(200..300).contains(&status) {
+                return Err(failed_response(status, &upstream_headers, &body));
+            }
 
-        response
-            .json::<AnthropicResponse>()
-            .await
-            .map_err(|e| ApiError::AnthropicError { 
-                message: format!("Failed to parse response: {}", e),
-                type_: "parse_error".to_string(),
-                param: None,
-                code: None
+            let body = serde_json::from_str::<AnthropicResponse>(&body)
+                .map_err(|e| ApiError::AnthropicError { 
+                    message: format!("Failed to parse response: {}", e),
+                    type_: "parse_error".to_string(),
+                    param: None,
+                    code: None,
+                    rate_limits: None,
+                })?;
+
+            Ok(UpstreamResponse {
+                status,
+                headers: upstream_headers,
+                timing,
+                body,
             })
+        }
+        .instrument(span)
+        .await
     }
 
     /// Sends a streaming chat request to the Anthropic API.
     ///
-    /// Returns a stream that yields events from the model's response as they arrive.
+    /// Waits for the upstream response headers, then returns a stream that
+    /// yields events from the model's response as they arrive.
     ///
     /// # Arguments
     ///
@@ -461,143 +642,503 @@ impl AnthropicClient {
     ///
     /// # Returns
     ///
-    /// * `Pin<Box<dyn Stream<Item = Result<StreamEvent>> + Send>>` - A stream of response events
+    /// * `Result<UpstreamResponse<EventStream>>` - A stream of response events,
+    ///   with details of the upstream HTTP response
     ///
     /// # Errors
     ///
-    /// The stream may yield `ApiError::AnthropicError` if:
-    /// - The API request fails
+    /// Returns `ApiError::AnthropicError` if the API request fails or the
+    /// response status is not successful. The stream may yield
+    /// `ApiError::AnthropicError` if:
     /// - Stream processing encounters an error
-    /// - Response events cannot be parsed
-    pub fn chat_stream(
+    /// - The API reports an error event
+    pub async fn chat_stream(
         &self,
         messages: Vec<Message>,
-        system: Option<String>,
+        system: Option<MessageContent>,
         config: &ApiConfig,
-    ) -> Pin<Box<dyn Stream<Item = Result<StreamEvent>> + Send>> {
-        println!("Setting up Anthropic chat_stream");
-        println!("API token length: {}", self.api_token.len());
-        
-        let headers = match self.build_headers(Some(&config.headers), config) {
-            Ok(h) => {
-                println!("Headers built successfully: {:?}", h);
-                h
-            },
-            Err(e) => {
-                println!("Error building headers: {:?}", e);
-                return Box::pin(futures::stream::once(async move { Err(e) }));
-            },
-        };
-
+    ) -> Result<UpstreamResponse<EventStream>> {
+        let headers = self.build_headers(Some(&config.headers), config)?;
         let request = self.build_request(messages, system, true, config);
-        println!("Request built with stream={}", request.stream);
-        let client = self.client.clone();
-
-        Box::pin(async_stream::try_stream! {
-            println!("Sending request to Anthropic API: {}", ANTHROPIC_API_URL);
-            
-            let response = client
-                .post(ANTHROPIC_API_URL)
-                .headers(headers)
-                .json(&request)
-                .send()
-                .await
-                .map_err(|e| {
-                    println!("Error sending request: {}", e);
-                    ApiError::AnthropicError { 
-                        message: format!("Request failed: {}", e),
-                        type_: "request_failed".to_string(),
-                        param: None,
-                        code: None
-                    }
-                })?;
-                
-            println!("Received response from Anthropic API: status={}", response.status());
-            
-            if !response.status().is_success() {
-                // Get the status code before consuming the response
-                let status = response.status().as_u16();
-                
-                // Clone headers for logging if needed
-                let headers_str = format!("{:?}", response.headers());
-                
-                // Now consume the response to get the error text
-                let error_text = response.text().await.unwrap_or_else(|_| "Failed to read error response".to_string());
-                println!("Error response from Anthropic API (status {}): {} - Headers: {}", 
-                    status, error_text, headers_str);
-                
-                let err = ApiError::AnthropicError { 
-                    message: format!("API Error ({}): {}", status, error_text),
-                    type_: "api_error".to_string(),
-                    param: None,
-                    code: Some(status.to_string())
-                };
-                Err(err)?;  // This properly propagates the error in the try_stream macro
-                
-                // We won't reach here, but this satisfies the compiler
-                return;
+        let fingerprint = request.fingerprint();
+        let span = telemetry::upstream_span(request.model(), &self.messages_url());
+
+        let response = async {
+            let response = self.send(headers, &request, &fingerprint).await?;
+            if !(200..300).contains(&response.status) {
+                let body = read_body(response.body).await?;
+                self.record_cassette(&fingerprint, &request, response.status, &response.headers, vec![body.clone()]);
+                return Err(failed_response(response.status, &response.headers, &body));
             }
-            
-            // Only get here if status is success
-            let mut stream = response.bytes_stream();
+            Ok(response)
+        }
+        .instrument(span.clone())
+        .await?;
+
+        let recorder = self.cassettes.clone().filter(CassetteStore::is_recording);
+        let status = response.status;
+        let recorded_headers = response.headers.clone();
+        let mut stream = response.body;
+        // Complete responses are recorded, including those ending in an
+        // error event; interrupted ones are not
+        let record = move |chunks: Vec<String>| {
+            if let Some(store) = &recorder {
+                store.save(&fingerprint, &Cassette {
+                    request: serde_json::to_value(&request).unwrap_or_default(),
+                    status,
+                    headers: recorded_headers.clone(),
+                    chunks,
+                });
+            }
+        };
+        let recording = self.cassettes.as_ref().is_some_and(CassetteStore::is_recording);
+
+        // The upstream span stays open until the response stream is consumed
+        let events = Box::pin(async_stream::try_stream! {
+            let mut recorded = Vec::new();
+            let mut buffer = SseBuffer::default();
 
-            let mut data = String::new();
-            
             while let Some(chunk) = stream.next().await {
                 let chunk = chunk.map_err(|e| {
-                    println!("Stream error: {}", e);
+                    tracing::warn!(error = %e, "Anthropic stream interrupted");
                     ApiError::AnthropicError { 
                         message: format!("Stream error: {}", e),
                         type_: "stream_error".to_string(),
                         param: None,
-                        code: None
+                        code: None,
+                        rate_limits: None,
                     }
                 })?;
                 
-                let chunk_text = String::from_utf8_lossy(&chunk);
-                println!("Received chunk: {} bytes", chunk.len());
-                data.push_str(&chunk_text);
-
-                let mut start = 0;
-                while let Some(end) = data[start..].find("\n\n") {
-                    let end = start + end;
-                    let event_data = &data[start..end];
-                    start = end + 2;
-
-                    println!("Processing SSE event: {}", event_data);
-                    
+                tracing::trace!(bytes = chunk.len(), "Received chunk");
+                buffer.push(&chunk);
+
+                while let Some(event_data) = buffer.next_event() {
+                    // Recorded event by event, so that no chunk ends
+                    // inside a UTF-8 character
+                    if recording {
+                        recorded.push(format!("{}\n\n", event_data));
+                    }
+
                     if let Some(event_line) = event_data.strip_prefix("event: ") {
-                        println!("Found event type: {}", event_line.lines().next().unwrap_or("unknown"));
+                        let event_type = event_line.lines().next().unwrap_or("unknown");
+                        tracing::trace!(event_type, "Received SSE event");
                         
                         if let Some(data_line) = event_data.lines().nth(1) {
                             if let Some(json_data) = data_line.strip_prefix("data: ") {
-                                println!("Parsing JSON data: {}", json_data);
+                                tracing::trace!(data = %crate::logging::content(json_data), "SSE event data");
                                 
                                 match serde_json::from_str::<StreamEvent>(json_data) {
+                                    Ok(StreamEvent::Error { error }) => {
+                                        tracing::warn!(error_type = %error.error_type, "Anthropic stream error event");
+                                        record(std::mem::take(&mut recorded));
+                                        Err(ApiError::AnthropicError {
+                                            message: error.message,
+                                            type_: error.error_type,
+                                            param: None,
+                                            code: None,
+                                            rate_limits: None,
+                                        })?;
+                                    },
                                     Ok(event) => {
-                                        println!("Successfully parsed StreamEvent");
                                         yield event;
                                     },
                                     Err(e) => {
-                                        println!("Failed to parse event JSON: {}", e);
+                                        tracing::debug!(event_type, error = %e, "Skipping unparseable SSE event");
                                     }
                                 }
                             } else {
-                                println!("Data line doesn't start with 'data: ': {}", data_line);
+                                tracing::debug!(event_type, "SSE event without data line");
                             }
                         } else {
-                            println!("No data line found in event");
+                            tracing::debug!(event_type, "SSE event without data line");
                         }
                     } else {
-                        println!("Event doesn't start with 'event: ': {}", event_data);
+                        tracing::debug!("Skipping SSE block without event line");
                     }
                 }
+            }
+
+            record(recorded);
+        });
+
+        Ok(UpstreamResponse {
+            status,
+            headers: response.headers,
+            timing: response.timing,
+            body: Box::pin(InSpan::new(events, span)),
+        })
+    }
 
-                if start > 0 {
-                    data = data[start..].to_string();
+    /// Counts the input tokens of a request with Anthropic's token counting
+    /// API, without sending it to the model.
+    ///
+    /// Only the parameters the counting API accepts are sent: the model,
+    /// messages, system prompt, thinking and tools. Cassettes are not used.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - Vector of messages for the conversation
+    /// * `system` - Optional system prompt to set context
+    /// * `config` - Configuration options for the request
+    ///
+    /// # Returns
+    ///
+    /// * `Result<u32>` - The number of input tokens
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::AnthropicError` if the request cannot be sent, the
+    /// response status is not successful, or the response cannot be parsed
+    pub async fn count_tokens(
+        &self,
+        messages: Vec<Message>,
+        system: Option<MessageContent>,
+        config: &ApiConfig,
+    ) -> Result<u32> {
+        let headers = self.build_headers(Some(&config.headers), config)?;
+        let request = self.build_request(messages, system, false, config);
+        let span = telemetry::upstream_span(request.model(), &self.count_tokens_url());
+        let body = match serde_json::to_value(&request) {
+            Ok(serde_json::Value::Object(map)) => map
+                .into_iter()
+                .filter(|(key, _)| COUNT_TOKENS_PARAMS.contains(&key.as_str()))
+                .collect::<serde_json::Map<_, _>>(),
+            _ => serde_json::Map::new(),
+        };
+
+        async {
+            let response = self
+                .client
+                .post(self.count_tokens_url())
+                .headers(headers)
+                .json(&body)
+                .send()
+                .await
+                .map_err(|e| {
+                    tracing::warn!(error = %e, "Anthropic token count request failed");
+                    ApiError::AnthropicError {
+                        message: format!("Request failed: {}", e),
+                        type_: "request_failed".to_string(),
+                        param: None,
+                        code: None,
+                        rate_limits: None,
+                    }
+                })?;
+            let status = response.status().as_u16();
+            tracing::Span::current().record("http.response.status_code", status);
+            let upstream_headers = selected_headers(response.headers());
+            let body = response.text().await.unwrap_or_default();
+            if !(200..300).contains(&status) {
+                return Err(failed_response(status, &upstream_headers, &body));
+            }
+
+            serde_json::from_str::<TokenCount>(&body)
+                .map(|count| count.input_tokens)
+                .map_err(|e| ApiError::AnthropicError {
+                    message: format!("Failed to parse token count: {}", e),
+                    type_: "parse_error".to_string(),
+                    param: None,
+                    code: None,
+                    rate_limits: None,
+                })
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Sends a request upstream, or replays its recorded response.
+    ///
+    /// # Arguments
+    ///
+    /// * `headers` - Request headers, including authentication
+    /// * `request` - The request to send
+    /// * `fingerprint` - Fingerprint of the request, naming its cassette
+    ///
+    /// # Returns
+    ///
+    /// * `Result<UpstreamResponse<ByteStream>>` - The response, with its
+    ///   body not yet read
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::AnthropicError` if the request cannot be sent, or
+    /// if replaying and no cassette exists for it
+    async fn send(
+        &self,
+        headers: HeaderMap,
+        request: &AnthropicRequest,
+        fingerprint: &str,
+    ) -> Result<UpstreamResponse<ByteStream>> {
+        let response: UpstreamResponse<ByteStream> = match self.replay_cassette(fingerprint)? {
+            Some(cassette) => {
+                tracing::debug!(fingerprint, "Replaying Anthropic response from cassette");
+                let chunks = cassette.chunks.into_iter().map(|c| Ok(Bytes::from(c)));
+                UpstreamResponse {
+                    status: cassette.status,
+                    headers: cassette.headers,
+                    timing: UpstreamTiming::default(),
+                    body: Box::pin(futures::stream::iter(chunks)),
                 }
             }
+            None => {
+                let url = self.messages_url();
+                tracing::debug!(url = %url, stream = request.stream, "Sending request to Anthropic");
+                tracing::trace!(
+                    body = %crate::logging::content(&serde_json::to_string(request).unwrap_or_default()),
+                    "Anthropic request body"
+                );
+                let mut headers = headers;
+                telemetry::inject_context(&mut headers);
+                let started = Instant::now();
+
+                let response = self
+                    .client
+                    .post(&url)
+                    .headers(headers)
+                    .json(request)
+                    .send()
+                    .await
+                    .map_err(|e| {
+                        tracing::warn!(error = %e, "Anthropic request failed");
+                        ApiError::AnthropicError { 
+                            message: format!("Request failed: {}", e),
+                            type_: "request_failed".to_string(),
+                            param: None,
+                            code: None,
+                            rate_limits: None,
+                        }
+                    })?;
+
+                let response = UpstreamResponse {
+                    status: response.status().as_u16(),
+                    headers: selected_headers(response.headers()),
+                    timing: UpstreamTiming {
+                        headers_ms: started.elapsed().as_millis() as u64,
+                        total_ms: None,
+                    },
+                    body: Box::pin(response.bytes_stream()) as ByteStream,
+                };
+                if let (Some((tracker, key_id)), Some(limits)) =
+                    (&self.upstream_limits, response.rate_limits())
+                {
+                    tracker.record(key_id, limits);
+                }
+                response
+            }
+        };
+
+        tracing::Span::current().record("http.response.status_code", response.status);
+        tracing::debug!(
+            status = response.status,
+            upstream_request_id = response.request_id(),
+            "Anthropic response received"
+        );
+        Ok(response)
+    }
+
+    /// Looks up the recorded response for a request when replaying.
+    ///
+    /// # Arguments
+    ///
+    /// * `fingerprint` - Fingerprint of the upstream request
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<Cassette>>` - The recorded exchange in replay mode,
+    ///   `None` otherwise
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::AnthropicError` if replaying and no cassette exists
+    fn replay_cassette(&self, fingerprint: &str) -> Result<Option<Cassette>> {
+        match &self.cassettes {
+            Some(store) if store.is_replaying() => store.load(fingerprint).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Records an exchange when recording is enabled.
+    fn record_cassette(
+        &self,
+        fingerprint: &str,
+        request: &AnthropicRequest,
+        status: u16,
+        headers: &HashMap<String, String>,
+        chunks: Vec<String>,
+    ) {
+        if let Some(store) = self.cassettes.as_ref().filter(|s| s.is_recording()) {
+            store.save(fingerprint, &Cassette {
+                request: serde_json::to_value(request).unwrap_or_default(),
+                status,
+                headers: headers.clone(),
+                chunks,
+            });
+        }
+    }
+}
+
+/// Collects the upstream response headers worth passing on to callers.
+fn selected_headers(headers: &HeaderMap) -> HashMap<String, String> {
+    headers
+        .iter()
+        .filter(|(name, _)| {
+            FORWARDED_HEADERS.contains(&name.as_str())
+                || name.as_str().starts_with(RATE_LIMIT_HEADER_PREFIX)
         })
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect()
+}
+
+/// Reads a whole response body as text.
+///
+/// # Errors
+///
+/// Returns `ApiError::AnthropicError` if the body cannot be read in full
+async fn read_body(mut stream: ByteStream) -> Result<String> {
+    let mut body = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| {
+            tracing::warn!(error = %e, "Failed to read Anthropic response body");
+            ApiError::AnthropicError {
+                message: format!("Failed to read response body: {}", e),
+                type_: "request_failed".to_string(),
+                param: None,
+                code: None,
+                rate_limits: None,
+            }
+        })?;
+        body.extend_from_slice(&chunk);
+    }
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+/// Logs an unsuccessful upstream response, marks the current span as
+/// failed, and builds the error to return, carrying the rate limits the
+/// response reported.
+fn failed_response(status: u16, headers: &HashMap<String, String>, body: &str) -> ApiError {
+    let request_id = headers.get(REQUEST_ID_HEADER).map(String::as_str);
+    tracing::warn!(status, upstream_request_id = request_id, "Anthropic returned an error response");
+    tracing::trace!(body = %crate::logging::content(body), "Anthropic error body");
+
+    let mut error = upstream_error(status, body);
+    if let ApiError::AnthropicError { type_, rate_limits, .. } = &mut error {
+        telemetry::record_error(&tracing::Span::current(), type_);
+        *rate_limits = AnthropicRateLimits::from_headers(headers).map(Box::new);
+    }
+    error
+}
+
+/// Builds the error for an unsuccessful upstream response.
+///
+/// Anthropic error bodies look like
+/// `{"type": "error", "error": {"type": "overloaded_error", "message": "..."}}`;
+/// the error type and message are taken from the body when it has this
+/// shape, and the raw body is used otherwise.
+///
+/// # Arguments
+///
+/// * `status` - HTTP status code of the upstream response
+/// * `body` - Raw upstream response body
+fn upstream_error(status: u16, body: &str) -> ApiError {
+    let error = serde_json::from_str::<serde_json::Value>(body)
+        .ok()
+        .and_then(|v| serde_json::from_value::<StreamError>(v.get("error")?.clone()).ok());
+
+    let (type_, message) = match error {
+        Some(error) => (error.error_type, error.message),
+        None => ("api_error".to_string(), body.to_string()),
+    };
+
+    ApiError::AnthropicError {
+        message: format!("API Error ({}): {}", status, message),
+        type_,
+        param: None,
+        code: Some(status.to_string()),
+        rate_limits: None,
+    }
+}
+
+impl AnthropicRequest {
+    /// Returns the model this request is addressed to.
+    pub(crate) fn model(&self) -> &str {
+        self.additional_params
+            .get("model")
+            .and_then(|v| v.as_str())
+            .unwrap_or(DEFAULT_MODEL)
+    }
+
+    /// Computes a stable fingerprint of this request.
+    ///
+    /// The request is serialized with object keys sorted at every level, so
+    /// two requests with the same content always share a fingerprint.
+    ///
+    /// # Returns
+    ///
+    /// The hex-encoded SHA-256 digest of the canonical request JSON
+    pub(crate) fn fingerprint(&self) -> String {
+        let value = serde_json::to_value(self).unwrap_or_default();
+        let digest = Sha256::digest(canonical_json(&value).as_bytes());
+        hex::encode(digest)
+    }
+}
+
+/// Converts message content to the form Anthropic expects.
+///
+/// Content blocks keep only the fields Anthropic accepts for their type,
+/// since it rejects unknown fields.
+fn message_content(content: MessageContent) -> serde_json::Value {
+    match content {
+        MessageContent::Text(text) => serde_json::Value::String(text),
+        MessageContent::Blocks(blocks) => blocks.into_iter().map(upstream_block).collect(),
+    }
+}
+
+/// Converts a content block to the form Anthropic expects.
+fn upstream_block(block: models::ContentBlock) -> serde_json::Value {
+    let mut value = match block.content_type.as_str() {
+        "thinking" => serde_json::json!({
+            "type": block.content_type,
+            "thinking": block.thinking.unwrap_or_default(),
+            "signature": block.signature.unwrap_or_default(),
+        }),
+        "redacted_thinking" => serde_json::json!({
+            "type": block.content_type,
+            "data": block.data.unwrap_or_default(),
+        }),
+        _ => serde_json::json!({
+            "type": block.content_type,
+            "text": block.text,
+        }),
+    };
+    if let (Some(cache_control), serde_json::Value::Object(map)) = (block.cache_control, &mut value) {
+        map.insert(
+            "cache_control".to_string(),
+            serde_json::to_value(cache_control).unwrap_or_default(),
+        );
+    }
+    value
+}
+
+/// Serializes a JSON value with object keys in sorted order.
+fn canonical_json(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            let fields: Vec<String> = entries
+                .into_iter()
+                .map(|(k, v)| {
+                    format!("{}:{}", serde_json::Value::String(k.clone()), canonical_json(v))
+                })
+                .collect();
+            format!("{{{}}}", fields.join(","))
+        }
+        serde_json::Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(canonical_json).collect();
+            format!("[{}]", items.join(","))
+        }
+        other => other.to_string(),
     }
 }
 
@@ -610,6 +1151,7 @@ impl From<ContentBlock> for crate::models::response::ContentBlock {
             thinking: block.thinking,
             signature: block.signature,
             data: block.data,
+            cache_control: None,
         }
     }
 }
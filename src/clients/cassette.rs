@@ -0,0 +1,124 @@
+//! Recording and replay of upstream API exchanges.
+//!
+//! A cassette captures the exact request body sent upstream together with
+//! the status code, selected response headers and raw response body; a
+//! streamed body is split into its events. In record mode every exchange
+//! is written to the cassette directory; in replay mode responses are
+//! served from it and the network is never touched.
+//!
+//! Cassettes are named after the request fingerprint, so replaying the same
+//! conversation with the same parameters finds the same cassette. Request
+//! headers, including API keys, are never recorded.
+
+use crate::{
+    config::{CassetteConfig, CassetteMode},
+    error::{ApiError, Result},
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::PathBuf};
+
+/// A single recorded request/response exchange.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cassette {
+    pub request: serde_json::Value,
+    pub status: u16,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    pub chunks: Vec<String>,
+}
+
+/// Directory of cassettes used for recording or replay.
+#[derive(Debug, Clone)]
+pub struct CassetteStore {
+    mode: CassetteMode,
+    dir: PathBuf,
+}
+
+impl CassetteStore {
+    /// Creates a store from configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Cassette configuration
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Self>` - The store, or `None` when recording is off
+    pub fn from_config(config: &CassetteConfig) -> Option<Self> {
+        match config.mode {
+            CassetteMode::Off => None,
+            mode => Some(Self {
+                mode,
+                dir: PathBuf::from(&config.dir),
+            }),
+        }
+    }
+
+    /// Returns true if exchanges should be written to the store.
+    pub fn is_recording(&self) -> bool {
+        self.mode == CassetteMode::Record
+    }
+
+    /// Returns true if responses should be served from the store.
+    pub fn is_replaying(&self) -> bool {
+        self.mode == CassetteMode::Replay
+    }
+
+    fn path(&self, fingerprint: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", fingerprint))
+    }
+
+    /// Loads the cassette recorded for a request.
+    ///
+    /// # Arguments
+    ///
+    /// * `fingerprint` - Fingerprint of the upstream request
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Cassette>` - The recorded exchange
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::AnthropicError` if no cassette exists for the
+    /// request or it cannot be parsed
+    pub fn load(&self, fingerprint: &str) -> Result<Cassette> {
+        let path = self.path(fingerprint);
+        let contents = std::fs::read_to_string(&path).map_err(|e| ApiError::AnthropicError {
+            message: format!("No cassette for request at {}: {}", path.display(), e),
+            type_: "cassette_not_found".to_string(),
+            param: None,
+            code: None,
+            rate_limits: None,
+        })?;
+
+        serde_json::from_str(&contents).map_err(|e| ApiError::AnthropicError {
+            message: format!("Invalid cassette {}: {}", path.display(), e),
+            type_: "cassette_invalid".to_string(),
+            param: None,
+            code: None,
+            rate_limits: None,
+        })
+    }
+
+    /// Writes a cassette, replacing any existing recording for the request.
+    ///
+    /// Failures are logged rather than returned so that recording never
+    /// fails the request being recorded.
+    ///
+    /// # Arguments
+    ///
+    /// * `fingerprint` - Fingerprint of the upstream request
+    /// * `cassette` - The exchange to record
+    pub fn save(&self, fingerprint: &str, cassette: &Cassette) {
+        let path = self.path(fingerprint);
+        let result = std::fs::create_dir_all(&self.dir)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| Ok(serde_json::to_vec_pretty(cassette)?))
+            .and_then(|bytes| Ok(std::fs::write(&path, bytes)?));
+
+        if let Err(e) = result {
+            tracing::warn!("Failed to write cassette {}: {}", path.display(), e);
+        }
+    }
+}
@@ -2,13 +2,19 @@
 //!
 //! This module contains client implementations for different AI model providers:
 //! - `anthropic`: Client for Anthropic's Claude models
+//! - `cassette`: Recording and replay of upstream exchanges
+//! - `rate_limits`: Parsing of Anthropic's rate limit headers
 //!
 //! Each client handles authentication, request building, and response parsing
 //! specific to its provider's API.
 
 pub mod anthropic;
+pub mod cassette;
+pub mod rate_limits;
 
 pub use anthropic::AnthropicClient;
+pub use cassette::CassetteStore;
+pub use rate_limits::AnthropicRateLimits;
 
 use crate::error::Result;
 use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
@@ -0,0 +1,154 @@
+//! Anthropic rate limit headers.
+//!
+//! Every Anthropic response reports the remaining allowance of the API key
+//! that made the request in `anthropic-ratelimit-*` headers, one group of
+//! `limit`, `remaining` and `reset` headers per limited resource, plus
+//! `retry-after` once a limit has been hit. This module parses them into
+//! [`AnthropicRateLimits`] and writes them back out for callers.
+
+use chrono::{DateTime, Duration, Utc};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use std::collections::HashMap;
+
+/// Prefix of the rate limit headers Anthropic sends on every response.
+pub const RATE_LIMIT_HEADER_PREFIX: &str = "anthropic-ratelimit-";
+
+/// Header telling clients how many seconds to wait before retrying.
+const RETRY_AFTER_HEADER: &str = "retry-after";
+
+/// The state of one of an API key's rate limits.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RateLimitWindow {
+    /// Maximum allowed within the window
+    pub limit: Option<u64>,
+    /// Amount left before the limit is reached
+    pub remaining: Option<u64>,
+    /// When the limit will be fully replenished
+    pub reset: Option<DateTime<Utc>>,
+}
+
+impl RateLimitWindow {
+    /// Returns when the limit is available again, if it is exhausted.
+    pub fn exhausted_until(&self) -> Option<DateTime<Utc>> {
+        match (self.remaining, self.reset) {
+            (Some(0), Some(reset)) => Some(reset),
+            _ => None,
+        }
+    }
+}
+
+/// Rate limits Anthropic reported for an API key.
+///
+/// Parsed from the `anthropic-ratelimit-{requests,tokens,input-tokens,
+/// output-tokens}-{limit,remaining,reset}` and `retry-after` response
+/// headers.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AnthropicRateLimits {
+    pub requests: RateLimitWindow,
+    pub tokens: RateLimitWindow,
+    pub input_tokens: RateLimitWindow,
+    pub output_tokens: RateLimitWindow,
+    /// Seconds to wait before retrying, sent with `429` responses
+    pub retry_after: Option<u64>,
+}
+
+impl AnthropicRateLimits {
+    /// Parses the rate limit headers of an upstream response.
+    ///
+    /// Headers with malformed values are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `headers` - Upstream response headers, keyed by lowercase name
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Self>` - The rate limits, or `None` if the response carried
+    ///   no rate limit headers
+    pub fn from_headers(headers: &HashMap<String, String>) -> Option<Self> {
+        let window = |resource: &str| {
+            let value = |field: &str| {
+                headers
+                    .get(&format!("{}{}-{}", RATE_LIMIT_HEADER_PREFIX, resource, field))
+                    .map(|value| value.trim())
+            };
+            RateLimitWindow {
+                limit: value("limit").and_then(|v| v.parse().ok()),
+                remaining: value("remaining").and_then(|v| v.parse().ok()),
+                reset: value("reset")
+                    .and_then(|v| DateTime::parse_from_rfc3339(v).ok())
+                    .map(|reset| reset.with_timezone(&Utc)),
+            }
+        };
+
+        let limits = Self {
+            requests: window("requests"),
+            tokens: window("tokens"),
+            input_tokens: window("input-tokens"),
+            output_tokens: window("output-tokens"),
+            retry_after: headers
+                .get(RETRY_AFTER_HEADER)
+                .and_then(|v| v.trim().parse().ok()),
+        };
+        (limits != Self::default()).then_some(limits)
+    }
+
+    /// Returns when requests may be sent again, if any limit is exhausted.
+    ///
+    /// # Arguments
+    ///
+    /// * `received` - When the headers were received, which `retry-after`
+    ///   is relative to
+    pub fn blocked_until(&self, received: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        [
+            &self.requests,
+            &self.tokens,
+            &self.input_tokens,
+            &self.output_tokens,
+        ]
+        .into_iter()
+        .filter_map(RateLimitWindow::exhausted_until)
+        .chain(
+            self.retry_after
+                .map(|seconds| received + Duration::seconds(seconds as i64)),
+        )
+        .max()
+    }
+
+    /// Writes these limits as `anthropic-ratelimit-*` headers, and
+    /// `retry-after` if Anthropic sent one.
+    ///
+    /// # Arguments
+    ///
+    /// * `headers` - The response headers to add the rate limit headers to
+    pub fn apply_headers(&self, headers: &mut HeaderMap) {
+        let windows = [
+            ("requests", &self.requests),
+            ("tokens", &self.tokens),
+            ("input-tokens", &self.input_tokens),
+            ("output-tokens", &self.output_tokens),
+        ];
+
+        for (resource, window) in windows {
+            let values = [
+                ("limit", window.limit.map(|v| v.to_string())),
+                ("remaining", window.remaining.map(|v| v.to_string())),
+                ("reset", window.reset.map(|v| v.to_rfc3339())),
+            ];
+            for (field, value) in values {
+                let name = format!("{}{}-{}", RATE_LIMIT_HEADER_PREFIX, resource, field);
+                if let (Some(value), Ok(name)) = (value, HeaderName::from_bytes(name.as_bytes())) {
+                    if let Ok(value) = HeaderValue::from_str(&value) {
+                        headers.insert(name, value);
+                    }
+                }
+            }
+        }
+        if let Some(retry_after) = self.retry_after {
+            headers.insert(
+                HeaderName::from_static(RETRY_AFTER_HEADER),
+                HeaderValue::from(retry_after),
+            );
+        }
+    }
+}
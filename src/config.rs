@@ -5,7 +5,13 @@
 //! AI model providers and server settings.
 
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::{collections::HashMap, net::SocketAddr, path::Path};
+
+/// Config file read when no explicit path is given.
+pub const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+/// Replacement shown for secrets when printing configuration.
+const SECRET_MASK: &str = "********";
 
 /// Root configuration structure containing all application settings.
 ///
@@ -14,17 +20,470 @@ use std::path::Path;
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Config {
     pub server: ServerConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
     pub pricing: PricingConfig,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    #[serde(default)]
+    pub budgets: BudgetsConfig,
+    #[serde(default)]
+    pub ledger: LedgerConfig,
+    #[serde(default)]
+    pub conversations: ConversationsConfig,
+    #[serde(default)]
+    pub context: ContextConfig,
+    #[serde(default)]
+    pub prompt_cache: PromptCacheConfig,
+    #[serde(default)]
+    pub response_cache: ResponseCacheConfig,
+    #[serde(default)]
+    pub fallbacks: FallbackConfig,
+    #[serde(default)]
+    pub admin: AdminConfig,
+    #[serde(default)]
+    pub anthropic: AnthropicConfig,
+    #[serde(default)]
+    pub cassettes: CassetteConfig,
+    #[serde(default)]
+    pub health: HealthConfig,
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
 }
 
 /// Server-specific configuration settings.
 ///
 /// Contains settings related to the HTTP server, such as the
-/// host address and port number to bind to.
+/// host address and port number to bind to, TLS and CORS.
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
+    #[serde(default = "default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64, // how long open streams may finish on shutdown
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
+    #[serde(default)]
+    pub cors: CorsConfig,
+}
+
+fn default_shutdown_timeout_secs() -> u64 {
+    30
+}
+
+/// TLS termination settings.
+///
+/// When present, the server accepts HTTPS only. The certificate and key
+/// are read again on every config reload, so renewed certificates can be
+/// picked up with `SIGHUP`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct TlsConfig {
+    pub cert_path: String,  // PEM certificate chain
+    pub key_path: String,   // PEM private key
+}
+
+/// Cross-origin request policy for browsers.
+///
+/// Requests carry the caller's Anthropic API key, so only trusted origins
+/// should be allowed. `"*"` allows any origin and cannot be combined with
+/// `allow_credentials`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    pub allow_credentials: bool,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec!["http://localhost:3000".to_string()],
+            allowed_methods: vec!["GET".to_string(), "POST".to_string(), "DELETE".to_string()],
+            allowed_headers: [
+                "content-type",
+                "x-anthropic-api-token",
+                "x-deepclaude-project",
+                "x-deepclaude-cache",
+                "x-request-id",
+                "traceparent",
+                "tracestate",
+            ]
+            .map(String::from)
+            .to_vec(),
+            allow_credentials: false,
+        }
+    }
+}
+
+/// Logging settings.
+///
+/// API keys are always redacted from logs. Prompt and completion text is
+/// only logged at `trace` level, and only when `redact_prompts` is off.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    pub filter: String,          // tracing filter directives, overridden by RUST_LOG
+    pub redact_prompts: bool,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::default(),
+            filter: "deepclaude=info,tower_http=info".to_string(),
+            redact_prompts: true,
+        }
+    }
+}
+
+/// Output format for log lines.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Per-caller admission control settings.
+///
+/// Every limit is optional; an unset limit is not enforced. Limits apply
+/// to each caller identity separately.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct LimitsConfig {
+    pub requests_per_minute: Option<u32>,
+    pub tokens_per_minute: Option<u32>,   // input + output tokens
+    pub max_concurrent_streams: Option<u32>,
+    #[serde(default = "default_upstream_backoff")]
+    pub upstream_backoff: bool,           // hold requests while Anthropic's limits are exhausted
+}
+
+fn default_upstream_backoff() -> bool {
+    true
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_minute: None,
+            tokens_per_minute: None,
+            max_concurrent_streams: None,
+            upstream_backoff: default_upstream_backoff(),
+        }
+    }
+}
+
+/// Spend budget settings.
+///
+/// Budgets are tracked per caller key and, when the caller names one,
+/// per project. Spend is persisted to `store_path` so budgets survive
+/// restarts; without a path it is kept in memory only.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct BudgetsConfig {
+    pub store_path: Option<String>,
+    #[serde(default = "default_soft_limit_ratio")]
+    pub soft_limit_ratio: f64,      // fraction of a budget that triggers warnings
+    pub default_key: Option<BudgetLimit>,
+    #[serde(default)]
+    pub keys: HashMap<String, BudgetLimit>,
+    #[serde(default)]
+    pub projects: HashMap<String, BudgetLimit>,
+}
+
+/// Dollar limits for a single budget scope.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct BudgetLimit {
+    pub daily_usd: Option<f64>,
+    pub monthly_usd: Option<f64>,
+}
+
+fn default_soft_limit_ratio() -> f64 {
+    0.8
+}
+
+impl Default for BudgetsConfig {
+    fn default() -> Self {
+        Self {
+            store_path: None,
+            soft_limit_ratio: default_soft_limit_ratio(),
+            default_key: None,
+            keys: HashMap::new(),
+            projects: HashMap::new(),
+        }
+    }
+}
+
+/// Usage ledger settings.
+///
+/// When `path` is set, every completed request is appended to it
+/// as a JSON line.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct LedgerConfig {
+    pub path: Option<String>,
+}
+
+/// Server-side conversation settings.
+///
+/// When `path` is set, conversations are stored in an embedded database
+/// at that path and can be continued by id.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct ConversationsConfig {
+    pub path: Option<String>,
+}
+
+/// Context window management settings.
+///
+/// When a request's estimated input would not fit in `context_window`
+/// alongside its `max_tokens`, the `strategies` are applied in order until
+/// it does. With no strategies the request is sent as is.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ContextConfig {
+    #[serde(default)]
+    pub strategies: Vec<ContextStrategy>,
+    #[serde(default = "default_context_window")]
+    pub context_window: u32,            // tokens, shared by input and max_tokens
+    #[serde(default = "default_keep_recent_messages")]
+    pub keep_recent_messages: usize,    // never dropped or summarized
+    #[serde(default = "default_summary_model")]
+    pub summary_model: String,
+    #[serde(default = "default_summary_max_tokens")]
+    pub summary_max_tokens: u32,
+}
+
+/// A way of shrinking a request to fit the context window.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextStrategy {
+    /// Remove thinking blocks from all but the latest assistant message
+    StripThinking,
+    /// Remove the oldest messages
+    DropOldest,
+    /// Replace older messages with a summary written by `summary_model`
+    Summarize,
+}
+
+impl ContextStrategy {
+    /// Returns the strategy's name as used in configuration.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ContextStrategy::StripThinking => "strip_thinking",
+            ContextStrategy::DropOldest => "drop_oldest",
+            ContextStrategy::Summarize => "summarize",
+        }
+    }
+}
+
+fn default_context_window() -> u32 {
+    200_000
+}
+
+fn default_keep_recent_messages() -> usize {
+    4
+}
+
+fn default_summary_model() -> String {
+    "claude-3-5-haiku-20241022".to_string()
+}
+
+fn default_summary_max_tokens() -> u32 {
+    1024
+}
+
+impl Default for ContextConfig {
+    fn default() -> Self {
+        Self {
+            strategies: Vec::new(),
+            context_window: default_context_window(),
+            keep_recent_messages: default_keep_recent_messages(),
+            summary_model: default_summary_model(),
+            summary_max_tokens: default_summary_max_tokens(),
+        }
+    }
+}
+
+/// Prompt caching settings.
+///
+/// With `auto` enabled, requests that do not set `cache_control`
+/// themselves get cache breakpoints on the system prompt and on the last
+/// stable turn. Requests can override this with `auto_cache`.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct PromptCacheConfig {
+    #[serde(default)]
+    pub auto: bool,
+}
+
+/// Exact-match response cache settings.
+///
+/// Disabled by default: identical requests are cached only when `enabled`
+/// is set. Entries are kept in memory and, with `dir` set, on disk.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ResponseCacheConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_response_cache_capacity")]
+    pub capacity: usize,                // entries kept in memory
+    #[serde(default = "default_response_cache_ttl")]
+    pub ttl_secs: u64,
+    #[serde(default)]
+    pub dir: Option<String>,
+}
+
+fn default_response_cache_capacity() -> usize {
+    1000
+}
+
+fn default_response_cache_ttl() -> u64 {
+    86_400
+}
+
+impl Default for ResponseCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            capacity: default_response_cache_capacity(),
+            ttl_secs: default_response_cache_ttl(),
+            dir: None,
+        }
+    }
+}
+
+/// Model fallback chains.
+///
+/// When Anthropic is overloaded (`529`) or fails (`5xx`) before a response
+/// has started, the steps configured for the requested model are tried in
+/// order, each on its own. Models without a chain are not retried.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct FallbackConfig {
+    /// Steps to try, by requested model
+    #[serde(default)]
+    pub chains: HashMap<String, Vec<FallbackStep>>,
+}
+
+/// A way of retrying a request that failed upstream.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct FallbackStep {
+    #[serde(default)]
+    pub model: Option<String>,        // defaults to the requested model
+    #[serde(default = "default_fallback_thinking")]
+    pub thinking: bool,               // false sends the request without extended thinking
+}
+
+fn default_fallback_thinking() -> bool {
+    true
+}
+
+/// Admin API settings.
+///
+/// The admin endpoints are disabled unless a bearer token is configured.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct AdminConfig {
+    pub token: Option<String>,
+}
+
+/// Upstream Anthropic API settings.
+///
+/// `base_url` can point at any Anthropic-compatible server, such as the
+/// bundled `mock-anthropic` binary.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AnthropicConfig {
+    #[serde(default = "default_anthropic_base_url")]
+    pub base_url: String,
+}
+
+fn default_anthropic_base_url() -> String {
+    crate::clients::anthropic::ANTHROPIC_API_BASE_URL.to_string()
+}
+
+impl Default for AnthropicConfig {
+    fn default() -> Self {
+        Self {
+            base_url: default_anthropic_base_url(),
+        }
+    }
+}
+
+/// Readiness check settings.
+///
+/// When `check_upstream` is enabled, `/readyz` also verifies that the
+/// configured Anthropic base URL is reachable.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct HealthConfig {
+    #[serde(default)]
+    pub check_upstream: bool,
+    #[serde(default = "default_upstream_timeout_ms")]
+    pub upstream_timeout_ms: u64,
+}
+
+fn default_upstream_timeout_ms() -> u64 {
+    2000
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            check_upstream: false,
+            upstream_timeout_ms: default_upstream_timeout_ms(),
+        }
+    }
+}
+
+/// OpenTelemetry trace export.
+///
+/// Spans are exported over OTLP/HTTP when `otlp_endpoint` is set, e.g.
+/// `http://localhost:4318/v1/traces` for a local collector.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: None,
+            service_name: "deepclaude".to_string(),
+        }
+    }
+}
+
+/// Recording and replay of upstream Anthropic exchanges.
+///
+/// In `record` mode every upstream request and raw response is written to
+/// `dir`; in `replay` mode responses are served from `dir` instead of the
+/// network.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CassetteConfig {
+    #[serde(default)]
+    pub mode: CassetteMode,
+    #[serde(default = "default_cassette_dir")]
+    pub dir: String,
+}
+
+/// Whether upstream exchanges are recorded, replayed, or neither.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CassetteMode {
+    #[default]
+    Off,
+    Record,
+    Replay,
+}
+
+fn default_cassette_dir() -> String {
+    "cassettes".to_string()
+}
+
+impl Default for CassetteConfig {
+    fn default() -> Self {
+        Self {
+            mode: CassetteMode::Off,
+            dir: default_cassette_dir(),
+        }
+    }
 }
 
 /// Pricing configuration for all supported AI models.
@@ -71,29 +530,183 @@ pub struct ModelPricing {
     pub cache_read_price: f64,        // per million tokens
 }
 
+impl AnthropicPricing {
+    /// Selects the pricing table for a Claude model name.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The model identifier, e.g. `claude-3-5-haiku-20241022`
+    ///
+    /// # Returns
+    ///
+    /// The matching `ModelPricing`, defaulting to Sonnet pricing for
+    /// unrecognised models
+    pub fn for_model(&self, model: &str) -> &ModelPricing {
+        if model.contains("claude-3-5-sonnet") {
+            &self.claude_3_sonnet
+        } else if model.contains("claude-3-5-haiku") {
+            &self.claude_3_haiku
+        } else if model.contains("claude-3-opus") {
+            &self.claude_3_opus
+        } else {
+            &self.claude_3_sonnet // default to sonnet pricing
+        }
+    }
+}
+
 impl Config {
-    /// Loads configuration from the default config file.
+    /// Loads the effective configuration.
+    ///
+    /// Settings are layered, with later layers taking precedence:
+    /// 1. Built-in defaults
+    /// 2. The config file, if present
+    /// 3. `DEEPCLAUDE_*` environment variables, using `__` to separate
+    ///    nested keys (e.g. `DEEPCLAUDE_SERVER__PORT=8080`). The shorthands
+    ///    `DEEPCLAUDE_HOST` and `DEEPCLAUDE_PORT` set the server address.
+    ///
+    /// # Arguments
     ///
-    /// Attempts to load and parse the configuration from 'config.toml'.
-    /// Falls back to default values if the file cannot be loaded or parsed.
+    /// * `path` - Explicit config file path. When `None`, `config.toml` in
+    ///   the working directory is used if it exists.
     ///
     /// # Returns
     ///
-    /// * `anyhow::Result<Self>` - The loaded configuration or an error if loading fails
+    /// * `anyhow::Result<Self>` - The loaded and validated configuration
     ///
     /// # Errors
     ///
     /// Returns an error if:
-    /// - The config file cannot be read
-    /// - The TOML content cannot be parsed
-    /// - The parsed content doesn't match the expected structure
-    pub fn load() -> anyhow::Result<Self> {
-        let config_path = Path::new("config.toml");
+    /// - An explicitly given config file does not exist
+    /// - A config file exists but cannot be read or parsed
+    /// - The merged settings don't match the expected structure
+    /// - The merged settings fail validation
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        let (path, required) = match path {
+            Some(path) => (path, true),
+            None => (Path::new(DEFAULT_CONFIG_PATH), false),
+        };
+        if required && !path.exists() {
+            anyhow::bail!("Config file {} does not exist", path.display());
+        }
+
         let config = config::Config::builder()
-            .add_source(config::File::from(config_path))
-            .build()?;
+            .add_source(config::Config::try_from(&Config::default())?)
+            .add_source(config::File::from(path).required(required))
+            .add_source(
+                config::Environment::with_prefix("DEEPCLAUDE")
+                    .prefix_separator("_")
+                    .separator("__")
+                    .try_parsing(true),
+            )
+            .set_override_option("server.host", std::env::var("DEEPCLAUDE_HOST").ok())?
+            .set_override_option("server.port", std::env::var("DEEPCLAUDE_PORT").ok())?
+            .build()
+            .map_err(|e| anyhow::anyhow!("Failed to load {}: {}", path.display(), e))?;
+
+        let config: Self = config
+            .try_deserialize()
+            .map_err(|e| anyhow::anyhow!("Invalid configuration in {}: {}", path.display(), e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks that settings are consistent and usable.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error describing the first invalid setting found
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.server.socket_addr()?;
+
+        if !(self.budgets.soft_limit_ratio > 0.0 && self.budgets.soft_limit_ratio <= 1.0) {
+            anyhow::bail!("budgets.soft_limit_ratio must be in (0, 1]");
+        }
+        let budget_limits = self
+            .budgets
+            .default_key
+            .iter()
+            .chain(self.budgets.keys.values())
+            .chain(self.budgets.projects.values());
+        for limit in budget_limits {
+            if limit.daily_usd.is_some_and(|v| v < 0.0) || limit.monthly_usd.is_some_and(|v| v < 0.0) {
+                anyhow::bail!("Budget limits must not be negative");
+            }
+        }
+
+        if self.context.context_window == 0 || self.context.summary_max_tokens == 0 {
+            anyhow::bail!("context.context_window and context.summary_max_tokens must be positive");
+        }
+        if self.response_cache.capacity == 0 || self.response_cache.ttl_secs == 0 {
+            anyhow::bail!("response_cache.capacity and response_cache.ttl_secs must be positive");
+        }
+
+        for (model, chain) in &self.fallbacks.chains {
+            for step in chain {
+                if step.model.as_deref().is_some_and(|m| m.trim().is_empty()) {
+                    anyhow::bail!("fallbacks.chains.{} has a step with an empty model", model);
+                }
+                if step.model.as_deref().is_none_or(|m| m == model) && step.thinking {
+                    anyhow::bail!("fallbacks.chains.{} has a step that changes neither the model nor thinking", model);
+                }
+            }
+        }
+
+        if self.context.keep_recent_messages == 0 {
+            anyhow::bail!("context.keep_recent_messages must be at least 1");
+        }
+
+        if !self.anthropic.base_url.starts_with("http://")
+            && !self.anthropic.base_url.starts_with("https://")
+        {
+            anyhow::bail!("anthropic.base_url must be an http:// or https:// URL");
+        }
+        if self.telemetry.otlp_endpoint.as_deref().is_some_and(|endpoint| {
+            !endpoint.starts_with("http://") && !endpoint.starts_with("https://")
+        }) {
+            anyhow::bail!("telemetry.otlp_endpoint must be an http:// or https:// URL");
+        }
+
+        let cors = &self.server.cors;
+        if cors.allow_credentials && cors.allowed_origins.iter().any(|origin| origin == "*") {
+            anyhow::bail!("server.cors.allow_credentials cannot be used with allowed_origins = [\"*\"]");
+        }
+        for origin in cors.allowed_origins.iter().filter(|origin| *origin != "*") {
+            if !origin.starts_with("http://") && !origin.starts_with("https://") {
+                anyhow::bail!("server.cors.allowed_origins entry {} must be an http:// or https:// origin", origin);
+            }
+        }
+        for method in &cors.allowed_methods {
+            axum::http::Method::from_bytes(method.as_bytes())
+                .map_err(|_| anyhow::anyhow!("Invalid method {} in server.cors.allowed_methods", method))?;
+        }
+        for header in &cors.allowed_headers {
+            axum::http::HeaderName::from_bytes(header.as_bytes())
+                .map_err(|_| anyhow::anyhow!("Invalid header {} in server.cors.allowed_headers", header))?;
+        }
 
-        Ok(config.try_deserialize()?)
+        Ok(())
+    }
+
+    /// Returns a copy of this configuration with secrets replaced by a mask.
+    pub fn masked(&self) -> Self {
+        let mut config = self.clone();
+        if config.admin.token.is_some() {
+            config.admin.token = Some(SECRET_MASK.to_string());
+        }
+        config
+    }
+}
+
+impl ServerConfig {
+    /// Parses the configured host and port into a socket address.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the host is not a valid IP address
+    pub fn socket_addr(&self) -> anyhow::Result<SocketAddr> {
+        format!("{}:{}", self.host, self.port)
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid server address {}:{}: {}", self.host, self.port, e))
     }
 }
 
@@ -106,8 +719,12 @@ impl Default for Config {
         Self {
             server: ServerConfig {
                 host: "127.0.0.1".to_string(),
-                port: 3000,
+                port: 1337,
+                shutdown_timeout_secs: default_shutdown_timeout_secs(),
+                tls: None,
+                cors: CorsConfig::default(),
             },
+            logging: LoggingConfig::default(),
             pricing: PricingConfig {
                 deepseek: DeepSeekPricing {
                     input_cache_hit_price: 0.14,
@@ -135,6 +752,19 @@ impl Default for Config {
                     },
                 },
             },
+            limits: LimitsConfig::default(),
+            budgets: BudgetsConfig::default(),
+            ledger: LedgerConfig::default(),
+            conversations: ConversationsConfig::default(),
+            context: ContextConfig::default(),
+            prompt_cache: PromptCacheConfig::default(),
+            response_cache: ResponseCacheConfig::default(),
+            fallbacks: FallbackConfig::default(),
+            admin: AdminConfig::default(),
+            anthropic: AnthropicConfig::default(),
+            cassettes: CassetteConfig::default(),
+            health: HealthConfig::default(),
+            telemetry: TelemetryConfig::default(),
         }
     }
 }
@@ -0,0 +1,348 @@
+//! Context window management.
+//!
+//! Long conversations eventually outgrow the model's context window. When a
+//! request's estimated input would not leave room for its `max_tokens`, the
+//! strategies configured under `[context]` are applied in order until it
+//! fits: stripping thinking from older assistant turns, dropping the oldest
+//! turns, or replacing older turns with a summary written by a cheaper
+//! model.
+//!
+//! Only the request sent upstream is changed; stored conversations keep
+//! their full history. What was done is reported in the response.
+
+use crate::{
+    clients::anthropic::{default_max_tokens, DEFAULT_MODEL},
+    config::ContextStrategy,
+    error::{ApiError, Result},
+    handlers::{AppState, Caller},
+    models::{
+        AnthropicUsage, ApiConfig, ApiRequest, ContextManagement, Message, MessageContent, Role,
+    },
+    pricing::{calculate_anthropic_cost, format_cost},
+};
+use std::collections::HashMap;
+
+/// Instructions given to the summary model.
+const SUMMARY_PROMPT: &str = "Summarize the conversation below so that it can be continued \
+without it. Keep facts, decisions, open questions and anything the user asked to be \
+remembered. Reply with the summary only.";
+
+/// Shrinks a request to fit the context window, if it needs to.
+///
+/// # Arguments
+///
+/// * `state` - Application state, used to call and charge for the summary model
+/// * `caller` - The admitted caller, charged for any summary
+/// * `token` - The caller's Anthropic API token
+/// * `request` - The request to shrink, after any stored conversation has
+///   been expanded into it
+///
+/// # Returns
+///
+/// * `Option<ContextManagement>` - What was done to the request, or `None`
+///   if it already fit or no strategy could be applied
+pub(crate) async fn fit(
+    state: &AppState,
+    caller: &Caller,
+    token: &str,
+    request: &mut ApiRequest,
+) -> Option<ContextManagement> {
+    let settings = &caller.config.context;
+    // Requests with two system prompts are rejected later, unchanged
+    if settings.strategies.is_empty() || !request.validate_system_prompt() {
+        return None;
+    }
+
+    let model = request.model().unwrap_or(DEFAULT_MODEL);
+    let max_tokens = request
+        .max_tokens()
+        .unwrap_or_else(|| default_max_tokens(model));
+    let target = settings.context_window.saturating_sub(max_tokens);
+    let before = request.approximate_input_tokens();
+    if before <= target {
+        return None;
+    }
+
+    // A system message is kept out of the way of the strategies
+    if let Some(index) = request
+        .messages
+        .iter()
+        .position(|msg| msg.role == Role::System)
+    {
+        let system = request.messages.remove(index);
+        request.system = Some(system.content);
+    }
+    let messages_before = request.messages.len();
+
+    let mut applied = Vec::new();
+    let mut summary_cost = None;
+    for strategy in &settings.strategies {
+        if request.approximate_input_tokens() <= target {
+            break;
+        }
+        let changed = match strategy {
+            ContextStrategy::StripThinking => strip_thinking(&mut request.messages),
+            ContextStrategy::DropOldest => {
+                drop_oldest(request, target, settings.keep_recent_messages)
+            }
+            ContextStrategy::Summarize => {
+                match summarize(state, caller, token, request).await {
+                    Ok(Some(cost)) => {
+                        summary_cost = Some(summary_cost.unwrap_or(0.0) + cost);
+                        true
+                    }
+                    Ok(None) => false,
+                    Err(e) => {
+                        tracing::warn!("Failed to summarize earlier messages: {}", e);
+                        false
+                    }
+                }
+            }
+        };
+        if changed {
+            applied.push(strategy.name().to_string());
+        }
+    }
+
+    let after = request.approximate_input_tokens();
+    if after > target {
+        tracing::warn!(
+            estimated_tokens = after,
+            target_tokens = target,
+            "Request still exceeds the context window"
+        );
+    }
+    if applied.is_empty() {
+        return None;
+    }
+
+    tracing::info!(
+        strategies = %applied.join(","),
+        estimated_tokens_before = before,
+        estimated_tokens_after = after,
+        "Shrunk request to fit the context window"
+    );
+    Some(ContextManagement {
+        applied,
+        estimated_tokens_before: before,
+        estimated_tokens_after: after,
+        tokens_saved: before.saturating_sub(after),
+        messages_removed: messages_before - request.messages.len(),
+        summary_cost: summary_cost.map(format_cost),
+    })
+}
+
+/// Removes thinking blocks from every assistant message but the latest,
+/// which Anthropic may still need to verify.
+///
+/// Messages with nothing but thinking are left as they are.
+///
+/// # Returns
+///
+/// * `bool` - Whether any block was removed
+fn strip_thinking(messages: &mut [Message]) -> bool {
+    let Some(latest) = messages.iter().rposition(|msg| msg.role == Role::Assistant) else {
+        return false;
+    };
+
+    let mut changed = false;
+    for msg in messages[..latest]
+        .iter_mut()
+        .filter(|msg| msg.role == Role::Assistant)
+    {
+        let MessageContent::Blocks(blocks) = &mut msg.content else {
+            continue;
+        };
+        let is_thinking =
+            |content_type: &str| matches!(content_type, "thinking" | "redacted_thinking");
+        if blocks.iter().all(|block| is_thinking(&block.content_type))
+            || !blocks.iter().any(|block| is_thinking(&block.content_type))
+        {
+            continue;
+        }
+        blocks.retain(|block| !is_thinking(&block.content_type));
+        changed = true;
+    }
+    changed
+}
+
+/// Drops the oldest messages until the request fits, keeping at least
+/// `keep` messages and starting the rest with a user message.
+///
+/// # Returns
+///
+/// * `bool` - Whether any message was dropped
+fn drop_oldest(request: &mut ApiRequest, target: u32, keep: usize) -> bool {
+    let cuts = cut_points(&request.messages, keep);
+    let Some(&last) = cuts.last() else {
+        return false;
+    };
+
+    // The earliest cut point leaving few enough bytes, or else the latest
+    let system_len = request
+        .system
+        .as_ref()
+        .map_or(0, MessageContent::text_len);
+    let total: usize = request
+        .messages
+        .iter()
+        .map(|msg| msg.content.text_len())
+        .sum();
+    let (mut dropped, mut start) = (0, 0);
+    let cut = cuts
+        .iter()
+        .copied()
+        .find(|&cut| {
+            dropped += request.messages[start..cut]
+                .iter()
+                .map(|msg| msg.content.text_len())
+                .sum::<usize>();
+            start = cut;
+            ((system_len + total - dropped) as u32).div_ceil(4) <= target
+        })
+        .unwrap_or(last);
+
+    request.messages.drain(..cut);
+    true
+}
+
+/// Replaces the messages before the most recent `keep_recent_messages`
+/// with a summary in the system prompt.
+///
+/// The summary is charged to the caller like any other request: its
+/// worst-case cost is checked against the caller's budgets and reserved
+/// before it is sent.
+///
+/// # Returns
+///
+/// * `Result<Option<f64>>` - The cost of the summary, or `None` if there
+///   were no older messages to summarize
+///
+/// # Errors
+///
+/// Returns `ApiError::BudgetExceeded` if the summary could exceed a
+/// budget, any error from the summary request, and
+/// `ApiError::AnthropicError` if the summary is empty
+async fn summarize(
+    state: &AppState,
+    caller: &Caller,
+    token: &str,
+    request: &mut ApiRequest,
+) -> Result<Option<f64>> {
+    let config = &caller.config;
+    let settings = &config.context;
+    let Some(&cut) = cut_points(&request.messages, settings.keep_recent_messages).last() else {
+        return Ok(None);
+    };
+
+    let transcript = request.messages[..cut]
+        .iter()
+        .map(|msg| {
+            let speaker = match msg.role {
+                Role::Assistant => "Assistant",
+                _ => "User",
+            };
+            format!("{}: {}", speaker, msg.content.text())
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let worst_case = calculate_anthropic_cost(
+        &settings.summary_model,
+        ((SUMMARY_PROMPT.len() + transcript.len()) as u32).div_ceil(4),
+        settings.summary_max_tokens,
+        0,
+        0,
+        config,
+    );
+    let (_, reservation) = state.budgets.check(
+        &caller.key_id,
+        caller.project.as_deref(),
+        worst_case,
+        &config.budgets,
+    )?;
+
+    let summary_config = ApiConfig {
+        headers: HashMap::new(),
+        body: serde_json::json!({
+            "model": settings.summary_model,
+            "max_tokens": settings.summary_max_tokens,
+        }),
+    };
+
+    let response = state
+        .anthropic_client(config, token.to_string())
+        .chat(
+            vec![Message {
+                role: Role::User,
+                content: transcript.into(),
+            }],
+            Some(SUMMARY_PROMPT.into()),
+            &summary_config,
+        )
+        .await?
+        .body;
+
+    let usage = &response.usage;
+    state.rate_limiter.record_tokens(
+        &caller.key_id,
+        &config.limits,
+        usage.input_tokens + usage.output_tokens,
+    );
+    let cost = calculate_anthropic_cost(
+        &response.model,
+        usage.input_tokens,
+        usage.output_tokens,
+        usage.cache_creation_input_tokens,
+        usage.cache_read_input_tokens,
+        config,
+    );
+    state.budgets.settle(reservation, cost);
+    let summary_usage = AnthropicUsage {
+        total_cost: format_cost(cost),
+        ..AnthropicUsage::from_anthropic(usage.clone())
+    };
+    state.ledger.append(&caller.usage_record(
+        &response.model,
+        false,
+        &summary_usage,
+        cost,
+        response.stop_reason.clone(),
+    ));
+    state.metrics.model_reported(&response.model);
+    state
+        .metrics
+        .record_usage(&response.model, &summary_usage, 0, cost);
+
+    let summary: String = response
+        .content
+        .iter()
+        .filter(|block| block.content_type == "text")
+        .map(|block| block.text.as_str())
+        .collect();
+    if summary.trim().is_empty() {
+        return Err(ApiError::AnthropicError {
+            message: "The summary model returned no text".to_string(),
+            type_: "empty_summary".to_string(),
+            param: None,
+            code: None,
+            rate_limits: None,
+        });
+    }
+
+    let summary = format!("Summary of the earlier conversation:\n{}", summary.trim());
+    request
+        .system
+        .get_or_insert_with(|| String::new().into())
+        .push_text(&summary);
+    request.messages.drain(..cut);
+
+    Ok(Some(cost))
+}
+
+/// Returns the indices the messages may be cut at, in order: those of user
+/// messages that leave at least `keep` messages.
+fn cut_points(messages: &[Message], keep: usize) -> Vec<usize> {
+    (1..messages.len().saturating_sub(keep.saturating_sub(1)))
+        .filter(|&index| messages[index].role == Role::User)
+        .collect()
+}
@@ -0,0 +1,303 @@
+//! Server-side conversations.
+//!
+//! When `[conversations] path` is set, conversations are stored in an
+//! embedded database and a chat request naming a `conversation_id` only
+//! needs to carry its new turn: the stored history is sent with it, and the
+//! turn and the reply, including its signed thinking blocks, are appended
+//! once the reply completes.
+//!
+//! Conversations belong to the caller key that created them. Every lookup
+//! is keyed by the caller's key id as well as the conversation id, so one
+//! key can never see or change another key's conversations.
+
+use crate::{
+    config::ConversationsConfig,
+    error::{ApiError, Result},
+    models::{
+        ApiRequest, Conversation, ConversationSummary, CreateConversationRequest, Message, Role,
+    },
+};
+use chrono::Utc;
+use redb::{Database, ReadableTable, TableDefinition};
+use std::path::Path;
+
+/// Conversations as JSON, keyed by caller key id and conversation id.
+const CONVERSATIONS: TableDefinition<(&str, &str), &[u8]> = TableDefinition::new("conversations");
+
+/// Store of server-side conversations.
+///
+/// When no path is configured every operation fails with
+/// `ApiError::NotFound`.
+pub struct ConversationStore {
+    db: Option<Database>,
+}
+
+/// The new turn of a request continuing a stored conversation, saved with
+/// the reply once it completes.
+#[derive(Debug, Clone)]
+pub struct ConversationTurn {
+    pub conversation_id: String,
+    pub messages: Vec<Message>,
+}
+
+impl ConversationStore {
+    /// Opens the configured database, creating it if needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Conversation settings
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database cannot be created or opened, e.g.
+    /// because another process holds it
+    pub fn open(config: &ConversationsConfig) -> anyhow::Result<Self> {
+        let Some(path) = &config.path else {
+            return Ok(Self { db: None });
+        };
+
+        if let Some(parent) = Path::new(path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let db = Database::create(path)
+            .map_err(|e| anyhow::anyhow!("Failed to open conversation store {}: {}", path, e))?;
+
+        // Create the table so that reads never find it missing
+        let txn = db.begin_write()?;
+        txn.open_table(CONVERSATIONS)?;
+        txn.commit()?;
+
+        Ok(Self { db: Some(db) })
+    }
+
+    /// Creates a conversation.
+    ///
+    /// # Arguments
+    ///
+    /// * `key_id` - The caller that owns the conversation
+    /// * `request` - Title, system prompt and initial messages
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Conversation>` - The stored conversation, with its new id
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::NotFound` if conversations are disabled and
+    /// `ApiError::Internal` if the conversation cannot be stored
+    pub fn create(&self, key_id: &str, request: CreateConversationRequest) -> Result<Conversation> {
+        let db = self.db()?;
+        let now = Utc::now();
+        let conversation = Conversation {
+            id: format!("conv_{}", uuid::Uuid::new_v4().simple()),
+            created: now,
+            updated: now,
+            title: request.title,
+            system: request.system,
+            messages: request.messages,
+        };
+
+        let txn = db.begin_write().map_err(storage_error)?;
+        {
+            let mut table = txn.open_table(CONVERSATIONS).map_err(storage_error)?;
+            table
+                .insert(
+                    (key_id, conversation.id.as_str()),
+                    encode(&conversation)?.as_slice(),
+                )
+                .map_err(storage_error)?;
+        }
+        txn.commit().map_err(storage_error)?;
+
+        Ok(conversation)
+    }
+
+    /// Lists a caller's conversations, most recently updated first.
+    ///
+    /// # Arguments
+    ///
+    /// * `key_id` - The caller whose conversations to list
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::NotFound` if conversations are disabled and
+    /// `ApiError::Internal` if the store cannot be read
+    pub fn list(&self, key_id: &str) -> Result<Vec<ConversationSummary>> {
+        let txn = self.db()?.begin_read().map_err(storage_error)?;
+        let table = txn.open_table(CONVERSATIONS).map_err(storage_error)?;
+
+        let mut summaries = Vec::new();
+        for entry in table.range((key_id, "")..).map_err(storage_error)? {
+            let (key, value) = entry.map_err(storage_error)?;
+            if key.value().0 != key_id {
+                break;
+            }
+            summaries.push(decode(value.value())?.summary());
+        }
+        summaries.sort_by_key(|summary| std::cmp::Reverse(summary.updated));
+
+        Ok(summaries)
+    }
+
+    /// Returns one of a caller's conversations.
+    ///
+    /// # Arguments
+    ///
+    /// * `key_id` - The caller that owns the conversation
+    /// * `id` - The conversation id
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::NotFound` if conversations are disabled or the
+    /// caller has no conversation with this id, and `ApiError::Internal` if
+    /// the store cannot be read
+    pub fn get(&self, key_id: &str, id: &str) -> Result<Conversation> {
+        let txn = self.db()?.begin_read().map_err(storage_error)?;
+        let table = txn.open_table(CONVERSATIONS).map_err(storage_error)?;
+        match table.get((key_id, id)).map_err(storage_error)? {
+            Some(value) => decode(value.value()),
+            None => Err(not_found(id)),
+        }
+    }
+
+    /// Deletes one of a caller's conversations.
+    ///
+    /// # Arguments
+    ///
+    /// * `key_id` - The caller that owns the conversation
+    /// * `id` - The conversation id
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::NotFound` if conversations are disabled or the
+    /// caller has no conversation with this id, and `ApiError::Internal` if
+    /// the store cannot be written
+    pub fn delete(&self, key_id: &str, id: &str) -> Result<()> {
+        let txn = self.db()?.begin_write().map_err(storage_error)?;
+        let removed = {
+            let mut table = txn.open_table(CONVERSATIONS).map_err(storage_error)?;
+            let removed = table.remove((key_id, id)).map_err(storage_error)?;
+            removed.is_some()
+        };
+        txn.commit().map_err(storage_error)?;
+
+        if removed {
+            Ok(())
+        } else {
+            Err(not_found(id))
+        }
+    }
+
+    /// Appends messages to one of a caller's conversations.
+    ///
+    /// The read and write happen in one transaction, so concurrent
+    /// requests on the same conversation never lose each other's messages.
+    ///
+    /// # Arguments
+    ///
+    /// * `key_id` - The caller that owns the conversation
+    /// * `id` - The conversation id
+    /// * `messages` - The messages to append
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::NotFound` if the conversation no longer exists,
+    /// and `ApiError::Internal` if the store cannot be written
+    pub fn append(&self, key_id: &str, id: &str, messages: Vec<Message>) -> Result<()> {
+        let txn = self.db()?.begin_write().map_err(storage_error)?;
+        {
+            let mut table = txn.open_table(CONVERSATIONS).map_err(storage_error)?;
+            let mut conversation = match table.get((key_id, id)).map_err(storage_error)? {
+                Some(value) => decode(value.value())?,
+                None => return Err(not_found(id)),
+            };
+            conversation.messages.extend(messages);
+            conversation.updated = Utc::now();
+            table
+                .insert((key_id, id), encode(&conversation)?.as_slice())
+                .map_err(storage_error)?;
+        }
+        txn.commit().map_err(storage_error)?;
+
+        Ok(())
+    }
+
+    /// Expands a request continuing a stored conversation into a complete
+    /// request.
+    ///
+    /// The stored messages are placed before the request's messages, and
+    /// the stored system prompt is used unless the request sets its own.
+    ///
+    /// # Arguments
+    ///
+    /// * `key_id` - The caller making the request
+    /// * `request` - The request, holding only the new turn
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<ConversationTurn>>` - The new turn to save with the
+    ///   reply, or `None` if the request does not name a conversation
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::BadRequest` if the request has no new user message,
+    /// and any error from [`ConversationStore::get`]
+    pub fn expand(
+        &self,
+        key_id: &str,
+        request: &mut ApiRequest,
+    ) -> Result<Option<ConversationTurn>> {
+        let Some(id) = request.conversation_id.clone() else {
+            return Ok(None);
+        };
+        if !request.messages.iter().any(|msg| msg.role == Role::User) {
+            return Err(ApiError::BadRequest {
+                message: "A request continuing a conversation must contain a new user message"
+                    .to_string(),
+            });
+        }
+
+        let conversation = self.get(key_id, &id)?;
+        let turn = std::mem::replace(&mut request.messages, conversation.messages);
+        request.messages.extend(turn.iter().cloned());
+        if request.system.is_none() {
+            request.system = conversation.system.map(Into::into);
+        }
+
+        Ok(Some(ConversationTurn {
+            conversation_id: id,
+            messages: turn,
+        }))
+    }
+
+    fn db(&self) -> Result<&Database> {
+        self.db.as_ref().ok_or_else(|| ApiError::NotFound {
+            message: "Conversations are disabled; set conversations.path to enable them"
+                .to_string(),
+        })
+    }
+}
+
+fn not_found(id: &str) -> ApiError {
+    ApiError::NotFound {
+        message: format!("Conversation {} not found", id),
+    }
+}
+
+fn encode(conversation: &Conversation) -> Result<Vec<u8>> {
+    serde_json::to_vec(conversation).map_err(|e| ApiError::Internal {
+        message: format!("Failed to encode conversation: {}", e),
+    })
+}
+
+fn decode(bytes: &[u8]) -> Result<Conversation> {
+    serde_json::from_slice(bytes).map_err(|e| ApiError::Internal {
+        message: format!("Failed to decode stored conversation: {}", e),
+    })
+}
+
+fn storage_error(e: impl Into<redb::Error>) -> ApiError {
+    ApiError::Internal {
+        message: format!("Conversation store error: {}", e.into()),
+    }
+}
@@ -6,8 +6,9 @@
 //! - Response formatting for API errors
 //! - Type aliases for common Result types
 
+use crate::{clients::rate_limits::AnthropicRateLimits, rate_limit::RateLimitStatus};
 use axum::{
-    http::StatusCode,
+    http::{header::RETRY_AFTER, HeaderValue, StatusCode},
     response::{IntoResponse, Response, sse::Event},
     Json,
 };
@@ -60,6 +61,16 @@ pub enum ApiError {
         header: String,
     },
 
+    #[error("Unauthorized: {message}")]
+    Unauthorized {
+        message: String,
+    },
+
+    #[error("Not found: {message}")]
+    NotFound {
+        message: String,
+    },
+
     #[error("Invalid system prompt configuration")]
     InvalidSystemPrompt,
 
@@ -69,6 +80,20 @@ pub enum ApiError {
         type_: String,
         param: Option<String>,
         code: Option<String>,
+        /// Rate limits reported with an unsuccessful upstream response
+        rate_limits: Option<Box<AnthropicRateLimits>>,
+    },
+
+    #[error("Rate limit exceeded: {message}")]
+    RateLimited {
+        message: String,
+        retry_after: u64,
+        status: RateLimitStatus,
+    },
+
+    #[error("Budget exceeded: {message}")]
+    BudgetExceeded {
+        message: String,
     },
 
     #[error("Internal server error: {message}")]
@@ -111,6 +136,28 @@ impl IntoResponse for ApiError {
                     },
                 },
             ),
+            ApiError::Unauthorized { message } => (
+                StatusCode::UNAUTHORIZED,
+                ErrorResponse {
+                    error: ErrorDetails {
+                        message: message.clone(),
+                        type_: "unauthorized".to_string(),
+                        param: None,
+                        code: None,
+                    },
+                },
+            ),
+            ApiError::NotFound { message } => (
+                StatusCode::NOT_FOUND,
+                ErrorResponse {
+                    error: ErrorDetails {
+                        message: message.clone(),
+                        type_: "not_found".to_string(),
+                        param: None,
+                        code: None,
+                    },
+                },
+            ),
             ApiError::InvalidSystemPrompt => (
                 StatusCode::BAD_REQUEST,
                 ErrorResponse {
@@ -122,8 +169,8 @@ impl IntoResponse for ApiError {
                     },
                 },
             ),
-            ApiError::AnthropicError { message, type_, param, code } => (
-                StatusCode::BAD_REQUEST,
+            ApiError::AnthropicError { message, type_, param, code, .. } => (
+                upstream_status(code.as_deref()),
                 ErrorResponse {
                     error: ErrorDetails {
                         message: format!("Anthropic API Error: {}", message),
@@ -133,6 +180,28 @@ impl IntoResponse for ApiError {
                     },
                 },
             ),
+            ApiError::RateLimited { message, .. } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                ErrorResponse {
+                    error: ErrorDetails {
+                        message: message.clone(),
+                        type_: "rate_limit_exceeded".to_string(),
+                        param: None,
+                        code: None,
+                    },
+                },
+            ),
+            ApiError::BudgetExceeded { message } => (
+                StatusCode::PAYMENT_REQUIRED,
+                ErrorResponse {
+                    error: ErrorDetails {
+                        message: message.clone(),
+                        type_: "budget_exceeded".to_string(),
+                        param: None,
+                        code: None,
+                    },
+                },
+            ),
             ApiError::Internal { message } => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 ErrorResponse {
@@ -157,10 +226,36 @@ impl IntoResponse for ApiError {
             ),
         };
 
-        (status, Json(error_response)).into_response()
+        let mut response = (status, Json(error_response)).into_response();
+
+        if let ApiError::RateLimited { retry_after, status, .. } = &self {
+            let headers = response.headers_mut();
+            headers.insert(RETRY_AFTER, HeaderValue::from(*retry_after));
+            status.apply_headers(headers);
+        }
+
+        // Anthropic's rate limits are passed on with its errors, as they
+        // are with its successful responses
+        if let ApiError::AnthropicError { rate_limits: Some(rate_limits), .. } = &self {
+            rate_limits.apply_headers(response.headers_mut());
+        }
+
+        response
     }
 }
 
+/// Returns the status for an Anthropic error with the given upstream status
+/// code.
+///
+/// Rate limits and server errors keep Anthropic's status, so that clients
+/// retry them; other errors are reported as bad requests.
+fn upstream_status(code: Option<&str>) -> StatusCode {
+    code.and_then(|code| code.parse::<u16>().ok())
+        .and_then(|code| StatusCode::from_u16(code).ok())
+        .filter(|status| *status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error())
+        .unwrap_or(StatusCode::BAD_REQUEST)
+}
+
 /// Converts generic errors into API errors.
 ///
 /// This implementation allows using the `?` operator with functions that
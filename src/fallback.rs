@@ -0,0 +1,225 @@
+//! Model fallback chains.
+//!
+//! When Anthropic is overloaded or failing, a request can often still be
+//! answered by asking for less: the same model without extended thinking,
+//! or a smaller model. The steps configured under `[fallbacks.chains]` for
+//! the requested model are tried in order until one succeeds.
+//!
+//! Streams fall back only until their first event has arrived, so a caller
+//! never receives part of an answer from a model that then failed.
+
+use crate::{
+    clients::anthropic::{
+        default_max_tokens, max_output_tokens, EventStream, UpstreamResponse, DEFAULT_MODEL,
+    },
+    config::{FallbackConfig, FallbackStep},
+    error::{ApiError, Result},
+    metrics::Metrics,
+    models::{ApiConfig, Fallback},
+};
+use futures::StreamExt;
+use std::future::Future;
+
+/// Smallest thinking budget Anthropic accepts.
+const MIN_THINKING_BUDGET: u32 = 1024;
+
+/// Sends a request, retrying it with the fallback chain of its model while
+/// Anthropic fails.
+///
+/// # Arguments
+///
+/// * `config` - The request's Anthropic configuration, as requested
+/// * `fallbacks` - The configured fallback chains
+/// * `metrics` - Metrics counting the errors fallen back from and the
+///   fallbacks used
+/// * `send` - Sends the request with a given configuration
+///
+/// # Returns
+///
+/// * `Result<(T, Option<Fallback>)>` - The first successful response, and
+///   how it was served if a fallback answered it
+///
+/// # Errors
+///
+/// Returns the first error that is not an upstream failure, or the error of
+/// the last attempt if every step of the chain failed
+pub(crate) async fn send<T, F, Fut>(
+    config: &ApiConfig,
+    fallbacks: &FallbackConfig,
+    metrics: &Metrics,
+    mut send: F,
+) -> Result<(T, Option<Fallback>)>
+where
+    F: FnMut(ApiConfig) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let requested_model = model(config).to_string();
+    let mut steps = fallbacks
+        .chains
+        .get(&requested_model)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter();
+
+    let mut attempt = config.clone();
+    let mut errors = Vec::new();
+    loop {
+        let e = match send(attempt.clone()).await {
+            Ok(response) if errors.is_empty() => return Ok((response, None)),
+            Ok(response) => {
+                let model = model(&attempt).to_string();
+                tracing::warn!(
+                    requested_model = %requested_model,
+                    model = %model,
+                    attempts = errors.len() + 1,
+                    "Request answered by a fallback"
+                );
+                metrics.fallback(&requested_model, &model);
+                let fallback = Fallback {
+                    requested_model,
+                    model,
+                    thinking: has_thinking(&attempt),
+                    errors,
+                };
+                return Ok((response, Some(fallback)));
+            }
+            Err(e) => e,
+        };
+
+        let Some(step) = steps.next().filter(|_| is_upstream_failure(&e)) else {
+            return Err(e);
+        };
+        metrics.upstream_error(&e);
+        errors.push(describe(&e));
+        attempt = apply(config, step);
+        tracing::warn!(
+            error = %e,
+            model = %model(&attempt),
+            thinking = has_thinking(&attempt),
+            "Anthropic failed, falling back"
+        );
+    }
+}
+
+/// Waits for the first event of a stream, so that a stream failing before
+/// sending anything can still fall back.
+///
+/// # Arguments
+///
+/// * `upstream` - The connected stream
+///
+/// # Returns
+///
+/// * `Result<UpstreamResponse<EventStream>>` - The stream, with its first
+///   event still to be read
+///
+/// # Errors
+///
+/// Returns the error the stream starts with
+pub(crate) async fn first_event(
+    mut upstream: UpstreamResponse<EventStream>,
+) -> Result<UpstreamResponse<EventStream>> {
+    match upstream.body.next().await {
+        Some(Err(e)) => Err(e),
+        Some(Ok(event)) => {
+            let first = futures::stream::once(async { Ok(event) });
+            upstream.body = Box::pin(first.chain(upstream.body));
+            Ok(upstream)
+        }
+        None => Ok(upstream),
+    }
+}
+
+/// Returns true for errors a fallback may avoid: Anthropic being
+/// overloaded (`529`) or failing (`5xx`), whether reported by status or by
+/// the error event a stream starts with.
+fn is_upstream_failure(error: &ApiError) -> bool {
+    let ApiError::AnthropicError { type_, code, .. } = error else {
+        return false;
+    };
+    match code.as_deref().and_then(|code| code.parse::<u16>().ok()) {
+        Some(status) => status >= 500,
+        None => matches!(type_.as_str(), "overloaded_error" | "api_error"),
+    }
+}
+
+/// Describes an error fallen back from, e.g. `overloaded_error (529)`.
+fn describe(error: &ApiError) -> String {
+    match error {
+        ApiError::AnthropicError {
+            type_,
+            code: Some(code),
+            ..
+        } => format!("{} ({})", type_, code),
+        ApiError::AnthropicError { type_, .. } => type_.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Applies a fallback step to the requested configuration.
+///
+/// A `max_tokens` above the fallback model's output limit is lowered to it,
+/// and a thinking budget that no longer fits below `max_tokens` is halved
+/// to fit, or thinking is turned off if that would leave less than the
+/// minimum budget.
+fn apply(config: &ApiConfig, step: &FallbackStep) -> ApiConfig {
+    let mut config = config.clone();
+    if config.body.is_null() {
+        config.body = serde_json::json!({});
+    }
+    if let serde_json::Value::Object(body) = &mut config.body {
+        if let Some(model) = &step.model {
+            body.insert("model".to_string(), serde_json::json!(model));
+        }
+        if !step.thinking {
+            body.remove("thinking");
+        }
+    }
+
+    let model = model(&config).to_string();
+    let Some(body) = config.body.as_object_mut() else {
+        return config;
+    };
+    let mut max_tokens = body
+        .get("max_tokens")
+        .and_then(serde_json::Value::as_u64)
+        .map_or_else(
+            || default_max_tokens(&model),
+            |max_tokens| u32::try_from(max_tokens).unwrap_or(u32::MAX),
+        );
+    if let Some(limit) = max_output_tokens(&model).filter(|&limit| max_tokens > limit) {
+        max_tokens = limit;
+        body.insert("max_tokens".to_string(), serde_json::json!(limit));
+    }
+
+    let budget_tokens = body
+        .get("thinking")
+        .and_then(|thinking| thinking.get("budget_tokens"))
+        .and_then(serde_json::Value::as_u64);
+    if budget_tokens.is_some_and(|budget| budget >= u64::from(max_tokens)) {
+        let budget = max_tokens / 2;
+        if budget >= MIN_THINKING_BUDGET {
+            body["thinking"]["budget_tokens"] = serde_json::json!(budget);
+        } else {
+            body.remove("thinking");
+        }
+    }
+    config
+}
+
+fn model(config: &ApiConfig) -> &str {
+    config
+        .body
+        .get("model")
+        .and_then(serde_json::Value::as_str)
+        .unwrap_or(DEFAULT_MODEL)
+}
+
+fn has_thinking(config: &ApiConfig) -> bool {
+    config
+        .body
+        .get("thinking")
+        .and_then(|thinking| thinking.get("type"))
+        .and_then(serde_json::Value::as_str)
+        == Some("enabled")
+}
@@ -6,30 +6,245 @@
 //! usage tracking and cost calculations.
 
 use crate::{
-    clients::AnthropicClient,
-    config::Config,
+    budget::{BudgetReservation, BudgetTracker, DEFAULT_THINKING_BUDGET},
+    clients::{
+        anthropic::{AnthropicResponse, UpstreamResponse},
+        AnthropicClient, AnthropicRateLimits, CassetteStore,
+    },
+    config::{CassetteMode, Config},
+    context,
+    conversations::{ConversationStore, ConversationTurn},
+    response_cache::{self, ResponseCache},
     error::{ApiError, Result, SseResponse},
+    fallback,
+    ledger::{aggregate, to_csv, GroupBy, UsageFilter, UsageLedger, UsageRecord},
+    logging,
+    metrics::{estimate_thinking_tokens, Metrics, ResponseModel},
+    pricing::{
+        calculate_anthropic_cost, estimate_worst_case_cost, format_cost, worst_case_cost,
+        worst_case_output_tokens,
+    },
     models::{
-        AnthropicUsage, ApiRequest, ApiResponse, CombinedUsage, ContentBlock,
-        ExternalApiResponse, StreamEvent,
+        AnthropicUsage, ApiRequest, ApiResponse, CombinedUsage, ContentBlock, ContextManagement,
+        Conversation, ConversationSummary, CreateConversationRequest, ExternalApiResponse, HealthResponse,
+        Message, ReadinessResponse, Role, StreamEvent, TokenCountResponse, VersionResponse,
     },
+    rate_limit::{RateLimiter, StreamPermit, UpstreamLimits},
+    shutdown::{self, Shutdown},
+    request_id::RequestId,
+    telemetry,
 };
 use axum::{
-    extract::State,
+    extract::{Extension, Path, Query, State},
+    http::{header::CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue, StatusCode},
     response::{sse::Event, IntoResponse},
     Json,
 };
+use axum_server::tls_rustls::RustlsConfig;
 use chrono::Utc;
 use futures::StreamExt;
-use std::{collections::HashMap, sync::Arc};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use serde::Deserialize;
+use std::{
+    sync::{Arc, RwLock},
+    time::Instant,
+};
 use tokio_stream::wrappers::ReceiverStream;
+use tracing::Instrument;
+
+/// Response header reporting the response cache lookup: `hit`, `miss` or
+/// `bypass`.
+const CACHE_RESULT_HEADER: HeaderName = HeaderName::from_static("x-deepclaude-cache");
 
 /// Application state shared across request handlers.
 ///
-/// Contains configuration that needs to be accessible
-/// to all request handlers.
+/// Contains configuration and shared services that need to be
+/// accessible to all request handlers.
+///
+/// The configuration can be replaced at runtime; handlers take a snapshot
+/// with [`AppState::config`] so each request runs with a single consistent
+/// configuration.
 pub struct AppState {
-    pub config: Config,
+    pub config: RwLock<Arc<Config>>,
+    pub rate_limiter: RateLimiter,
+    pub upstream_limits: Arc<UpstreamLimits>,
+    pub budgets: BudgetTracker,
+    pub ledger: UsageLedger,
+    pub conversations: ConversationStore,
+    pub response_cache: ResponseCache,
+    pub cassettes: Option<CassetteStore>,
+    pub metrics: Metrics,
+    pub shutdown: Shutdown,
+    /// TLS configuration, reloaded with the server configuration
+    pub tls: Option<RustlsConfig>,
+}
+
+impl AppState {
+    /// Creates the application state for a configuration.
+    ///
+    /// TLS is not set up; the state serves plain HTTP unless `tls` is
+    /// filled in.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The validated configuration to start with
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the budget store cannot be read, the ledger
+    /// writer cannot be started, the conversation store cannot be opened or
+    /// metrics cannot be registered
+    pub fn new(config: Config) -> anyhow::Result<Self> {
+        Ok(Self {
+            budgets: BudgetTracker::load(&config.budgets)?,
+            rate_limiter: RateLimiter::new(),
+            upstream_limits: Arc::new(UpstreamLimits::new()),
+            ledger: UsageLedger::new(config.ledger.path.clone())?,
+            conversations: ConversationStore::open(&config.conversations)?,
+            response_cache: ResponseCache::new(&config.response_cache),
+            cassettes: CassetteStore::from_config(&config.cassettes),
+            metrics: Metrics::new()?,
+            shutdown: Shutdown::new(),
+            tls: None,
+            config: RwLock::new(Arc::new(config)),
+        })
+    }
+
+    /// Returns a snapshot of the current configuration.
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Atomically replaces the configuration used by new requests.
+    ///
+    /// Requests already in flight keep the snapshot they started with.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The new, already validated configuration
+    pub fn replace_config(&self, config: Config) {
+        *self.config.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(config);
+    }
+
+    /// Creates an Anthropic client for a caller's token, applying
+    /// server-wide client settings and reporting the token's rate limits.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Configuration snapshot for the request
+    /// * `token` - The caller's Anthropic API token
+    pub(crate) fn anthropic_client(&self, config: &Config, token: String) -> AnthropicClient {
+        let key_id = caller_key_id(&token);
+        AnthropicClient::new(token)
+            .with_base_url(&config.anthropic.base_url)
+            .with_cassettes(self.cassettes.clone())
+            .with_upstream_limits(self.upstream_limits.clone(), key_id)
+    }
+}
+
+/// Caller information resolved before a request is dispatched.
+///
+/// Holds the caller's identity and any admission permits that must
+/// stay alive for the duration of the request.
+pub(crate) struct Caller {
+    pub request_id: String,
+    pub key_id: String,
+    pub project: Option<String>,
+    pub started: Instant,
+    /// Configuration snapshot the request was admitted with
+    pub config: Arc<Config>,
+    /// The new turn of a stored conversation, saved with the reply
+    pub conversation: Option<ConversationTurn>,
+    /// What was done to fit the request in the context window
+    pub context: Option<ContextManagement>,
+    /// The request's worst-case cost, held against its budgets until charged
+    pub budget: Option<BudgetReservation>,
+    /// Concurrent stream slot, released when the caller is dropped.
+    #[allow(dead_code)]
+    pub permit: Option<StreamPermit>,
+}
+
+impl Caller {
+    /// Builds the ledger record for a request made by this caller.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The model that served the request
+    /// * `stream` - Whether the request was streamed
+    /// * `usage` - Token usage reported by Anthropic
+    /// * `cost` - Cost of the request in dollars
+    /// * `stop_reason` - Why the model stopped generating, if known
+    pub(crate) fn usage_record(
+        &self,
+        model: &str,
+        stream: bool,
+        usage: &AnthropicUsage,
+        cost: f64,
+        stop_reason: Option<String>,
+    ) -> UsageRecord {
+        UsageRecord {
+            timestamp: Utc::now(),
+            key_id: self.key_id.clone(),
+            project: self.project.clone(),
+            model: model.to_string(),
+            stream,
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            cache_write_tokens: usage.cached_write_tokens,
+            cache_read_tokens: usage.cached_read_tokens,
+            cost_usd: cost,
+            latency_ms: self.started.elapsed().as_millis() as u64,
+            stop_reason,
+        }
+    }
+
+    /// Charges the actual cost of the request to the caller's budgets,
+    /// settling the reservation made when it was admitted.
+    ///
+    /// # Arguments
+    ///
+    /// * `budgets` - The budget tracker
+    /// * `cost` - Actual cost of the request in dollars
+    fn charge(&mut self, budgets: &BudgetTracker, cost: f64) {
+        match self.budget.take() {
+            Some(reservation) => budgets.settle(reservation, cost),
+            None => budgets.record(&self.key_id, self.project.as_deref(), cost),
+        }
+    }
+
+    /// Saves the request's turn and the reply to the caller's stored
+    /// conversation, if the request continued one.
+    ///
+    /// Failures are logged rather than returned, since the reply has
+    /// already been produced and paid for.
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - Application state containing the conversation store
+    /// * `reply` - Content blocks of the reply, including signed thinking
+    async fn save_turn(&self, state: &Arc<AppState>, reply: Vec<ContentBlock>) {
+        let Some(turn) = &self.conversation else {
+            return;
+        };
+
+        let mut messages = turn.messages.clone();
+        messages.push(Message {
+            role: Role::Assistant,
+            content: reply.into(),
+        });
+        let state = state.clone();
+        let key_id = self.key_id.clone();
+        let id = turn.conversation_id.clone();
+        let saved = blocking(move || state.conversations.append(&key_id, &id, messages)).await;
+        if let Err(e) = saved {
+            tracing::error!(
+                conversation_id = %turn.conversation_id,
+                "Failed to save conversation turn: {}",
+                e
+            );
+        }
+    }
 }
 
 /// Extracts API token from request headers.
@@ -61,63 +276,72 @@ fn extract_api_token(headers: &axum::http::HeaderMap) -> Result<String> {
     Ok(anthropic_token)
 }
 
-/// Calculates the cost of Anthropic API usage.
+/// Extracts the optional project name used for project budgets.
 ///
 /// # Arguments
 ///
-/// * `model` - The specific Claude model used
-/// * `input_tokens` - Number of input tokens processed
-/// * `output_tokens` - Number of output tokens generated
-/// * `cache_write_tokens` - Number of tokens written to cache
-/// * `cache_read_tokens` - Number of tokens read from cache
-/// * `config` - Configuration containing pricing information
+/// * `headers` - The HTTP request headers
 ///
 /// # Returns
 ///
-/// The total cost in dollars for the API usage
-fn calculate_anthropic_cost(
-    model: &str,
-    input_tokens: u32,
-    output_tokens: u32,
-    cache_write_tokens: u32,
-    cache_read_tokens: u32,
-    config: &Config,
-) -> f64 {
-    let pricing = if model.contains("claude-3-5-sonnet") {
-        &config.pricing.anthropic.claude_3_sonnet
-    } else if model.contains("claude-3-5-haiku") {
-        &config.pricing.anthropic.claude_3_haiku
-    } else if model.contains("claude-3-opus") {
-        &config.pricing.anthropic.claude_3_opus
-    } else {
-        &config.pricing.anthropic.claude_3_sonnet // default to sonnet pricing
-    };
-
-    let input_cost = (input_tokens as f64 / 1_000_000.0) * pricing.input_price;
-    let output_cost = (output_tokens as f64 / 1_000_000.0) * pricing.output_price;
-    let cache_write_cost = (cache_write_tokens as f64 / 1_000_000.0) * pricing.cache_write_price;
-    let cache_read_cost = (cache_read_tokens as f64 / 1_000_000.0) * pricing.cache_read_price;
+/// * `Option<String>` - The value of `X-DeepClaude-Project`, if present and valid
+fn extract_project(headers: &axum::http::HeaderMap) -> Option<String> {
+    headers
+        .get("X-DeepClaude-Project")
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(String::from)
+}
 
-    input_cost + output_cost + cache_write_cost + cache_read_cost
+/// Builds the response headers passing on details of the upstream
+/// Anthropic response.
+///
+/// # Arguments
+///
+/// * `upstream_request_id` - Anthropic's id for the upstream request, if known
+/// * `rate_limits` - The rate limits Anthropic reported, if any
+///
+/// # Returns
+///
+/// * `HeaderMap` - `X-Anthropic-Request-Id` when the upstream id is known,
+///   and the `anthropic-ratelimit-*` headers
+fn upstream_headers(
+    upstream_request_id: Option<&str>,
+    rate_limits: Option<&AnthropicRateLimits>,
+) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if let Some(value) = upstream_request_id.and_then(|id| HeaderValue::from_str(id).ok()) {
+        headers.insert(HeaderName::from_static("x-anthropic-request-id"), value);
+    }
+    if let Some(rate_limits) = rate_limits {
+        rate_limits.apply_headers(&mut headers);
+    }
+    headers
 }
 
-/// Formats a cost value as a dollar amount string.
+/// Derives a stable caller identity from an Anthropic API token.
+///
+/// The token is hashed so that the raw key never needs to be stored
+/// or logged when tracking per-caller state.
 ///
 /// # Arguments
 ///
-/// * `cost` - The cost value to format
+/// * `token` - The caller's Anthropic API token
 ///
 /// # Returns
 ///
-/// A string representing the cost with 3 decimal places and $ prefix
-fn format_cost(cost: f64) -> String {
-    format!("${:.3}", cost)
+/// The first 16 hex characters of the token's SHA-256 digest
+fn caller_key_id(token: &str) -> String {
+    let digest = Sha256::digest(token.as_bytes());
+    hex::encode(&digest[..8])
 }
 
 /// Main handler for chat requests.
 ///
-/// Routes requests to either streaming or non-streaming handlers
-/// based on the request configuration.
+/// Applies per-caller rate limits and spend budgets, then routes requests
+/// to either streaming or non-streaming handlers based on the request
+/// configuration.
 ///
 /// # Arguments
 ///
@@ -127,19 +351,95 @@ fn format_cost(cost: f64) -> String {
 ///
 /// # Returns
 ///
-/// * `Result<Response>` - The API response or an error
+/// * `Response` - The API response, or the error converted to a response,
+///   tagged with the requested model for metrics
 pub async fn handle_chat(
     state: State<Arc<AppState>>,
+    Extension(request_id): Extension<RequestId>,
     headers: axum::http::HeaderMap,
     Json(request): Json<ApiRequest>,
+) -> axum::response::Response {
+    let model = request
+        .model()
+        .unwrap_or(crate::clients::anthropic::DEFAULT_MODEL)
+        .to_string();
+    let span = telemetry::chat_span(&request);
+    let result = dispatch_chat(state, request_id, headers, request)
+        .instrument(span.clone())
+        .await;
+
+    // Upstream errors are reported by their Anthropic error type, others
+    // by the status code returned to the caller
+    let upstream_error_type = match &result {
+        Err(ApiError::AnthropicError { type_, .. }) => Some(type_.clone()),
+        _ => None,
+    };
+    let mut response = result.into_response();
+    if let Some(error_type) = upstream_error_type
+        .or_else(|| (!response.status().is_success()).then(|| response.status().as_str().to_string()))
+    {
+        telemetry::record_error(&span, &error_type);
+    }
+
+    response.extensions_mut().insert(ResponseModel(model));
+    response
+}
+
+/// Admits the caller and dispatches the request to the streaming or
+/// non-streaming handler.
+async fn dispatch_chat(
+    state: State<Arc<AppState>>,
+    request_id: RequestId,
+    headers: axum::http::HeaderMap,
+    request: ApiRequest,
 ) -> Result<axum::response::Response> {
-    if request.stream {
-        let stream_response = chat_stream(state, headers, Json(request)).await?;
-        Ok(stream_response.into_response())
-    } else {
-        let json_response = chat(state, headers, Json(request)).await?;
-        Ok(json_response.into_response())
+    let started = Instant::now();
+    let config = state.config();
+    let token = extract_api_token(&headers)?;
+    let key_id = caller_key_id(&token);
+    let project = extract_project(&headers);
+    // Continuing a stored conversation sends its whole history
+    let (conversation, mut request) = expand_conversation(&state, &key_id, request).await?;
+    let (budget_status, reservation) = state.budgets.check(
+        &key_id,
+        project.as_deref(),
+        estimate_worst_case_cost(&request, &config),
+        &config.budgets,
+    )?;
+    if config.limits.upstream_backoff {
+        state.upstream_limits.check(&key_id)?;
     }
+    let admission = state
+        .rate_limiter
+        .check(&key_id, &config.limits, request.stream)?;
+    let mut caller = Caller {
+        request_id: request_id.0,
+        key_id,
+        project,
+        started,
+        config: config.clone(),
+        conversation,
+        context: None,
+        budget: Some(reservation),
+        permit: admission.permit,
+    };
+    caller.context = context::fit(&state, &caller, &token, &mut request).await;
+    if request.auto_cache.unwrap_or(config.prompt_cache.auto) {
+        let breakpoints = request.add_cache_breakpoints();
+        tracing::debug!(breakpoints, "Placed prompt cache breakpoints");
+    }
+
+    let mut response = if request.stream {
+        let stream_response = chat_stream(state, headers, Json(request), caller).await?;
+        stream_response.into_response()
+    } else {
+        let json_response = chat(state, headers, Json(request), caller).await?;
+        json_response.into_response()
+    };
+
+    admission.status.apply_headers(response.headers_mut());
+    budget_status.apply_headers(response.headers_mut());
+    Ok(response)
 }
 
 /// Handler for non-streaming chat requests.
@@ -152,15 +452,18 @@ pub async fn handle_chat(
 /// * `state` - Application state containing configuration
 /// * `headers` - HTTP request headers
 /// * `request` - The parsed chat request
+/// * `caller` - The admitted caller
 ///
 /// # Returns
 ///
-/// * `Result<Json<ApiResponse>>` - The combined API response or an error
+/// * `Result<(HeaderMap, Json<ApiResponse>)>` - Correlation headers and the
+///   combined API response, or an error
 pub(crate) async fn chat(
     State(state): State<Arc<AppState>>,
     headers: axum::http::HeaderMap,
     Json(request): Json<ApiRequest>,
-) -> Result<Json<ApiResponse>> {
+    mut caller: Caller,
+) -> Result<(HeaderMap, Json<ApiResponse>)> {
     // Validate system prompt
     if !request.validate_system_prompt() {
         return Err(ApiError::InvalidSystemPrompt);
@@ -170,7 +473,8 @@ pub(crate) async fn chat(
     let anthropic_token = extract_api_token(&headers)?;
 
     // Initialize client
-    let anthropic_client = AnthropicClient::new(anthropic_token);
+    let config = caller.config.clone();
+    let anthropic_client = state.anthropic_client(&config, anthropic_token);
 
     // Get messages with system prompt
     let messages = request.get_messages_with_system();
@@ -182,7 +486,7 @@ pub(crate) async fn chat(
         // Add default thinking configuration if not provided
         let thinking_config = serde_json::json!({
             "type": "enabled",
-            "budget_tokens": 16000
+            "budget_tokens": DEFAULT_THINKING_BUDGET
         });
 
         if let serde_json::Value::Object(ref mut body) = anthropic_config.body {
@@ -190,21 +494,56 @@ pub(crate) async fn chat(
         }
     }
 
+    // Identical requests may be answered from the response cache
+    let cache_key = state.response_cache.is_enabled().then(|| {
+        response_cache::key(
+            &caller.key_id,
+            &anthropic_client.cache_key(
+                messages.clone(),
+                request.get_system_prompt().cloned(),
+                &anthropic_config,
+            ),
+        )
+    });
+    let cache_result = match &cache_key {
+        Some(key) => match cache_lookup(&state, &headers, key).await {
+            (Some(cached), _) => return Ok(cached_reply(&state, &caller, cached).await),
+            (None, result) => Some(result),
+        },
+        None => None,
+    };
+
     // Call Anthropic API directly with thinking enabled
     let anthropic_messages = messages;
 
-    // Call Anthropic API with thinking enabled
-    let anthropic_response = anthropic_client
-        .chat(
-            anthropic_messages,
-            request.get_system_prompt().map(String::from),
-            &anthropic_config,
-        )
-        .await?;
+    // Call Anthropic API with thinking enabled, falling back while it fails
+    let (upstream, fallback) = fallback::send(
+        &anthropic_config,
+        &config.fallbacks,
+        &state.metrics,
+        |attempt| {
+            let messages = anthropic_messages.clone();
+            let system = request.get_system_prompt().cloned();
+            let client = &anthropic_client;
+            async move { client.chat(messages, system, &attempt).await }
+        },
+    )
+    .await
+    .inspect_err(|e| state.metrics.upstream_error(e))?;
+    let upstream_request_id = upstream.request_id().map(String::from);
+    let rate_limits = upstream.rate_limits();
+    let UpstreamResponse {
+        status: anthropic_status,
+        headers: anthropic_headers,
+        timing: anthropic_timing,
+        body: anthropic_response,
+    } = upstream;
 
-    // Store response metadata
-    let anthropic_status: u16 = 200;
-    let anthropic_headers = HashMap::new(); // Headers not available when using high-level chat method
+    state.rate_limiter.record_tokens(
+        &caller.key_id,
+        &config.limits,
+        anthropic_response.usage.input_tokens + anthropic_response.usage.output_tokens,
+    );
 
     // Calculate usage costs for Anthropic only
     let anthropic_cost = calculate_anthropic_cost(
@@ -213,8 +552,14 @@ pub(crate) async fn chat(
         anthropic_response.usage.output_tokens,
         anthropic_response.usage.cache_creation_input_tokens,
         anthropic_response.usage.cache_read_input_tokens,
-        &state.config,
+        &config,
     );
+    caller.charge(&state.budgets, anthropic_cost);
+
+    // Answers from a fallback are not what was asked for, and not cached
+    if let (Some(key), None) = (&cache_key, &fallback) {
+        state.response_cache.put(key, &anthropic_response);
+    }
 
     // Use Anthropic's response blocks directly, which include thinking blocks
     let content = anthropic_response
@@ -223,6 +568,48 @@ pub(crate) async fn chat(
         .into_iter()
         .map(ContentBlock::from_anthropic)
         .collect::<Vec<_>>();
+    caller.save_turn(&state, content.clone()).await;
+
+    let anthropic_usage = AnthropicUsage {
+        total_cost: format_cost(anthropic_cost),
+        ..AnthropicUsage::from_anthropic(anthropic_response.usage.clone())
+    };
+    state.ledger.append(&caller.usage_record(
+        &anthropic_response.model,
+        false,
+        &anthropic_usage,
+        anthropic_cost,
+        anthropic_response.stop_reason.clone(),
+    ));
+    let thinking_chars = anthropic_response
+        .content
+        .iter()
+        .filter_map(|block| block.thinking.as_ref())
+        .map(String::len)
+        .sum();
+    state.metrics.model_reported(&anthropic_response.model);
+    state.metrics.record_usage(
+        &anthropic_response.model,
+        &anthropic_usage,
+        estimate_thinking_tokens(thinking_chars),
+        anthropic_cost,
+    );
+    telemetry::record_response(
+        &tracing::Span::current(),
+        &anthropic_response.model,
+        anthropic_usage.input_tokens,
+        anthropic_usage.output_tokens,
+        anthropic_response.stop_reason.as_deref(),
+        anthropic_cost,
+    );
+    tracing::info!(
+        model = %anthropic_response.model,
+        input_tokens = anthropic_usage.input_tokens,
+        output_tokens = anthropic_usage.output_tokens,
+        cost_usd = anthropic_cost,
+        latency_ms = caller.started.elapsed().as_millis() as u64,
+        "Chat completed"
+    );
 
     // Build response with only Anthropic details
     let response = ApiResponse {
@@ -231,23 +618,192 @@ pub(crate) async fn chat(
         anthropic_response: request.verbose.then(|| ExternalApiResponse {
             status: anthropic_status,
             headers: anthropic_headers,
+            timing: anthropic_timing,
             body: serde_json::to_value(&anthropic_response).unwrap_or_default(),
+            request_id: caller.request_id.clone(),
+            upstream_request_id: upstream_request_id.clone(),
         }),
         combined_usage: CombinedUsage {
             total_cost: format_cost(anthropic_cost), // Only Anthropic cost
-            anthropic_usage: AnthropicUsage {
-                input_tokens: anthropic_response.usage.input_tokens,
-                output_tokens: anthropic_response.usage.output_tokens,
-                cached_write_tokens: anthropic_response.usage.cache_creation_input_tokens,
-                cached_read_tokens: anthropic_response.usage.cache_read_input_tokens,
-                total_tokens: anthropic_response.usage.input_tokens
-                    + anthropic_response.usage.output_tokens,
-                total_cost: format_cost(anthropic_cost),
-            },
+            cost_usd: anthropic_cost,
+            anthropic_usage,
+            cache_hit: false,
         },
+        context: caller.context.clone(),
+        fallback,
     };
 
-    Ok(Json(response))
+    let mut response_headers =
+        upstream_headers(upstream_request_id.as_deref(), rate_limits.as_ref());
+    if let Some(result) = cache_result {
+        response_headers.insert(CACHE_RESULT_HEADER, HeaderValue::from_static(result));
+    }
+    Ok((response_headers, Json(response)))
+}
+
+/// Looks up a request in the response cache, unless the caller bypasses
+/// the cache with `X-DeepClaude-Cache: bypass`.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing the response cache
+/// * `headers` - HTTP request headers
+/// * `key` - Response cache key of the upstream request
+///
+/// # Returns
+///
+/// * `(Option<AnthropicResponse>, &'static str)` - The cached response on
+///   a hit, and the result of the lookup: `hit`, `miss` or `bypass`
+async fn cache_lookup(
+    state: &AppState,
+    headers: &axum::http::HeaderMap,
+    key: &str,
+) -> (Option<AnthropicResponse>, &'static str) {
+    let bypass = headers
+        .get(response_cache::BYPASS_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("bypass"));
+    let (cached, result) = if bypass {
+        (None, "bypass")
+    } else {
+        match state.response_cache.get(key).await {
+            Some(cached) => (Some(cached), "hit"),
+            None => (None, "miss"),
+        }
+    };
+
+    state.metrics.response_cache(result);
+    (cached, result)
+}
+
+/// Builds the usage of a response served from the response cache: the
+/// cached token counts, at no cost.
+fn cached_usage(response: &AnthropicResponse) -> CombinedUsage {
+    CombinedUsage {
+        total_cost: format_cost(0.0),
+        cost_usd: 0.0,
+        anthropic_usage: AnthropicUsage {
+            total_cost: format_cost(0.0),
+            ..AnthropicUsage::from_anthropic(response.usage.clone())
+        },
+        cache_hit: true,
+    }
+}
+
+/// Answers a non-streaming request from the response cache.
+///
+/// Cache hits are neither charged to budgets nor recorded in the usage
+/// ledger, but continue stored conversations like any other reply.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing the conversation store
+/// * `caller` - The admitted caller
+/// * `cached` - The cached response
+async fn cached_reply(
+    state: &Arc<AppState>,
+    caller: &Caller,
+    cached: AnthropicResponse,
+) -> (HeaderMap, Json<ApiResponse>) {
+    tracing::info!(model = %cached.model, "Chat served from response cache");
+    state.metrics.model_reported(&cached.model);
+    let combined_usage = cached_usage(&cached);
+    let content = cached
+        .content
+        .into_iter()
+        .map(ContentBlock::from_anthropic)
+        .collect::<Vec<_>>();
+    caller.save_turn(state, content.clone()).await;
+
+    let mut headers = HeaderMap::new();
+    headers.insert(CACHE_RESULT_HEADER, HeaderValue::from_static("hit"));
+    (
+        headers,
+        Json(ApiResponse {
+            created: Utc::now(),
+            content,
+            anthropic_response: None,
+            combined_usage,
+            context: caller.context.clone(),
+            fallback: None,
+        }),
+    )
+}
+
+/// Replays a response from the response cache as a stream, with the same
+/// events a live stream sends.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing the conversation store
+/// * `caller` - The admitted caller
+/// * `cached` - The cached response
+async fn cached_stream(
+    state: &Arc<AppState>,
+    caller: Caller,
+    cached: AnthropicResponse,
+) -> (HeaderMap, SseResponse) {
+    tracing::info!(model = %cached.model, "Stream served from response cache");
+    state.metrics.model_reported(&cached.model);
+    let combined_usage = cached_usage(&cached);
+    let content = cached
+        .content
+        .into_iter()
+        .map(ContentBlock::from_anthropic)
+        .collect::<Vec<_>>();
+    caller.save_turn(state, content.clone()).await;
+
+    let mut events = vec![(
+        "start",
+        StreamEvent::Start {
+            created: Utc::now(),
+            request_id: caller.request_id.clone(),
+            upstream_request_id: None,
+            anthropic_response: None,
+            context: caller.context.clone().map(Box::new),
+            fallback: None,
+        },
+    )];
+    for block in content {
+        let deltas = match block.content_type.as_str() {
+            "thinking" => vec![
+                ContentBlock {
+                    content_type: "thinking_delta".to_string(),
+                    thinking: block.thinking,
+                    ..ContentBlock::text("")
+                },
+                ContentBlock {
+                    content_type: "signature_delta".to_string(),
+                    signature: block.signature,
+                    ..ContentBlock::text("")
+                },
+            ],
+            "text" => vec![ContentBlock {
+                content_type: "text_delta".to_string(),
+                ..block
+            }],
+            _ => vec![block],
+        };
+        events.extend(
+            deltas
+                .into_iter()
+                .map(|delta| ("content", StreamEvent::Content { content: vec![delta] })),
+        );
+    }
+    events.push(("usage", StreamEvent::Usage { usage: combined_usage }));
+    events.push(("message_stop", StreamEvent::MessageStop));
+    events.push(("done", StreamEvent::Done));
+
+    let (tx, rx) = tokio::sync::mpsc::channel(events.len());
+    for (name, event) in events {
+        let _ = tx.try_send(Ok(Event::default()
+            .event(name)
+            .data(serde_json::to_string(&event).unwrap_or_default())));
+    }
+
+    let mut headers = HeaderMap::new();
+    headers.insert(CACHE_RESULT_HEADER, HeaderValue::from_static("hit"));
+    (headers, SseResponse::new(ReceiverStream::new(rx)))
 }
 
 /// Handler for streaming chat requests.
@@ -260,17 +816,19 @@ pub(crate) async fn chat(
 /// * `state` - Application state containing configuration
 /// * `headers` - HTTP request headers
 /// * `request` - The parsed chat request
+/// * `caller` - The admitted caller, whose stream permit is held until
+///   the stream completes
 ///
 /// # Returns
 ///
-/// * `Result<SseResponse>` - A stream of Server-Sent Events or an error
+/// * `Result<(HeaderMap, SseResponse)>` - Correlation headers and a stream
+///   of Server-Sent Events, or an error
 pub(crate) async fn chat_stream(
     State(state): State<Arc<AppState>>,
     headers: axum::http::HeaderMap,
     Json(request): Json<ApiRequest>,
-) -> Result<SseResponse> {
-    println!("Handling streaming chat request");
-
+    caller: Caller,
+) -> Result<(HeaderMap, SseResponse)> {
     // Validate system prompt
     if !request.validate_system_prompt() {
         return Err(ApiError::InvalidSystemPrompt);
@@ -279,24 +837,9 @@ pub(crate) async fn chat_stream(
     // Extract API token
     let anthropic_token = extract_api_token(&headers)?;
 
-    // Debug log token length and first/last few characters for debugging
-    let token_len = anthropic_token.len();
-    let token_preview = if token_len > 10 {
-        format!(
-            "{}...{}",
-            &anthropic_token[0..5],
-            &anthropic_token[token_len - 5..token_len]
-        )
-    } else {
-        format!("Token too short: {}", token_len)
-    };
-    println!(
-        "Using Anthropic API token (length {}): {}",
-        token_len, token_preview
-    );
-
     // Initialize client
-    let anthropic_client = AnthropicClient::new(anthropic_token);
+    let config = caller.config.clone();
+    let anthropic_client = state.anthropic_client(&config, anthropic_token);
 
     // Get messages with system prompt
     let messages = request.get_messages_with_system();
@@ -308,7 +851,7 @@ pub(crate) async fn chat_stream(
         // Add default thinking configuration if not provided
         let thinking_config = serde_json::json!({
             "type": "enabled",
-            "budget_tokens": 16000
+            "budget_tokens": DEFAULT_THINKING_BUDGET
         });
 
         if let serde_json::Value::Object(ref mut body) = anthropic_config.body {
@@ -316,54 +859,175 @@ pub(crate) async fn chat_stream(
         }
     }
 
+    // Identical requests may be replayed from the response cache
+    let cache_key = state.response_cache.is_enabled().then(|| {
+        response_cache::key(
+            &caller.key_id,
+            &anthropic_client.cache_key(
+                messages.clone(),
+                request.get_system_prompt().cloned(),
+                &anthropic_config,
+            ),
+        )
+    });
+    let cache_result = match &cache_key {
+        Some(key) => match cache_lookup(&state, &headers, key).await {
+            (Some(cached), _) => return Ok(cached_stream(&state, caller, cached).await),
+            (None, result) => Some(result),
+        },
+        None => None,
+    };
+
+    // Connect to Anthropic before responding, so the upstream request id
+    // can be returned in the response headers, and wait for the first
+    // event, so that failures can still fall back. Connection errors are
+    // reported through the stream like any other stream error.
+    tracing::info!(
+        model = %request.model().unwrap_or(crate::clients::anthropic::DEFAULT_MODEL),
+        messages = messages.len(),
+        "Starting Anthropic stream"
+    );
+    let upstream = fallback::send(
+        &anthropic_config, // Use the config with thinking enabled
+        &config.fallbacks,
+        &state.metrics,
+        |attempt| {
+            let messages = messages.clone(); // Use original messages directly
+            let system = request.get_system_prompt().cloned();
+            let client = &anthropic_client;
+            async move { fallback::first_event(client.chat_stream(messages, system, &attempt).await?).await }
+        },
+    )
+    .await;
+    let (upstream, fallback) = match upstream {
+        Ok((upstream, fallback)) => (Ok(upstream), fallback),
+        Err(e) => (Err(e), None),
+    };
+    let upstream_request_id = upstream
+        .as_ref()
+        .ok()
+        .and_then(|u| u.request_id().map(String::from));
+    // Verbose streams report the upstream response details in the start event
+    let anthropic_details = upstream
+        .as_ref()
+        .ok()
+        .filter(|_| request.verbose)
+        .map(|u| ExternalApiResponse {
+            status: u.status,
+            headers: u.headers.clone(),
+            timing: u.timing,
+            body: serde_json::Value::Null,
+            request_id: caller.request_id.clone(),
+            upstream_request_id: upstream_request_id.clone(),
+        });
+
     // Create channel for stream events
     let (tx, rx) = tokio::sync::mpsc::channel(100);
     let tx = Arc::new(tx);
 
-    // Spawn task to handle streaming
-    let config = state.config.clone();
+    // Spawn task to handle streaming; the stream keeps this configuration
+    // snapshot even if the server configuration is reloaded meanwhile
     let request_clone = request.clone();
+    // The stream span is a child of the chat span, keeping both open until
+    // the stream ends and giving stream logs the request id
+    let chat_span = tracing::Span::current();
+    let stream_span = tracing::info_span!("stream", first_token_ms = tracing::field::Empty);
+    let rate_limits = match &upstream {
+        Ok(upstream) => upstream.rate_limits(),
+        Err(ApiError::AnthropicError { rate_limits, .. }) => rate_limits.as_deref().cloned(),
+        Err(_) => None,
+    };
+    let mut response_headers = upstream_headers(upstream_request_id.as_deref(), rate_limits.as_ref());
+    if let Some(result) = cache_result {
+        response_headers.insert(CACHE_RESULT_HEADER, HeaderValue::from_static(result));
+    }
     tokio::spawn(async move {
         let tx = tx.clone();
+        // Keep the caller's stream slot until this task finishes
+        let mut caller = caller;
+        let mut message_usage = crate::clients::anthropic::Usage::default();
+        let mut final_usage: Option<(AnthropicUsage, f64)> = None;
+        let mut stop_reason: Option<String> = None;
+        let mut stream_failed = false;
+        let mut first_token_seen = false;
+        // The reply's content blocks, assembled for saving to a conversation
+        let mut reply: Vec<crate::clients::anthropic::ContentBlock> = Vec::new();
+        let mut message_id = String::new();
+        let mut thinking_chars = 0;
+        let mut model = match &fallback {
+            Some(fallback) => fallback.model.clone(),
+            None => request_clone
+                .model()
+                .unwrap_or(crate::clients::anthropic::DEFAULT_MODEL)
+                .to_string(),
+        };
 
         // Start event
         let _ = tx
             .send(Ok(Event::default().event("start").data(
                 serde_json::to_string(&StreamEvent::Start {
                     created: Utc::now(),
+                    request_id: caller.request_id.clone(),
+                    upstream_request_id,
+                    anthropic_response: anthropic_details,
+                    context: caller.context.clone().map(Box::new),
+                    fallback: fallback.clone().map(Box::new),
                 })
                 .unwrap_or_default(),
             )))
             .await;
 
-        println!("Starting Anthropic API stream request");
-
-        // Stream from Anthropic with thinking enabled
-        let mut anthropic_stream = anthropic_client.chat_stream(
-            messages.clone(), // Use original messages directly
-            request_clone.get_system_prompt().map(String::from),
-            &anthropic_config, // Use the config with thinking enabled
-        );
-
-        println!(
-            "Streaming request sent to Anthropic API with {} messages",
-            messages.len()
-        );
+        let mut anthropic_stream = match upstream {
+            Ok(upstream) => upstream.body,
+            Err(e) => Box::pin(futures::stream::once(async move { Err(e) })),
+        };
 
         // We no longer use DeepSeek, so no need to track its usage
 
-        while let Some(chunk) = anthropic_stream.next().await {
+        loop {
+            // Streams still open at the shutdown deadline are ended with
+            // an error event
+            let chunk = tokio::select! {
+                chunk = anthropic_stream.next() => chunk,
+                _ = state.shutdown.closing() => {
+                    tracing::warn!("Ending stream for shutdown");
+                    let _ = tx
+                        .send(Ok(Event::default().event("error").data(
+                            serde_json::to_string(&StreamEvent::Error {
+                                message: "Server shutting down".to_string(),
+                                code: 503,
+                                error_type: Some(shutdown::SHUTTING_DOWN.to_string()),
+                            })
+                            .unwrap_or_default(),
+                        )))
+                        .await;
+                    stream_failed = true;
+                    break;
+                }
+            };
+            let Some(chunk) = chunk else {
+                break;
+            };
+
             match chunk {
                 Ok(event) => {
-                    println!("Received Anthropic stream event: {:?}", event);
+                    tracing::trace!(event = %logging::content(&format!("{:?}", event)), "Anthropic stream event");
 
                     match event {
                         crate::clients::anthropic::StreamEvent::MessageStart { message } => {
-                            println!(
-                                "MessageStart event with {} content blocks",
-                                message.content.len()
+                            tracing::debug!(
+                                model = %message.model,
+                                content_blocks = message.content.len(),
+                                "Anthropic message started"
                             );
 
+                            // Input token counts and the resolved model are only
+                            // reported at message start
+                            message_usage = message.usage.clone();
+                            model = message.model.clone();
+                            state.metrics.model_reported(&model);
+                            message_id = message.id.clone();
+
                             // Only send content event if there's actual content to send
                             if !message.content.is_empty() {
                                 let content_blocks = message
@@ -372,11 +1036,6 @@ pub(crate) async fn chat_stream(
                                     .map(ContentBlock::from_anthropic)
                                     .collect::<Vec<_>>();
 
-                                println!(
-                                    "Sending content event with {} blocks",
-                                    content_blocks.len()
-                                );
-
                                 let _ = tx
                                     .send(Ok(Event::default().event("content").data(
                                         serde_json::to_string(&StreamEvent::Content {
@@ -385,35 +1044,35 @@ pub(crate) async fn chat_stream(
                                         .unwrap_or_default(),
                                     )))
                                     .await;
-                            } else {
-                                println!("MessageStart event has empty content, not sending event");
                             }
                         }
+                        crate::clients::anthropic::StreamEvent::ContentBlockStart {
+                            content_block, ..
+                        } => {
+                            reply.push(content_block);
+                        }
                         crate::clients::anthropic::StreamEvent::ContentBlockDelta {
-                            delta, ..
+                            index, delta
                         } => {
-                            // Create a base content block
-                            let content_block = ContentBlock {
-                                content_type: delta.delta_type.clone(),
-                                text: String::new(),
-                                thinking: None,
-                                signature: None,
-                                data: None,
-                            };
+                            if !first_token_seen {
+                                first_token_seen = true;
+                                state.metrics.observe_first_token(&model, caller.started);
+                                tracing::Span::current().record(
+                                    "first_token_ms",
+                                    caller.started.elapsed().as_millis() as u64,
+                                );
+                            }
+                            thinking_chars += delta.thinking.as_ref().map_or(0, String::len);
 
                             // Apply all delta fields including signature_delta and data
-                            // This will use the apply_to method which handles all fields properly
-                            delta.apply_to(&mut crate::clients::anthropic::ContentBlock {
-                                content_type: content_block.content_type.clone(),
-                                text: content_block.text.clone(),
-                                thinking: content_block.thinking.clone(),
-                                signature: content_block.signature.clone(),
-                                data: content_block.data.clone(),
-                            });
+                            // to the block they belong to
+                            if let Some(block) = reply.get_mut(index) {
+                                delta.apply_to(block);
+                            }
 
                             // Convert to the application's content block
                             let content_block =
-                                if delta.delta_type == "thinking" && delta.thinking.is_some() {
+                                if delta.thinking.is_some() {
                                     // Handle thinking content
                                     ContentBlock {
                                         content_type: delta.delta_type,
@@ -421,6 +1080,7 @@ pub(crate) async fn chat_stream(
                                         thinking: delta.thinking,
                                         signature: delta.signature_delta,
                                         data: delta.data,
+                                        cache_control: None,
                                     }
                                 } else {
                                     // Handle regular text content
@@ -430,6 +1090,7 @@ pub(crate) async fn chat_stream(
                                         thinking: None,
                                         signature: delta.signature_delta,
                                         data: delta.data,
+                                        cache_control: None,
                                     }
                                 };
 
@@ -442,21 +1103,46 @@ pub(crate) async fn chat_stream(
                                 )))
                                 .await;
                         }
-                        crate::clients::anthropic::StreamEvent::MessageDelta { usage: Some(usage), .. } => {
+                        crate::clients::anthropic::StreamEvent::MessageDelta { delta, usage: Some(usage) } => {
+                            stop_reason = delta.stop_reason.or(stop_reason);
+                            let usage = crate::clients::anthropic::Usage {
+                                input_tokens: usage.input_tokens.max(message_usage.input_tokens),
+                                output_tokens: usage.output_tokens,
+                                cache_creation_input_tokens: usage
+                                    .cache_creation_input_tokens
+                                    .max(message_usage.cache_creation_input_tokens),
+                                cache_read_input_tokens: usage
+                                    .cache_read_input_tokens
+                                    .max(message_usage.cache_read_input_tokens),
+                            };
                             let anthropic_usage = AnthropicUsage::from_anthropic(usage);
+                            state.rate_limiter.record_tokens(
+                                &caller.key_id,
+                                &config.limits,
+                                anthropic_usage.total_tokens,
+                            );
                             let anthropic_cost = calculate_anthropic_cost(
-                                "claude-3-7-sonnet-20250219", // Use latest model
+                                &model,
                                 anthropic_usage.input_tokens,
                                 anthropic_usage.output_tokens,
                                 anthropic_usage.cached_write_tokens,
                                 anthropic_usage.cached_read_tokens,
                                 &config,
                             );
+                            caller.charge(&state.budgets, anthropic_cost);
+                            final_usage = Some((
+                                AnthropicUsage {
+                                    total_cost: format_cost(anthropic_cost),
+                                    ..anthropic_usage.clone()
+                                },
+                                anthropic_cost,
+                            ));
                             let _ = tx
                                 .send(Ok(Event::default().event("usage").data(
                                     serde_json::to_string(&StreamEvent::Usage {
                                         usage: CombinedUsage {
                                             total_cost: format_cost(anthropic_cost), // Only Anthropic cost
+                                            cost_usd: anthropic_cost,
                                             anthropic_usage: AnthropicUsage {
                                                 input_tokens: anthropic_usage.input_tokens,
                                                 output_tokens: anthropic_usage
@@ -467,18 +1153,21 @@ pub(crate) async fn chat_stream(
                                                     .cached_read_tokens,
                                                 total_tokens: anthropic_usage.total_tokens,
                                                 total_cost: format_cost(anthropic_cost),
+                                                cache_hit_rate: anthropic_usage.cache_hit_rate,
                                             },
+                                            cache_hit: false,
                                         },
                                     })
                                     .unwrap_or_default(),
                                 )))
                                 .await;
                         }
-                        crate::clients::anthropic::StreamEvent::MessageDelta { usage: None, .. } => {
+                        crate::clients::anthropic::StreamEvent::MessageDelta { delta, usage: None } => {
                             // No usage data to send
+                            stop_reason = delta.stop_reason.or(stop_reason);
                         }
                         crate::clients::anthropic::StreamEvent::MessageStop => {
-                            println!("MessageStop event received");
+                            tracing::debug!("Anthropic message stopped");
                             let _ = tx
                                 .send(Ok(Event::default().event("message_stop").data(
                                     serde_json::to_string(&StreamEvent::MessageStop)
@@ -490,35 +1179,118 @@ pub(crate) async fn chat_stream(
                     }
                 }
                 Err(e) => {
-                    println!("Error from Anthropic stream: {}", e);
+                    tracing::warn!(error = %e, "Anthropic stream failed");
+                    state.metrics.upstream_error(&e);
+                    if let ApiError::AnthropicError { type_, .. } = &e {
+                        telemetry::record_error(&chat_span, type_);
+                    }
 
                     let error_message = e.to_string();
-                    println!("Sending error event to client: {}", error_message);
 
                     let _ = tx
                         .send(Ok(Event::default().event("error").data(
                             serde_json::to_string(&StreamEvent::Error {
                                 message: error_message,
                                 code: 500,
+                                error_type: None,
                             })
                             .unwrap_or_default(),
                         )))
                         .await;
-                    return;
+                    stream_failed = true;
+                    break;
                 }
             }
         }
 
-        // Send done event
-        let _ = tx
-            .send(Ok(Event::default().event("done").data(
-                serde_json::to_string(&StreamEvent::Done).unwrap_or_default(),
-            )))
-            .await;
+        if let (false, Some(key), Some((usage, _)), None) =
+            (stream_failed, &cache_key, &final_usage, &fallback)
+        {
+            state.response_cache.put(key, &AnthropicResponse {
+                id: message_id.clone(),
+                response_type: "message".to_string(),
+                role: "assistant".to_string(),
+                model: model.clone(),
+                content: reply.clone(),
+                stop_reason: stop_reason.clone(),
+                stop_sequence: None,
+                usage: crate::clients::anthropic::Usage {
+                    input_tokens: usage.input_tokens,
+                    output_tokens: usage.output_tokens,
+                    cache_creation_input_tokens: usage.cached_write_tokens,
+                    cache_read_input_tokens: usage.cached_read_tokens,
+                },
+            });
+        }
 
-        // Debug logging to confirm event was sent
-        println!("Stream completed, sent done event");
-    });
+        if !stream_failed {
+            caller
+                .save_turn(
+                    &state,
+                    reply.into_iter().map(ContentBlock::from_anthropic).collect(),
+                )
+                .await;
+
+            // Send done event
+            let _ = tx
+                .send(Ok(Event::default().event("done").data(
+                    serde_json::to_string(&StreamEvent::Done).unwrap_or_default(),
+                )))
+                .await;
+        }
+
+        // Record usage, falling back to the input usage from message start
+        // if the stream ended before a final usage report; that cost is
+        // charged here, since no usage event charged it
+        let (usage, cost) = match final_usage {
+            Some(final_usage) => final_usage,
+            None => {
+                let usage = AnthropicUsage::from_anthropic(message_usage);
+                let cost = calculate_anthropic_cost(
+                    &model,
+                    usage.input_tokens,
+                    usage.output_tokens,
+                    usage.cached_write_tokens,
+                    usage.cached_read_tokens,
+                    &config,
+                );
+                caller.charge(&state.budgets, cost);
+                (usage, cost)
+            }
+        };
+        let stop_reason = if stream_failed {
+            Some("error".to_string())
+        } else {
+            stop_reason
+        };
+        state
+            .ledger
+            .append(&caller.usage_record(&model, true, &usage, cost, stop_reason.clone()));
+        state.metrics.record_usage(
+            &model,
+            &usage,
+            estimate_thinking_tokens(thinking_chars),
+            cost,
+        );
+        state.metrics.observe_stream_duration(&model, caller.started);
+        telemetry::record_response(
+            &chat_span,
+            &model,
+            usage.input_tokens,
+            usage.output_tokens,
+            stop_reason.as_deref(),
+            cost,
+        );
+        tracing::info!(
+            model = %model,
+            input_tokens = usage.input_tokens,
+            output_tokens = usage.output_tokens,
+            cost_usd = cost,
+            latency_ms = caller.started.elapsed().as_millis() as u64,
+            failed = stream_failed,
+            "Stream completed"
+        );
+    }.instrument(stream_span));
 
     // Convert receiver into stream
     let stream = ReceiverStream::new(rx);
@@ -530,6 +1302,448 @@ pub(crate) async fn chat_stream(
             .text("keep-alive-text"),
     );
 
-    println!("Created SSE response, returning to client");
-    Ok(sse)
+    Ok((response_headers, sse))
+}
+
+/// Handler for counting the input tokens of a chat request without
+/// sending it.
+///
+/// Requests continuing a stored conversation are counted with its history.
+/// The tokens are counted by Anthropic's token counting API, or estimated
+/// locally when Anthropic cannot be reached or cannot count them.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration
+/// * `headers` - HTTP request headers
+/// * `request` - The chat request to count
+///
+/// # Returns
+///
+/// * `Result<Json<TokenCountResponse>>` - The input token count and the
+///   request's worst-case cost, or an error
+///
+/// # Errors
+///
+/// Returns `ApiError::InvalidSystemPrompt` for a request with two system
+/// prompts, and `ApiError::AnthropicError` if Anthropic rejects the request
+pub async fn count_tokens(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Json(request): Json<ApiRequest>,
+) -> Result<Json<TokenCountResponse>> {
+    if !request.validate_system_prompt() {
+        return Err(ApiError::InvalidSystemPrompt);
+    }
+    let token = extract_api_token(&headers)?;
+    let (_, request) = expand_conversation(&state, &caller_key_id(&token), request).await?;
+
+    let config = state.config();
+    let counted = state
+        .anthropic_client(&config, token)
+        .count_tokens(
+            request.get_messages_with_system(),
+            request.get_system_prompt().cloned(),
+            &request.anthropic_config,
+        )
+        .await;
+    let (input_tokens, method) = match counted {
+        Ok(tokens) => (tokens, "anthropic"),
+        // Unreachable, or an API without token counting, e.g. a mock
+        Err(ApiError::AnthropicError { type_, .. })
+            if matches!(
+                type_.as_str(),
+                "request_failed" | "not_found_error" | "api_error" | "overloaded_error"
+            ) =>
+        {
+            tracing::debug!(error_type = %type_, "Estimating token count locally");
+            (request.approximate_input_tokens(), "approximate")
+        }
+        Err(e) => return Err(e),
+    };
+
+    Ok(Json(TokenCountResponse {
+        model: request
+            .model()
+            .unwrap_or(crate::clients::anthropic::DEFAULT_MODEL)
+            .to_string(),
+        input_tokens,
+        method: method.to_string(),
+        max_output_tokens: worst_case_output_tokens(&request),
+        worst_case_cost: format_cost(worst_case_cost(&request, input_tokens, &config)),
+    }))
+}
+
+/// Handler for creating a stored conversation.
+///
+/// The conversation belongs to the caller's API key.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing the conversation store
+/// * `headers` - HTTP request headers carrying the caller's API token
+/// * `request` - Title, system prompt and initial messages
+///
+/// # Returns
+///
+/// * `Result<(StatusCode, Json<Conversation>)>` - `201 Created` with the new
+///   conversation, or an error
+pub async fn create_conversation(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Json(request): Json<CreateConversationRequest>,
+) -> Result<(StatusCode, Json<Conversation>)> {
+    let key_id = caller_key_id(&extract_api_token(&headers)?);
+    let conversation = blocking(move || state.conversations.create(&key_id, request)).await?;
+    Ok((StatusCode::CREATED, Json(conversation)))
+}
+
+/// Handler for listing the caller's stored conversations.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing the conversation store
+/// * `headers` - HTTP request headers carrying the caller's API token
+///
+/// # Returns
+///
+/// * `Result<Json<Vec<ConversationSummary>>>` - The caller's conversations,
+///   most recently updated first, or an error
+pub async fn list_conversations(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<Vec<ConversationSummary>>> {
+    let key_id = caller_key_id(&extract_api_token(&headers)?);
+    let conversations = blocking(move || state.conversations.list(&key_id)).await?;
+    Ok(Json(conversations))
+}
+
+/// Handler for fetching one of the caller's stored conversations.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing the conversation store
+/// * `headers` - HTTP request headers carrying the caller's API token
+/// * `id` - The conversation id
+///
+/// # Returns
+///
+/// * `Result<Json<Conversation>>` - The conversation with all its messages,
+///   or an error
+pub async fn get_conversation(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Json<Conversation>> {
+    let key_id = caller_key_id(&extract_api_token(&headers)?);
+    let conversation = blocking(move || state.conversations.get(&key_id, &id)).await?;
+    Ok(Json(conversation))
+}
+
+/// Handler for deleting one of the caller's stored conversations.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing the conversation store
+/// * `headers` - HTTP request headers carrying the caller's API token
+/// * `id` - The conversation id
+///
+/// # Returns
+///
+/// * `Result<StatusCode>` - `204 No Content`, or an error
+pub async fn delete_conversation(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Path(id): Path<String>,
+) -> Result<StatusCode> {
+    let key_id = caller_key_id(&extract_api_token(&headers)?);
+    blocking(move || state.conversations.delete(&key_id, &id)).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Query parameters for the usage admin endpoints.
+#[derive(Debug, Deserialize)]
+pub struct UsageQuery {
+    #[serde(default)]
+    pub group_by: GroupBy,
+    #[serde(flatten)]
+    pub filter: UsageFilter,
+}
+
+/// Verifies the bearer token sent to admin endpoints.
+///
+/// # Arguments
+///
+/// * `headers` - The HTTP request headers
+/// * `config` - Configuration containing the admin token
+///
+/// # Errors
+///
+/// Returns `ApiError::Unauthorized` if no admin token is configured or
+/// the request does not carry it
+fn authorize_admin(headers: &axum::http::HeaderMap, config: &Config) -> Result<()> {
+    let Some(expected) = config.admin.token.as_deref() else {
+        return Err(ApiError::Unauthorized {
+            message: "Admin API is disabled; set admin.token to enable it".to_string(),
+        });
+    };
+
+    let provided = headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    // Compare digests in constant time, so that neither the token nor its
+    // length can be guessed from response times
+    let matches = provided.is_some_and(|provided| {
+        let provided = Sha256::digest(provided.as_bytes());
+        bool::from(provided.ct_eq(&Sha256::digest(expected.as_bytes())))
+    });
+    if !matches {
+        return Err(ApiError::Unauthorized {
+            message: "Invalid admin token".to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Reads the ledger records matching a filter, off the async runtime.
+async fn read_ledger(state: &Arc<AppState>, filter: UsageFilter) -> Result<Vec<UsageRecord>> {
+    let state = state.clone();
+    blocking(move || Ok(state.ledger.read(&filter)?)).await
+}
+
+/// Expands a request continuing a stored conversation, reading the store
+/// off the async runtime.
+///
+/// # Returns
+///
+/// * `Result<(Option<ConversationTurn>, ApiRequest)>` - The new turn to
+///   save with the reply, if any, and the expanded request
+///
+/// # Errors
+///
+/// Returns any error from [`ConversationStore::expand`]
+async fn expand_conversation(
+    state: &Arc<AppState>,
+    key_id: &str,
+    mut request: ApiRequest,
+) -> Result<(Option<ConversationTurn>, ApiRequest)> {
+    if request.conversation_id.is_none() {
+        return Ok((None, request));
+    }
+    let state = state.clone();
+    let key_id = key_id.to_string();
+    blocking(move || {
+        let turn = state.conversations.expand(&key_id, &mut request)?;
+        Ok((turn, request))
+    })
+    .await
+}
+
+/// Runs blocking work, such as file or database I/O, on the blocking
+/// thread pool.
+///
+/// # Errors
+///
+/// Returns the work's error, or `ApiError::Other` if it panicked
+pub(crate) async fn blocking<T, F>(work: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(|e| ApiError::Other {
+            message: format!("Blocking task failed: {}", e),
+        })?
+}
+
+/// Handler for usage aggregate queries.
+///
+/// Returns ledger totals grouped by day, model, key or project,
+/// optionally restricted by date range, key, model or project.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing the usage ledger
+/// * `headers` - HTTP request headers carrying the admin token
+/// * `query` - Grouping and filter parameters
+///
+/// # Returns
+///
+/// * `Result<Json<Vec<UsageAggregate>>>` - Aggregated usage or an error
+pub async fn usage_summary(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Query(query): Query<UsageQuery>,
+) -> Result<Json<Vec<crate::ledger::UsageAggregate>>> {
+    authorize_admin(&headers, &state.config())?;
+    let records = read_ledger(&state, query.filter).await?;
+    Ok(Json(aggregate(&records, query.group_by)))
+}
+
+/// Handler for CSV export of raw ledger records.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing the usage ledger
+/// * `headers` - HTTP request headers carrying the admin token
+/// * `filter` - Filter parameters
+///
+/// # Returns
+///
+/// * `Result<Response>` - A `text/csv` attachment or an error
+pub async fn usage_export(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Query(filter): Query<UsageFilter>,
+) -> Result<axum::response::Response> {
+    authorize_admin(&headers, &state.config())?;
+    let records = read_ledger(&state, filter).await?;
+    Ok((
+        [
+            (CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                axum::http::header::CONTENT_DISPOSITION,
+                "attachment; filename=\"usage.csv\"",
+            ),
+        ],
+        to_csv(&records),
+    )
+        .into_response())
+}
+
+/// Handler for the liveness probe.
+///
+/// Always succeeds while the process is able to serve requests.
+///
+/// # Returns
+///
+/// * `Json<HealthResponse>` - A static `ok` status
+pub async fn healthz() -> Json<HealthResponse> {
+    Json(HealthResponse {
+        status: "ok".to_string(),
+    })
+}
+
+/// Handler for the readiness probe.
+///
+/// Reports whether configuration is loaded and, when enabled in
+/// `[health]`, whether the configured Anthropic base URL is reachable.
+/// Any HTTP response from upstream counts as reachable. The upstream
+/// check is skipped while replaying cassettes.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration
+///
+/// # Returns
+///
+/// * `(StatusCode, Json<ReadinessResponse>)` - `200` when ready, `503` otherwise
+pub async fn readyz(State(state): State<Arc<AppState>>) -> (StatusCode, Json<ReadinessResponse>) {
+    let config = state.config();
+    let mut checks = std::collections::BTreeMap::new();
+    checks.insert("config".to_string(), "ok".to_string());
+
+    let upstream = if !config.health.check_upstream || config.cassettes.mode == CassetteMode::Replay {
+        "skipped".to_string()
+    } else {
+        let timeout = std::time::Duration::from_millis(config.health.upstream_timeout_ms);
+        match reqwest::Client::new()
+            .get(&config.anthropic.base_url)
+            .timeout(timeout)
+            .send()
+            .await
+        {
+            Ok(_) => "ok".to_string(),
+            Err(e) => format!("unreachable: {}", e),
+        }
+    };
+    checks.insert("upstream".to_string(), upstream);
+    if state.shutdown.is_draining() {
+        checks.insert("shutdown".to_string(), "draining".to_string());
+    }
+
+    let ready = checks.values().all(|v| v == "ok" || v == "skipped");
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+
+    (
+        status,
+        Json(ReadinessResponse {
+            status: if ready { "ready" } else { "not_ready" }.to_string(),
+            checks,
+        }),
+    )
+}
+
+/// Handler for the version endpoint.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration
+///
+/// # Returns
+///
+/// * `Json<VersionResponse>` - Crate version, git commit and enabled features
+pub async fn version(State(state): State<Arc<AppState>>) -> Json<VersionResponse> {
+    Json(VersionResponse {
+        name: env!("CARGO_PKG_NAME").to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_sha: env!("DEEPCLAUDE_GIT_SHA").to_string(),
+        features: enabled_features(&state.config()),
+    })
+}
+
+/// Lists the optional features enabled by the current configuration.
+fn enabled_features(config: &Config) -> Vec<String> {
+    let limits = &config.limits;
+    let budgets = &config.budgets;
+    let features = [
+        (
+            "rate_limits",
+            limits.requests_per_minute.is_some()
+                || limits.tokens_per_minute.is_some()
+                || limits.max_concurrent_streams.is_some(),
+        ),
+        (
+            "budgets",
+            budgets.default_key.is_some() || !budgets.keys.is_empty() || !budgets.projects.is_empty(),
+        ),
+        ("usage_ledger", config.ledger.path.is_some()),
+        ("conversations", config.conversations.path.is_some()),
+        ("context_management", !config.context.strategies.is_empty()),
+        ("auto_prompt_cache", config.prompt_cache.auto),
+        ("response_cache", config.response_cache.enabled),
+        ("model_fallback", !config.fallbacks.chains.is_empty()),
+        ("admin_api", config.admin.token.is_some()),
+        ("cassette_record", config.cassettes.mode == CassetteMode::Record),
+        ("cassette_replay", config.cassettes.mode == CassetteMode::Replay),
+        ("upstream_health_check", config.health.check_upstream),
+        ("otlp_export", config.telemetry.otlp_endpoint.is_some()),
+    ];
+
+    features
+        .into_iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
+/// Handler for the Prometheus metrics endpoint.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing the metrics registry
+///
+/// # Returns
+///
+/// * `Response` - All metrics in the Prometheus text format
+pub async fn metrics(State(state): State<Arc<AppState>>) -> axum::response::Response {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(),
+    )
+        .into_response()
 }
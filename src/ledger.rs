@@ -0,0 +1,340 @@
+//! Persistent usage ledger.
+//!
+//! Every completed request is appended to a local JSON Lines file so that
+//! usage and cost can be reviewed after the fact. Records are written by a
+//! background thread, off the request path. This module provides:
+//! - Appending usage records
+//! - Reading records back with optional filters
+//! - Aggregation by day, model, key or project
+//! - CSV export for reporting
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::OpenOptions,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Sender, SyncSender},
+};
+
+/// A single request's usage as stored in the ledger.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageRecord {
+    pub timestamp: DateTime<Utc>,
+    pub key_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+    pub model: String,
+    pub stream: bool,
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+    pub cache_write_tokens: u32,
+    pub cache_read_tokens: u32,
+    pub cost_usd: f64,
+    pub latency_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_reason: Option<String>,
+}
+
+/// Filters applied when reading records from the ledger.
+///
+/// All fields are optional; an unset field matches every record.
+/// Date bounds are inclusive and compared against the UTC day.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct UsageFilter {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub key_id: Option<String>,
+    pub model: Option<String>,
+    pub project: Option<String>,
+}
+
+impl UsageFilter {
+    fn matches(&self, record: &UsageRecord) -> bool {
+        let day = record.timestamp.date_naive();
+        self.from.is_none_or(|from| day >= from)
+            && self.to.is_none_or(|to| day <= to)
+            && self.key_id.as_ref().is_none_or(|k| *k == record.key_id)
+            && self.model.as_ref().is_none_or(|m| *m == record.model)
+            && self
+                .project
+                .as_ref()
+                .is_none_or(|p| record.project.as_ref() == Some(p))
+    }
+}
+
+/// Dimension used to group records when aggregating.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    #[default]
+    Day,
+    Model,
+    Key,
+    Project,
+}
+
+impl GroupBy {
+    fn group_of(&self, record: &UsageRecord) -> String {
+        match self {
+            GroupBy::Day => record.timestamp.format("%Y-%m-%d").to_string(),
+            GroupBy::Model => record.model.clone(),
+            GroupBy::Key => record.key_id.clone(),
+            GroupBy::Project => record.project.clone().unwrap_or_default(),
+        }
+    }
+}
+
+/// Totals for one group of ledger records.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UsageAggregate {
+    pub group: String,
+    pub requests: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cache_write_tokens: u64,
+    pub cache_read_tokens: u64,
+    pub cost_usd: f64,
+    pub avg_latency_ms: u64,
+}
+
+/// Append-only usage ledger backed by a JSON Lines file.
+///
+/// When no path is configured, records are discarded.
+#[derive(Debug)]
+pub struct UsageLedger {
+    path: Option<PathBuf>,
+    writes: Option<Sender<LedgerWrite>>,
+}
+
+/// Work for the ledger writer thread.
+#[derive(Debug)]
+enum LedgerWrite {
+    /// A serialized record, ending in a newline
+    Line(Vec<u8>),
+    /// Signals once every earlier line has been written
+    Flush(SyncSender<()>),
+}
+
+impl UsageLedger {
+    /// Creates a ledger writing to the given path.
+    ///
+    /// With a path, a background thread appends the records to it.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Location of the JSON Lines file, or `None` to disable the ledger
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the writer thread cannot be started
+    pub fn new(path: Option<String>) -> anyhow::Result<Self> {
+        let path = path.map(PathBuf::from);
+        let writes = match &path {
+            Some(path) => {
+                let (tx, rx) = mpsc::channel::<LedgerWrite>();
+                let path = path.clone();
+                std::thread::Builder::new()
+                    .name("usage-ledger".to_string())
+                    .spawn(move || {
+                        for write in rx {
+                            match write {
+                                LedgerWrite::Line(line) => write_line(&path, &line),
+                                LedgerWrite::Flush(done) => {
+                                    let _ = done.send(());
+                                }
+                            }
+                        }
+                    })?;
+                Some(tx)
+            }
+            None => None,
+        };
+
+        Ok(Self { path, writes })
+    }
+
+    /// Queues a record to be appended to the ledger.
+    ///
+    /// Failures are logged rather than returned so that bookkeeping
+    /// problems never fail a user's request.
+    ///
+    /// # Arguments
+    ///
+    /// * `record` - The usage record to append
+    pub fn append(&self, record: &UsageRecord) {
+        let Some(writes) = &self.writes else {
+            return;
+        };
+
+        match serde_json::to_vec(record) {
+            Ok(mut line) => {
+                line.push(b'\n');
+                let _ = writes.send(LedgerWrite::Line(line));
+            }
+            Err(e) => tracing::warn!("Failed to serialize usage record: {}", e),
+        }
+    }
+
+    /// Waits until every queued record has been written.
+    ///
+    /// Used at shutdown, so that no record is lost. Blocks on file I/O.
+    pub fn flush(&self) {
+        if let Some(writes) = &self.writes {
+            let (done, wait) = mpsc::sync_channel(1);
+            if writes.send(LedgerWrite::Flush(done)).is_ok() {
+                let _ = wait.recv();
+            }
+        }
+    }
+
+    /// Reads all records matching a filter.
+    ///
+    /// Lines that cannot be parsed are skipped.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - Criteria records must match
+    ///
+    /// # Returns
+    ///
+    /// * `anyhow::Result<Vec<UsageRecord>>` - Matching records in ledger order
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the ledger file exists but cannot be read
+    pub fn read(&self, filter: &UsageFilter) -> anyhow::Result<Vec<UsageRecord>> {
+        let Some(path) = &self.path else {
+            return Ok(Vec::new());
+        };
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let reader = BufReader::new(std::fs::File::open(path)?);
+        let mut records = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            if let Ok(record) = serde_json::from_str::<UsageRecord>(&line) {
+                if filter.matches(&record) {
+                    records.push(record);
+                }
+            }
+        }
+
+        Ok(records)
+    }
+}
+
+/// Appends a serialized record to the ledger file, logging failures.
+fn write_line(path: &Path, line: &[u8]) {
+    let result = (|| -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.write_all(line)?;
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        tracing::warn!("Failed to append usage record to {}: {}", path.display(), e);
+    }
+}
+
+/// Aggregates records into per-group totals.
+///
+/// # Arguments
+///
+/// * `records` - The records to aggregate
+/// * `group_by` - The dimension to group by
+///
+/// # Returns
+///
+/// * `Vec<UsageAggregate>` - One aggregate per group, sorted by group
+pub fn aggregate(records: &[UsageRecord], group_by: GroupBy) -> Vec<UsageAggregate> {
+    let mut groups: BTreeMap<String, (UsageAggregate, u64)> = BTreeMap::new();
+
+    for record in records {
+        let group = group_by.group_of(record);
+        let (totals, latency_sum) = groups.entry(group.clone()).or_insert_with(|| {
+            (
+                UsageAggregate {
+                    group,
+                    ..Default::default()
+                },
+                0,
+            )
+        });
+        totals.requests += 1;
+        totals.input_tokens += record.input_tokens as u64;
+        totals.output_tokens += record.output_tokens as u64;
+        totals.cache_write_tokens += record.cache_write_tokens as u64;
+        totals.cache_read_tokens += record.cache_read_tokens as u64;
+        totals.cost_usd += record.cost_usd;
+        *latency_sum += record.latency_ms;
+    }
+
+    groups
+        .into_values()
+        .map(|(mut totals, latency_sum)| {
+            totals.avg_latency_ms = latency_sum / totals.requests.max(1);
+            totals
+        })
+        .collect()
+}
+
+/// Renders records as CSV with a header row.
+///
+/// # Arguments
+///
+/// * `records` - The records to export
+///
+/// # Returns
+///
+/// The CSV document as a string
+pub fn to_csv(records: &[UsageRecord]) -> String {
+    let mut csv = String::from(
+        "timestamp,key_id,project,model,stream,input_tokens,output_tokens,\
+         cache_write_tokens,cache_read_tokens,cost_usd,latency_ms,stop_reason\n",
+    );
+
+    for r in records {
+        let fields = [
+            r.timestamp.to_rfc3339(),
+            csv_field(&r.key_id),
+            csv_field(r.project.as_deref().unwrap_or_default()),
+            csv_field(&r.model),
+            r.stream.to_string(),
+            r.input_tokens.to_string(),
+            r.output_tokens.to_string(),
+            r.cache_write_tokens.to_string(),
+            r.cache_read_tokens.to_string(),
+            format!("{:.6}", r.cost_usd),
+            r.latency_ms.to_string(),
+            csv_field(r.stop_reason.as_deref().unwrap_or_default()),
+        ];
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+
+    csv
+}
+
+/// Quotes a CSV field if it contains separators, quotes or newlines.
+///
+/// Fields a spreadsheet would read as a formula are prefixed with `'`, since
+/// some of them, like the project, are chosen by callers.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,290 @@
+//! DeepClaude - A high-performance LLM inference API and Chat UI that integrates DeepSeek R1's CoT reasoning traces with Anthropic Claude models..
+//!
+//! This library provides a REST API for chat interactions that:
+//! - Processes messages through DeepSeek R1 for reasoning
+//! - Uses Anthropic's Claude for final responses
+//! - Supports both streaming and non-streaming responses
+//! - Tracks token usage and costs
+//! - Provides detailed usage statistics
+//!
+//! The `deepclaude` binary runs the server; the same building blocks are
+//! available for embedding:
+//! - [`clients`]: the Anthropic API client
+//! - [`models`]: request and response types
+//! - [`config`]: configuration, including pricing
+//! - [`pricing`]: cost calculation
+//! - [`router`]: the HTTP API as an axum [`Router`], to mount in another
+//!   server
+//! - `sdk`: a typed client for the HTTP API, with the `sdk` feature
+//!
+//! # Example
+//!
+//! ```no_run
+//! use deepclaude::{config::Config, router, AppState};
+//! use std::sync::Arc;
+//!
+//! async fn example() -> anyhow::Result<()> {
+//!     let config = Config::load(None)?;
+//!     let state = Arc::new(AppState::new(config)?);
+//!     let app = axum::Router::new().nest("/deepclaude", router(state));
+//!
+//!     let listener = tokio::net::TcpListener::bind("127.0.0.1:8080").await?;
+//!     axum::serve(listener, app).await?;
+//!     Ok(())
+//! }
+//! ```
+
+pub mod clients;
+pub mod config;
+pub mod error;
+pub mod models;
+pub mod pricing;
+#[cfg(feature = "sdk")]
+pub mod sdk;
+
+mod budget;
+mod context;
+mod conversations;
+mod fallback;
+mod handlers;
+mod ledger;
+mod logging;
+mod metrics;
+mod rate_limit;
+mod reload;
+mod request_id;
+mod response_cache;
+mod shutdown;
+mod sse;
+mod telemetry;
+mod tls;
+
+pub use handlers::AppState;
+
+use crate::{
+    clients::rate_limits::RATE_LIMIT_HEADER_PREFIX,
+    config::{Config, CorsConfig},
+};
+use axum::{
+    http::{HeaderName, HeaderValue, Method},
+    routing::{get, post},
+    Router,
+};
+use std::{path::PathBuf, sync::Arc, time::Duration};
+use tower_http::{
+    cors::{AllowOrigin, CorsLayer},
+    trace::TraceLayer,
+};
+
+/// Response headers browser clients may read, besides the
+/// `anthropic-ratelimit-*` headers.
+const EXPOSED_HEADERS: [&str; 12] = [
+    "x-request-id",
+    "x-anthropic-request-id",
+    "x-deepclaude-cache",
+    "x-budget-remaining-usd",
+    "x-budget-warning",
+    "retry-after",
+    "x-ratelimit-limit-requests",
+    "x-ratelimit-remaining-requests",
+    "x-ratelimit-reset-requests",
+    "x-ratelimit-limit-tokens",
+    "x-ratelimit-remaining-tokens",
+    "x-ratelimit-reset-tokens",
+];
+
+/// Builds the HTTP API with its middleware.
+///
+/// Includes the chat and token counting endpoints, the conversation endpoints, health, version
+/// and metrics endpoints, the admin API, request ids, tracing, metrics collection and the configured
+/// CORS policy.
+///
+/// # Arguments
+///
+/// * `state` - Application state shared by all handlers
+///
+/// # Returns
+///
+/// * `Router` - The API, ready to serve or to nest in another router
+pub fn router(state: Arc<AppState>) -> Router {
+    let cors = cors_layer(&state.config().server.cors);
+
+    Router::new()
+        .route("/", post(handlers::handle_chat))
+        .route("/v1/count_tokens", post(handlers::count_tokens))
+        .route("/healthz", get(handlers::healthz))
+        .route("/readyz", get(handlers::readyz))
+        .route("/version", get(handlers::version))
+        .route("/metrics", get(handlers::metrics))
+        .route(
+            "/conversations",
+            post(handlers::create_conversation).get(handlers::list_conversations),
+        )
+        .route(
+            "/conversations/{id}",
+            get(handlers::get_conversation).delete(handlers::delete_conversation),
+        )
+        .route("/admin/usage", get(handlers::usage_summary))
+        .route("/admin/usage/export", get(handlers::usage_export))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            metrics::track_requests,
+        ))
+        .layer(TraceLayer::new_for_http().make_span_with(logging::request_span))
+        .layer(axum::middleware::from_fn(request_id::assign))
+        .layer(cors)
+        .with_state(state)
+}
+
+/// Runs the server until it has drained after `SIGTERM` or `SIGINT`.
+///
+/// Sets up logging and trace export, serves the API over HTTP or HTTPS,
+/// and reloads the configuration when the config file changes or on
+/// `SIGHUP`.
+///
+/// # Arguments
+///
+/// * `config` - The validated configuration to start with
+/// * `config_path` - The config file to watch for changes
+/// * `load` - Loads and validates the full configuration on reload
+///
+/// # Errors
+///
+/// Returns an error if:
+/// - Trace export or TLS cannot be set up
+/// - Server address binding fails
+/// - Server encounters a fatal error while running
+pub async fn serve<F>(config: Config, config_path: PathBuf, load: F) -> anyhow::Result<()>
+where
+    F: Fn() -> anyhow::Result<Config> + Send + Sync + 'static,
+{
+    // Initialize logging and, if configured, trace export
+    let tracer_provider = telemetry::tracer_provider(&config.telemetry)?;
+    logging::init(&config.logging, tracer_provider.as_ref());
+
+    let tls = match &config.server.tls {
+        Some(tls_config) => Some(tls::load(tls_config).await?),
+        None => None,
+    };
+
+    // Create application state
+    let addr = config.server.socket_addr()?;
+    let shutdown_timeout = Duration::from_secs(config.server.shutdown_timeout_secs);
+    let state = Arc::new(AppState {
+        tls: tls.clone(),
+        ..AppState::new(config)?
+    });
+
+    // Reload configuration when the config file changes or on SIGHUP
+    reload::spawn(state.clone(), config_path, load);
+
+    let app = router(state.clone());
+
+    tracing::info!(
+        "Starting server on {}://{}",
+        if tls.is_some() { "https" } else { "http" },
+        addr
+    );
+
+    // Drain in-flight requests on SIGTERM or SIGINT
+    shutdown::spawn(state.clone(), shutdown_timeout);
+
+    // Start server
+    let draining = state.clone();
+    let server = async move {
+        match tls {
+            Some(tls) => {
+                let handle = axum_server::Handle::new();
+                let shutdown_handle = handle.clone();
+                tokio::spawn(async move {
+                    draining.shutdown.draining().await;
+                    shutdown_handle.graceful_shutdown(None);
+                });
+                axum_server::bind_rustls(addr, tls)
+                    .handle(handle)
+                    .serve(app.into_make_service())
+                    .await
+            }
+            None => {
+                axum::serve(
+                    tokio::net::TcpListener::bind(&addr).await?,
+                    app.into_make_service(),
+                )
+                .with_graceful_shutdown(async move { draining.shutdown.draining().await })
+                .await
+            }
+        }
+    };
+
+    tokio::select! {
+        result = server => result?,
+        _ = shutdown::deadline(state.clone()) => {}
+    }
+    tracing::info!("Server stopped");
+
+    // Persist budget spend recorded since the last background write, and
+    // usage records still queued for the ledger
+    let flushing = state.clone();
+    tokio::task::spawn_blocking(move || {
+        flushing.budgets.flush();
+        flushing.ledger.flush();
+    })
+    .await?;
+
+    if let Some(provider) = tracer_provider {
+        provider.shutdown()?;
+    }
+
+    Ok(())
+}
+
+/// Builds the CORS layer for the configured policy.
+///
+/// Entries that are not valid header values are skipped; configuration
+/// validation rejects them before they get here. Request ids, rate limits,
+/// budgets and cache results are exposed to browser clients.
+///
+/// # Arguments
+///
+/// * `config` - CORS configuration
+fn cors_layer(config: &CorsConfig) -> CorsLayer {
+    let origins = if config.allowed_origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(
+            config
+                .allowed_origins
+                .iter()
+                .filter_map(|origin| HeaderValue::from_str(origin).ok()),
+        )
+    };
+    let methods = config
+        .allowed_methods
+        .iter()
+        .filter_map(|method| Method::from_bytes(method.as_bytes()).ok())
+        .collect::<Vec<_>>();
+    let headers = config
+        .allowed_headers
+        .iter()
+        .filter_map(|header| HeaderName::from_bytes(header.as_bytes()).ok())
+        .collect::<Vec<_>>();
+    let anthropic_rate_limits = ["requests", "tokens", "input-tokens", "output-tokens"]
+        .into_iter()
+        .flat_map(|resource| {
+            ["limit", "remaining", "reset"]
+                .map(|field| format!("{}{}-{}", RATE_LIMIT_HEADER_PREFIX, resource, field))
+        });
+    let exposed = EXPOSED_HEADERS
+        .map(String::from)
+        .into_iter()
+        .chain(anthropic_rate_limits)
+        .filter_map(|header| HeaderName::from_bytes(header.as_bytes()).ok())
+        .collect::<Vec<_>>();
+
+    CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(methods)
+        .allow_headers(headers)
+        .expose_headers(exposed)
+        .allow_credentials(config.allow_credentials)
+}
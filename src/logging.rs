@@ -0,0 +1,155 @@
+//! Structured logging with redaction.
+//!
+//! Logs are emitted through `tracing`, as text or JSON lines depending on
+//! the `[logging]` configuration. Every HTTP request runs in a `request`
+//! span carrying its request id, so all lines logged while serving it,
+//! including those from spawned streaming tasks, can be correlated.
+//!
+//! Two safeguards keep sensitive data out of logs:
+//! - Every formatted line passes through a redacting writer that masks API
+//!   keys and credential headers, whatever code path produced the line
+//! - Prompt and completion text is only logged at `trace` level and only
+//!   through [`content`], which replaces it with its length when
+//!   `logging.redact_prompts` is enabled
+
+use crate::{
+    config::{LogFormat, LoggingConfig},
+    request_id::RequestId,
+    telemetry,
+};
+use axum::http::Request;
+use once_cell::sync::Lazy;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use regex::Regex;
+use std::{
+    borrow::Cow,
+    io::{self, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, util::SubscriberInitExt};
+
+/// Replacement for redacted secrets.
+const REDACTED: &str = "[REDACTED]";
+
+/// Whether prompt and completion text is redacted, set once at startup.
+static REDACT_PROMPTS: AtomicBool = AtomicBool::new(true);
+
+/// API keys in any position, e.g. `sk-ant-api03-...` or DeepSeek `sk-...`.
+static API_KEY_PATTERN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"sk-(?:ant-)?[A-Za-z0-9_\-]{16,}").expect("valid regex"));
+
+/// Credential headers as they appear in formatted header maps or JSON.
+static CREDENTIAL_HEADER_PATTERN: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r#"(?i)("?(?:x-api-key|x-anthropic-api-token|authorization)"?\s*[:=]\s*"?)(?:Bearer\s+)?[^\s",}]+"#,
+    )
+    .expect("valid regex")
+});
+
+/// Installs the global tracing subscriber.
+///
+/// The `RUST_LOG` environment variable, when set, takes precedence over
+/// `logging.filter`.
+///
+/// # Arguments
+///
+/// * `config` - Logging configuration
+/// * `tracer_provider` - Exports spans over OpenTelemetry when given
+pub fn init(config: &LoggingConfig, tracer_provider: Option<&SdkTracerProvider>) {
+    REDACT_PROMPTS.store(config.redact_prompts, Ordering::Relaxed);
+
+    let json_logs = config.format == LogFormat::Json;
+    let writer = RedactingMakeWriter(io::stdout);
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| config.filter.as_str().into()),
+        )
+        .with(json_logs.then(|| {
+            tracing_subscriber::fmt::layer()
+                .json()
+                .with_current_span(true)
+                .with_span_list(false)
+                .with_writer(writer)
+        }))
+        .with((!json_logs).then(|| tracing_subscriber::fmt::layer().with_writer(writer)))
+        .with(tracer_provider.map(telemetry::layer))
+        .init();
+}
+
+/// Creates the span an HTTP request is served in.
+///
+/// Only the request id, method and path are recorded; query strings and
+/// headers are left out as they may carry credentials. The span continues
+/// any trace context sent by the caller.
+pub fn request_span<B>(request: &Request<B>) -> tracing::Span {
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .map_or("unknown", |id| id.0.as_str());
+    let span = tracing::info_span!(
+        "request",
+        otel.kind = "server",
+        request_id = %request_id,
+        method = %request.method(),
+        path = %request.uri().path(),
+    );
+    telemetry::set_remote_parent(&span, request.headers());
+    span
+}
+
+/// Prepares prompt or completion text for logging.
+///
+/// # Arguments
+///
+/// * `text` - The text to log
+///
+/// # Returns
+///
+/// * `Cow<str>` - The text, or a placeholder with its length when prompt
+///   redaction is enabled
+pub fn content(text: &str) -> Cow<'_, str> {
+    if REDACT_PROMPTS.load(Ordering::Relaxed) {
+        Cow::Owned(format!("[{} bytes redacted]", text.len()))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+/// Masks API keys and credential header values in a log line.
+pub fn redact_secrets(line: &str) -> Cow<'_, str> {
+    match CREDENTIAL_HEADER_PATTERN.replace_all(line, format!("${{1}}{}", REDACTED)) {
+        Cow::Borrowed(line) => API_KEY_PATTERN.replace_all(line, REDACTED),
+        Cow::Owned(line) => Cow::Owned(API_KEY_PATTERN.replace_all(&line, REDACTED).into_owned()),
+    }
+}
+
+/// Writer factory wrapping every log writer in a [`RedactingWriter`].
+#[derive(Clone, Copy)]
+struct RedactingMakeWriter<M>(M);
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for RedactingMakeWriter<M> {
+    type Writer = RedactingWriter<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter(self.0.make_writer())
+    }
+}
+
+/// Writer that redacts secrets before passing output on.
+///
+/// The formatter writes each log line in a single call, so patterns are
+/// never split across writes.
+struct RedactingWriter<W>(W);
+
+impl<W: Write> Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let line = String::from_utf8_lossy(buf);
+        self.0.write_all(redact_secrets(&line).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
@@ -0,0 +1,307 @@
+//! Prometheus metrics.
+//!
+//! This module defines the metrics exposed on `/metrics`, covering:
+//! - Requests by route, model and status, and their latency
+//! - Time to first token and total duration of streams
+//! - Upstream errors by type
+//! - Token usage and cumulative cost by caller key and model
+//! - Response cache lookups by result
+//!
+//! `model` labels are limited to models Anthropic has reported serving, so
+//! that callers cannot create unbounded series by requesting made-up model
+//! names; other values are labelled `other`.
+
+use crate::{error::ApiError, handlers::AppState, models::AnthropicUsage};
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use prometheus::{
+    CounterVec, Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+};
+use std::{
+    collections::HashSet,
+    sync::{Arc, RwLock},
+    time::Instant,
+};
+
+/// Latency buckets in seconds, sized for LLM calls that can take minutes.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0, 300.0,
+];
+
+/// Label for models Anthropic has not reported serving.
+const OTHER_MODEL: &str = "other";
+
+/// Model a response was served by, attached to responses as an extension
+/// so the metrics middleware can label requests by model.
+#[derive(Debug, Clone)]
+pub struct ResponseModel(pub String);
+
+/// All metrics exported by the server.
+#[derive(Debug)]
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
+    stream_first_token: HistogramVec,
+    stream_duration: HistogramVec,
+    upstream_errors: IntCounterVec,
+    tokens: IntCounterVec,
+    cost: CounterVec,
+    response_cache: IntCounterVec,
+    fallbacks: IntCounterVec,
+    /// Models Anthropic has reported, the values allowed as `model` labels
+    reported_models: RwLock<HashSet<String>>,
+}
+
+impl Metrics {
+    /// Creates and registers all metrics.
+    ///
+    /// # Returns
+    ///
+    /// * `prometheus::Result<Self>` - The metrics, or an error if a metric
+    ///   definition is invalid
+    pub fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new();
+
+        let requests = IntCounterVec::new(
+            Opts::new("deepclaude_requests_total", "HTTP requests by route, model and status"),
+            &["route", "model", "status"],
+        )?;
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "deepclaude_request_duration_seconds",
+                "Time until response headers are sent, by route",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["route"],
+        )?;
+        let stream_first_token = HistogramVec::new(
+            HistogramOpts::new(
+                "deepclaude_stream_first_token_seconds",
+                "Time from request to first streamed content, by model",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["model"],
+        )?;
+        let stream_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "deepclaude_stream_duration_seconds",
+                "Total duration of streamed responses, by model",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["model"],
+        )?;
+        let upstream_errors = IntCounterVec::new(
+            Opts::new("deepclaude_upstream_errors_total", "Errors from the Anthropic API by type"),
+            &["type"],
+        )?;
+        let tokens = IntCounterVec::new(
+            Opts::new(
+                "deepclaude_tokens_total",
+                "Tokens by model and kind (input, output, cache_write, cache_read, \
+                 thinking); thinking tokens are estimated from text length",
+            ),
+            &["model", "kind"],
+        )?;
+        let cost = CounterVec::new(
+            Opts::new("deepclaude_cost_usd_total", "Cumulative cost in dollars by model"),
+            &["model"],
+        )?;
+
+        let response_cache = IntCounterVec::new(
+            Opts::new(
+                "deepclaude_response_cache_total",
+                "Response cache lookups by result (hit, miss, bypass)",
+            ),
+            &["result"],
+        )?;
+        let fallbacks = IntCounterVec::new(
+            Opts::new(
+                "deepclaude_fallbacks_total",
+                "Requests answered by a fallback, by requested and answering model",
+            ),
+            &["requested_model", "model"],
+        )?;
+
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(request_duration.clone()))?;
+        registry.register(Box::new(stream_first_token.clone()))?;
+        registry.register(Box::new(stream_duration.clone()))?;
+        registry.register(Box::new(upstream_errors.clone()))?;
+        registry.register(Box::new(tokens.clone()))?;
+        registry.register(Box::new(cost.clone()))?;
+        registry.register(Box::new(response_cache.clone()))?;
+        registry.register(Box::new(fallbacks.clone()))?;
+
+        Ok(Self {
+            registry,
+            requests,
+            request_duration,
+            stream_first_token,
+            stream_duration,
+            upstream_errors,
+            tokens,
+            cost,
+            response_cache,
+            fallbacks,
+            reported_models: RwLock::default(),
+        })
+    }
+
+    /// Notes a model Anthropic reported serving a request, allowing it as a
+    /// `model` label.
+    pub fn model_reported(&self, model: &str) {
+        let known = self
+            .reported_models
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains(model);
+        if !known {
+            self.reported_models
+                .write()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(model.to_string());
+        }
+    }
+
+    /// Returns the `model` label for a model: the model itself if Anthropic
+    /// has reported serving it, `other` otherwise.
+    fn model_label<'a>(&self, model: &'a str) -> &'a str {
+        let reported = self.reported_models.read().unwrap_or_else(|e| e.into_inner());
+        if reported.contains(model) {
+            model
+        } else {
+            OTHER_MODEL
+        }
+    }
+
+    /// Records the time until the first content of a stream was sent.
+    pub fn observe_first_token(&self, model: &str, started: Instant) {
+        self.stream_first_token
+            .with_label_values(&[self.model_label(model)])
+            .observe(started.elapsed().as_secs_f64());
+    }
+
+    /// Records the total duration of a completed stream.
+    pub fn observe_stream_duration(&self, model: &str, started: Instant) {
+        self.stream_duration
+            .with_label_values(&[self.model_label(model)])
+            .observe(started.elapsed().as_secs_f64());
+    }
+
+    /// Counts an error returned by the Anthropic API.
+    ///
+    /// Errors that did not originate upstream are ignored.
+    pub fn upstream_error(&self, error: &ApiError) {
+        if let ApiError::AnthropicError { type_, .. } = error {
+            self.upstream_errors.with_label_values(&[type_]).inc();
+        }
+    }
+
+    /// Records token usage and cost for a completed request.
+    ///
+    /// Callers are not labelled, since any token creates a new caller;
+    /// per-key usage is kept in the usage ledger.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The model that served the request
+    /// * `usage` - Token usage reported by Anthropic
+    /// * `thinking_tokens` - Estimated tokens spent on thinking
+    /// * `cost` - Cost of the request in dollars
+    pub fn record_usage(
+        &self,
+        model: &str,
+        usage: &AnthropicUsage,
+        thinking_tokens: u32,
+        cost: f64,
+    ) {
+        let model = self.model_label(model);
+        let kinds = [
+            ("input", usage.input_tokens),
+            ("output", usage.output_tokens),
+            ("cache_write", usage.cached_write_tokens),
+            ("cache_read", usage.cached_read_tokens),
+            ("thinking", thinking_tokens),
+        ];
+        for (kind, count) in kinds {
+            self.tokens
+                .with_label_values(&[model, kind])
+                .inc_by(count as u64);
+        }
+        self.cost.with_label_values(&[model]).inc_by(cost);
+    }
+
+    /// Counts a response cache lookup.
+    ///
+    /// # Arguments
+    ///
+    /// * `result` - `hit`, `miss` or `bypass`
+    pub fn response_cache(&self, result: &str) {
+        self.response_cache.with_label_values(&[result]).inc();
+    }
+
+    /// Counts a request answered by a fallback.
+    ///
+    /// # Arguments
+    ///
+    /// * `requested_model` - The model the request was addressed to
+    /// * `model` - The model that answered it
+    pub fn fallback(&self, requested_model: &str, model: &str) {
+        self.fallbacks.with_label_values(&[requested_model, model]).inc();
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            tracing::warn!("Failed to encode metrics: {}", e);
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+/// Estimates thinking tokens from the length of thinking text.
+pub fn estimate_thinking_tokens(thinking_chars: usize) -> u32 {
+    (thinking_chars as u32).div_ceil(4)
+}
+
+/// Middleware counting every request by route, model and status.
+///
+/// The model label comes from the [`ResponseModel`] extension set by
+/// chat handlers, limited to reported models, and is `none` for other
+/// routes.
+pub async fn track_requests(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let started = Instant::now();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let response = next.run(request).await;
+
+    let model = response
+        .extensions()
+        .get::<ResponseModel>()
+        .map_or("none", |m| state.metrics.model_label(&m.0));
+    state
+        .metrics
+        .requests
+        .with_label_values(&[&route, model, response.status().as_str()])
+        .inc();
+    state
+        .metrics
+        .request_duration
+        .with_label_values(&[&route])
+        .observe(started.elapsed().as_secs_f64());
+
+    response
+}
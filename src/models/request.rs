@@ -3,6 +3,7 @@
 //! This module defines the structures used to represent incoming API requests,
 //! including chat messages, configuration options, and request parameters.
 
+use super::response::ContentBlock;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -18,11 +19,21 @@ pub struct ApiRequest {
     #[serde(default)]
     pub verbose: bool,
     
-    pub system: Option<String>,
+    pub system: Option<MessageContent>,
     pub messages: Vec<Message>,
     
     #[serde(default)]
     pub anthropic_config: ApiConfig,
+
+    /// Place prompt cache breakpoints automatically; defaults to
+    /// `prompt_cache.auto`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_cache: Option<bool>,
+
+    /// Stored conversation to continue; `messages` then holds only the new
+    /// turn
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conversation_id: Option<String>,
 }
 
 /// A single message in a chat conversation.
@@ -32,7 +43,31 @@ pub struct ApiRequest {
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Message {
     pub role: Role,
-    pub content: String,
+    pub content: MessageContent,
+}
+
+/// Content of a message.
+///
+/// Either plain text, or a list of content blocks such as the signed
+/// thinking blocks of an earlier assistant turn, which Anthropic accepts
+/// back unchanged.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Blocks(Vec<ContentBlock>),
+}
+
+/// A prompt cache breakpoint on a content block.
+///
+/// Anthropic caches the request prefix up to and including the block.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct CacheControl {
+    #[serde(rename = "type")]
+    pub cache_type: String,
+    /// Cache lifetime, e.g. `5m` or `1h`; Anthropic's default when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<String>,
 }
 
 /// Possible roles for a message in a chat conversation.
@@ -47,6 +82,18 @@ pub enum Role {
     Assistant,
 }
 
+/// Request body for creating a stored conversation.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CreateConversationRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    /// Messages to start the conversation with
+    #[serde(default)]
+    pub messages: Vec<Message>,
+}
+
 /// Configuration options for external API requests.
 ///
 /// Contains headers and body parameters that will be passed
@@ -108,13 +155,213 @@ impl ApiRequest {
     ///
     /// # Returns
     ///
-    /// * `Option<&str>` - The system prompt if found, None otherwise
-    pub fn get_system_prompt(&self) -> Option<&str> {
-        self.system.as_deref().or_else(|| {
+    /// * `Option<&MessageContent>` - The system prompt if found, None otherwise
+    pub fn get_system_prompt(&self) -> Option<&MessageContent> {
+        self.system.as_ref().or_else(|| {
             self.messages
                 .iter()
                 .find(|msg| matches!(msg.role, Role::System))
-                .map(|msg| msg.content.as_str())
+                .map(|msg| &msg.content)
         })
     }
+
+    /// Places prompt cache breakpoints on the system prompt and on the
+    /// last stable turn, the message before the new user message, so that
+    /// the next request in the conversation reads the history from the
+    /// cache.
+    ///
+    /// Requests that already set `cache_control` anywhere are left as they
+    /// are.
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - The number of breakpoints placed
+    pub fn add_cache_breakpoints(&mut self) -> usize {
+        if self.has_cache_control() {
+            return 0;
+        }
+
+        let mut placed = 0;
+        if let Some(system) = self
+            .system
+            .as_mut()
+            .or_else(|| {
+                self.messages
+                    .iter_mut()
+                    .find(|msg| msg.role == Role::System)
+                    .map(|msg| &mut msg.content)
+            })
+        {
+            placed += usize::from(system.set_cache_breakpoint());
+        }
+
+        let turns: Vec<usize> = (0..self.messages.len())
+            .filter(|&index| self.messages[index].role != Role::System)
+            .collect();
+        if let [.., stable, _] = turns[..] {
+            placed += usize::from(self.messages[stable].content.set_cache_breakpoint());
+        }
+        placed
+    }
+
+    /// Returns whether the system prompt or any message sets
+    /// `cache_control`.
+    pub fn has_cache_control(&self) -> bool {
+        self.system
+            .iter()
+            .chain(self.messages.iter().map(|msg| &msg.content))
+            .any(MessageContent::has_cache_control)
+    }
+
+    /// Returns the model requested in `anthropic_config.body`, if any.
+    pub fn model(&self) -> Option<&str> {
+        self.anthropic_config.body.get("model").and_then(|v| v.as_str())
+    }
+
+    /// Returns the `max_tokens` requested in `anthropic_config.body`, if any.
+    pub fn max_tokens(&self) -> Option<u32> {
+        self.anthropic_config
+            .body
+            .get("max_tokens")
+            .and_then(|v| v.as_u64())
+            .map(|v| u32::try_from(v).unwrap_or(u32::MAX))
+    }
+
+    /// Returns the thinking `budget_tokens` requested in
+    /// `anthropic_config.body`, if any.
+    pub fn thinking_budget(&self) -> Option<u32> {
+        self.anthropic_config
+            .body
+            .get("thinking")
+            .and_then(|t| t.get("budget_tokens"))
+            .and_then(|v| v.as_u64())
+            .map(|v| u32::try_from(v).unwrap_or(u32::MAX))
+    }
+
+    /// Roughly estimates the number of input tokens in this request.
+    ///
+    /// Uses the common approximation of four characters per token over
+    /// the system prompt and all message contents. Intended for
+    /// pre-flight checks, not billing.
+    ///
+    /// # Returns
+    ///
+    /// * `u32` - The approximate input token count
+    pub fn approximate_input_tokens(&self) -> u32 {
+        let chars: usize = self.system.as_ref().map_or(0, MessageContent::text_len)
+            + self.messages.iter().map(|msg| msg.content.text_len()).sum::<usize>();
+        (chars as u32).div_ceil(4)
+    }
+}
+
+impl MessageContent {
+    /// Returns the content if it is plain text.
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            MessageContent::Text(text) => Some(text),
+            MessageContent::Blocks(_) => None,
+        }
+    }
+
+    /// Returns the text of the content, without thinking.
+    pub fn text(&self) -> String {
+        match self {
+            MessageContent::Text(text) => text.clone(),
+            MessageContent::Blocks(blocks) => blocks
+                .iter()
+                .filter(|block| block.content_type == "text")
+                .map(|block| block.text.as_str())
+                .collect(),
+        }
+    }
+
+    /// Returns the number of bytes of text and thinking in the content,
+    /// for size estimates.
+    pub fn text_len(&self) -> usize {
+        match self {
+            MessageContent::Text(text) => text.len(),
+            MessageContent::Blocks(blocks) => blocks
+                .iter()
+                .map(|block| block.text.len() + block.thinking.as_ref().map_or(0, String::len))
+                .sum(),
+        }
+    }
+
+    /// Returns whether any block of the content has `cache_control` set.
+    pub fn has_cache_control(&self) -> bool {
+        match self {
+            MessageContent::Text(_) => false,
+            MessageContent::Blocks(blocks) => {
+                blocks.iter().any(|block| block.cache_control.is_some())
+            }
+        }
+    }
+
+    /// Sets an ephemeral cache breakpoint on the last block that can carry
+    /// one, turning plain text into a text block.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Whether a breakpoint was set; thinking blocks and empty
+    ///   text cannot carry one
+    pub fn set_cache_breakpoint(&mut self) -> bool {
+        if let MessageContent::Text(text) = self {
+            if text.is_empty() {
+                return false;
+            }
+            *self = MessageContent::Blocks(vec![ContentBlock::text(std::mem::take(text))]);
+        }
+
+        let MessageContent::Blocks(blocks) = self else {
+            return false;
+        };
+        match blocks
+            .iter_mut()
+            .rev()
+            .find(|block| !matches!(block.content_type.as_str(), "thinking" | "redacted_thinking"))
+        {
+            Some(block) => {
+                block.cache_control = Some(CacheControl {
+                    cache_type: "ephemeral".to_string(),
+                    ttl: None,
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Appends a paragraph of text to the content.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The text to append
+    pub fn push_text(&mut self, text: &str) {
+        match self {
+            MessageContent::Text(existing) if existing.is_empty() => existing.push_str(text),
+            MessageContent::Text(existing) => {
+                existing.push_str("\n\n");
+                existing.push_str(text);
+            }
+            MessageContent::Blocks(blocks) => blocks.push(ContentBlock::text(text)),
+        }
+    }
+}
+
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        MessageContent::Text(text)
+    }
+}
+
+impl From<&str> for MessageContent {
+    fn from(text: &str) -> Self {
+        MessageContent::Text(text.to_string())
+    }
+}
+
+impl From<Vec<ContentBlock>> for MessageContent {
+    fn from(blocks: Vec<ContentBlock>) -> Self {
+        MessageContent::Blocks(blocks)
+    }
 }
@@ -3,15 +3,16 @@
 //! This module defines the structures used to represent API responses,
 //! including chat completions, usage statistics, and streaming events.
 
+use super::request::{CacheControl, Message};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 /// Primary response structure for chat API endpoints.
 ///
 /// Contains the complete response from the Anthropic API, including
 /// content blocks, usage statistics, and optional raw API responses.
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ApiResponse {
     pub created: DateTime<Utc>,
     pub content: Vec<ContentBlock>,
@@ -20,6 +21,12 @@ pub struct ApiResponse {
     pub anthropic_response: Option<ExternalApiResponse>,
 
     pub combined_usage: CombinedUsage,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<ContextManagement>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<Fallback>,
 }
 
 /// A block of content in a response.
@@ -38,32 +45,92 @@ pub struct ContentBlock {
     pub signature: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub data: Option<String>,
+    /// Prompt cache breakpoint, on blocks sent in requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_control: Option<CacheControl>,
 }
 
 /// Raw response from an external API.
 ///
 /// Contains the complete response details from an external API
-/// call, including status code, headers, and response body.
-#[derive(Debug, Serialize, Clone)]
+/// call, including status code, headers, timing and response body, along
+/// with the ids correlating it with the request that caused it. The body is
+/// omitted for streamed responses.
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ExternalApiResponse {
     pub status: u16,
     pub headers: HashMap<String, String>,
+    pub timing: UpstreamTiming,
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
     pub body: serde_json::Value,
+    pub request_id: String,               // our id for the incoming request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_request_id: Option<String>, // the external API's id for its request
+}
+
+/// Timing of an upstream API call, in milliseconds.
+///
+/// `headers_ms` is zero for responses replayed from a cassette.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub struct UpstreamTiming {
+    /// Time until the response headers arrived
+    pub headers_ms: u64,
+    /// Time until the whole body was read; not known for streamed responses
+    /// when the stream starts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_ms: Option<u64>,
+}
+
+/// What was done to a request to fit it in the model's context window.
+///
+/// Token counts are the same rough estimates used for pre-flight checks.
+/// `summary_cost` is only present when older messages were summarized, and
+/// is charged on top of the request's own cost.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ContextManagement {
+    /// The strategies that changed the request, in the order applied
+    pub applied: Vec<String>,
+    pub estimated_tokens_before: u32,
+    pub estimated_tokens_after: u32,
+    pub tokens_saved: u32,
+    pub messages_removed: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary_cost: Option<String>,
+}
+
+/// How a request was served after the requested model failed upstream.
+///
+/// Usage and cost are those of `model`, the model that answered.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Fallback {
+    pub requested_model: String,
+    pub model: String,
+    /// Whether the answering request used extended thinking
+    pub thinking: bool,
+    /// The errors of the failed attempts, in order
+    pub errors: Vec<String>,
 }
 
 /// Usage statistics for API calls.
 ///
 /// Contains token usage and cost information from
 /// Anthropic API calls.
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CombinedUsage {
     pub total_cost: String,
+    /// `total_cost` in dollars, unrounded, for summing costs
+    #[serde(default)]
+    pub cost_usd: f64,
     pub anthropic_usage: AnthropicUsage,
+    /// Served from the response cache, at no cost
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cache_hit: bool,
 }
 
 /// Usage statistics for DeepSeek API calls (deprecated).
 ///
 /// Kept for backwards compatibility but no longer actively used.
+#[allow(dead_code)]
 #[derive(Debug, Serialize, Clone)]
 pub struct DeepSeekUsage {
     pub input_tokens: u32,
@@ -78,7 +145,7 @@ pub struct DeepSeekUsage {
 ///
 /// Tracks token consumption and costs specific to
 /// Anthropic model usage.
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AnthropicUsage {
     pub input_tokens: u32,
     pub output_tokens: u32,
@@ -86,6 +153,9 @@ pub struct AnthropicUsage {
     pub cached_read_tokens: u32,
     pub total_tokens: u32,
     pub total_cost: String,
+    /// Share of the prompt read from the prompt cache, from 0 to 1
+    #[serde(default)]
+    pub cache_hit_rate: f64,
 }
 
 // Streaming event types
@@ -94,11 +164,22 @@ pub struct AnthropicUsage {
 /// Represents different types of events that can occur
 /// during a streaming response, including content updates
 /// and usage statistics.
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum StreamEvent {
     #[serde(rename = "start")]
-    Start { created: DateTime<Utc> },
+    Start {
+        created: DateTime<Utc>,
+        request_id: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        upstream_request_id: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        anthropic_response: Option<ExternalApiResponse>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        context: Option<Box<ContextManagement>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        fallback: Option<Box<Fallback>>,
+    },
 
     #[serde(rename = "content")]
     Content { content: Vec<ContentBlock> },
@@ -113,7 +194,78 @@ pub enum StreamEvent {
     Done,
 
     #[serde(rename = "error")]
-    Error { message: String, code: u16 },
+    Error {
+        message: String,
+        code: u16,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error_type: Option<String>,
+    },
+}
+
+/// Response body for the token counting endpoint.
+///
+/// `method` is `anthropic` when Anthropic counted the tokens, or
+/// `approximate` when it could not be reached and the count is a local
+/// estimate. `worst_case_cost` assumes the full `max_output_tokens` are used.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TokenCountResponse {
+    pub model: String,
+    pub input_tokens: u32,
+    pub method: String,
+    pub max_output_tokens: u32,
+    pub worst_case_cost: String,
+}
+
+/// Response body for the liveness endpoint.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HealthResponse {
+    pub status: String,
+}
+
+/// Response body for the readiness endpoint.
+///
+/// `checks` maps each readiness check to `ok`, `skipped`, or an
+/// error description.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReadinessResponse {
+    pub status: String,
+    pub checks: BTreeMap<String, String>,
+}
+
+/// Response body for the version endpoint.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct VersionResponse {
+    pub name: String,
+    pub version: String,
+    pub git_sha: String,
+    pub features: Vec<String>,
+}
+
+/// A conversation stored on the server.
+///
+/// Assistant messages keep their content blocks, including signed thinking,
+/// so that the history can be sent back to Anthropic unchanged.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Conversation {
+    pub id: String,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    pub messages: Vec<Message>,
+}
+
+/// A stored conversation without its messages, as listed.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ConversationSummary {
+    pub id: String,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub message_count: usize,
 }
 
 impl ContentBlock {
@@ -133,6 +285,7 @@ impl ContentBlock {
             thinking: None,
             signature: None,
             data: None,
+            cache_control: None,
         }
     }
 
@@ -152,6 +305,20 @@ impl ContentBlock {
             thinking: block.thinking,
             signature: block.signature,
             data: block.data,
+            cache_control: None,
+        }
+    }
+}
+
+impl Conversation {
+    /// Summarizes the conversation for listing.
+    pub fn summary(&self) -> ConversationSummary {
+        ConversationSummary {
+            id: self.id.clone(),
+            created: self.created,
+            updated: self.updated,
+            title: self.title.clone(),
+            message_count: self.messages.len(),
         }
     }
 }
@@ -166,7 +333,6 @@ impl ApiResponse {
     /// # Returns
     ///
     /// A new `ApiResponse` with default values and the provided content
-    #[allow(dead_code)]
     pub fn new(content: impl Into<String>) -> Self {
         Self {
             created: Utc::now(),
@@ -174,6 +340,7 @@ impl ApiResponse {
             anthropic_response: None,
             combined_usage: CombinedUsage {
                 total_cost: "$0.00".to_string(),
+                cost_usd: 0.0,
                 anthropic_usage: AnthropicUsage {
                     input_tokens: 0,
                     output_tokens: 0,
@@ -181,10 +348,31 @@ impl ApiResponse {
                     cached_read_tokens: 0,
                     total_tokens: 0,
                     total_cost: "$0.00".to_string(),
+                    cache_hit_rate: 0.0,
                 },
+                cache_hit: false,
             },
+            context: None,
+            fallback: None,
         }
     }
+
+    /// Returns the concatenated text of the response, without thinking.
+    pub fn text(&self) -> String {
+        self.content
+            .iter()
+            .filter(|block| block.content_type == "text")
+            .map(|block| block.text.as_str())
+            .collect()
+    }
+
+    /// Returns the concatenated thinking of the response.
+    pub fn thinking(&self) -> String {
+        self.content
+            .iter()
+            .filter_map(|block| block.thinking.as_deref())
+            .collect()
+    }
 }
 
 impl AnthropicUsage {
@@ -205,6 +393,22 @@ impl AnthropicUsage {
             cached_read_tokens: usage.cache_read_input_tokens,
             total_tokens: usage.input_tokens + usage.output_tokens,
             total_cost: "$0.00".to_string(), // Cost will be calculated later
+            cache_hit_rate: cache_hit_rate(&usage),
         }
     }
 }
+
+/// Returns the share of a prompt read from the prompt cache, rounded to
+/// three decimal places.
+///
+/// Anthropic reports cached tokens separately from `input_tokens`, so the
+/// prompt is the sum of all three.
+fn cache_hit_rate(usage: &crate::clients::anthropic::Usage) -> f64 {
+    let prompt_tokens = usage.input_tokens
+        + usage.cache_creation_input_tokens
+        + usage.cache_read_input_tokens;
+    if prompt_tokens == 0 {
+        return 0.0;
+    }
+    (usage.cache_read_input_tokens as f64 / prompt_tokens as f64 * 1000.0).round() / 1000.0
+}
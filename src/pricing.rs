@@ -0,0 +1,130 @@
+//! Cost calculation.
+//!
+//! Prices come from the `[pricing]` configuration, in dollars per million
+//! tokens. Costs are computed from the token usage Anthropic reports once a
+//! response completes, and estimated up front from the request when
+//! checking spend budgets.
+
+use crate::{
+    clients::anthropic::{default_max_tokens, DEFAULT_MODEL},
+    config::Config,
+    models::ApiRequest,
+};
+
+/// Calculates the cost of Anthropic API usage.
+///
+/// # Arguments
+///
+/// * `model` - The specific Claude model used
+/// * `input_tokens` - Number of input tokens processed
+/// * `output_tokens` - Number of output tokens generated
+/// * `cache_write_tokens` - Number of tokens written to cache
+/// * `cache_read_tokens` - Number of tokens read from cache
+/// * `config` - Configuration containing pricing information
+///
+/// # Returns
+///
+/// The total cost in dollars for the API usage
+pub fn calculate_anthropic_cost(
+    model: &str,
+    input_tokens: u32,
+    output_tokens: u32,
+    cache_write_tokens: u32,
+    cache_read_tokens: u32,
+    config: &Config,
+) -> f64 {
+    let pricing = config.pricing.anthropic.for_model(model);
+
+    let input_cost = (input_tokens as f64 / 1_000_000.0) * pricing.input_price;
+    let output_cost = (output_tokens as f64 / 1_000_000.0) * pricing.output_price;
+    let cache_write_cost = (cache_write_tokens as f64 / 1_000_000.0) * pricing.cache_write_price;
+    let cache_read_cost = (cache_read_tokens as f64 / 1_000_000.0) * pricing.cache_read_price;
+
+    input_cost + output_cost + cache_write_cost + cache_read_cost
+}
+
+/// Formats a cost value as a dollar amount string.
+///
+/// # Arguments
+///
+/// * `cost` - The cost value to format
+///
+/// # Returns
+///
+/// A string representing the cost with 3 decimal places and $ prefix
+pub fn format_cost(cost: f64) -> String {
+    format!("${:.3}", cost)
+}
+
+/// Estimates the most a request could cost.
+///
+/// Deliberately conservative: assumes the full `max_tokens` and the full
+/// thinking budget are both used on top of the approximate input size.
+///
+/// # Arguments
+///
+/// * `request` - The incoming chat request
+/// * `config` - Configuration containing pricing information
+///
+/// # Returns
+///
+/// The worst-case cost in dollars
+pub fn estimate_worst_case_cost(request: &ApiRequest, config: &Config) -> f64 {
+    worst_case_cost(request, request.approximate_input_tokens(), config)
+}
+
+/// Calculates the most a request with a known input size could cost.
+///
+/// Input that may be written to the prompt cache is priced at the cache
+/// write price if that is higher, and the most expensive model of the
+/// request's fallback chain is assumed.
+///
+/// # Arguments
+///
+/// * `request` - The incoming chat request
+/// * `input_tokens` - The request's input tokens
+/// * `config` - Configuration containing pricing and fallback information
+///
+/// # Returns
+///
+/// The worst-case cost in dollars
+pub fn worst_case_cost(request: &ApiRequest, input_tokens: u32, config: &Config) -> f64 {
+    let requested = request.model().unwrap_or(DEFAULT_MODEL);
+    let writes_cache =
+        request.has_cache_control() || request.auto_cache.unwrap_or(config.prompt_cache.auto);
+    let output_tokens = worst_case_output_tokens(request);
+
+    let fallback_models = config
+        .fallbacks
+        .chains
+        .get(requested)
+        .into_iter()
+        .flatten()
+        .filter_map(|step| step.model.as_deref());
+    std::iter::once(requested)
+        .chain(fallback_models)
+        .map(|model| {
+            let pricing = config.pricing.anthropic.for_model(model);
+            let input_price = if writes_cache {
+                pricing.input_price.max(pricing.cache_write_price)
+            } else {
+                pricing.input_price
+            };
+            (input_tokens as f64 / 1_000_000.0) * input_price
+                + (output_tokens as f64 / 1_000_000.0) * pricing.output_price
+        })
+        .fold(0.0, f64::max)
+}
+
+/// Returns the most output tokens a request could be billed for: the full
+/// `max_tokens`, which already includes any thinking.
+///
+/// # Arguments
+///
+/// * `request` - The incoming chat request
+pub fn worst_case_output_tokens(request: &ApiRequest) -> u32 {
+    let model = request.model().unwrap_or(DEFAULT_MODEL);
+    request
+        .max_tokens()
+        .unwrap_or_else(|| default_max_tokens(model))
+}
@@ -0,0 +1,351 @@
+//! Per-caller admission control.
+//!
+//! This module implements token-bucket rate limiting keyed by caller
+//! identity. Each caller has independent buckets for:
+//! - Requests per minute
+//! - Input + output tokens per minute
+//! - Concurrently open streams
+//!
+//! Limits are passed in on every check rather than captured at construction,
+//! so changes to the configured limits take effect immediately.
+//!
+//! [`UpstreamLimits`] additionally tracks the rate limits Anthropic reports
+//! for each upstream API key, so requests that would be rejected upstream
+//! can be held back until the exhausted limit resets.
+
+use crate::{
+    clients::AnthropicRateLimits,
+    config::LimitsConfig,
+    error::{ApiError, Result},
+};
+use chrono::{DateTime, Utc};
+use axum::http::{HeaderMap, HeaderValue};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Number of tracked callers above which idle entries are pruned.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// How long a caller must be idle before its entry may be pruned.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Tracks rate limit state for every caller seen by the server.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    callers: Mutex<HashMap<String, CallerState>>,
+}
+
+/// Rate limit state for a single caller.
+#[derive(Debug, Default)]
+struct CallerState {
+    requests: TokenBucket,
+    tokens: TokenBucket,
+    active_streams: Arc<AtomicU32>,
+}
+
+/// A bucket that refills continuously at `capacity` units per minute.
+///
+/// The level starts out full and may go negative when usage is recorded
+/// after the fact, in which case the caller has to wait for it to refill.
+#[derive(Debug)]
+struct TokenBucket {
+    level: Option<f64>,
+    updated: Instant,
+}
+
+impl Default for TokenBucket {
+    fn default() -> Self {
+        Self {
+            level: None,
+            updated: Instant::now(),
+        }
+    }
+}
+
+impl TokenBucket {
+    /// Refills the bucket for the time elapsed since the last update and
+    /// returns the current level.
+    fn refill(&mut self, capacity: u32, now: Instant) -> f64 {
+        let capacity = capacity as f64;
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        let level = match self.level {
+            Some(level) => (level + elapsed * capacity / 60.0).min(capacity),
+            None => capacity,
+        };
+        self.level = Some(level);
+        self.updated = now;
+        level
+    }
+
+    /// Seconds until the bucket holds at least `needed` units.
+    fn seconds_until(&self, needed: f64, capacity: u32) -> u64 {
+        let level = self.level.unwrap_or(capacity as f64);
+        if level >= needed || capacity == 0 {
+            return 0;
+        }
+        ((needed - level) * 60.0 / capacity as f64).ceil() as u64
+    }
+
+    fn take(&mut self, amount: f64) {
+        if let Some(level) = self.level.as_mut() {
+            *level -= amount;
+        }
+    }
+}
+
+/// Snapshot of a caller's limits after an admission decision.
+///
+/// Fields are `None` when the corresponding limit is not configured.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimitStatus {
+    pub limit_requests: Option<u32>,
+    pub remaining_requests: Option<u32>,
+    pub reset_requests: Option<u64>,
+    pub limit_tokens: Option<u32>,
+    pub remaining_tokens: Option<u32>,
+    pub reset_tokens: Option<u64>,
+}
+
+impl RateLimitStatus {
+    /// Writes the `x-ratelimit-*` headers describing this status.
+    ///
+    /// # Arguments
+    ///
+    /// * `headers` - The response headers to add the rate limit headers to
+    pub fn apply_headers(&self, headers: &mut HeaderMap) {
+        let values = [
+            ("x-ratelimit-limit-requests", self.limit_requests.map(u64::from)),
+            ("x-ratelimit-remaining-requests", self.remaining_requests.map(u64::from)),
+            ("x-ratelimit-reset-requests", self.reset_requests),
+            ("x-ratelimit-limit-tokens", self.limit_tokens.map(u64::from)),
+            ("x-ratelimit-remaining-tokens", self.remaining_tokens.map(u64::from)),
+            ("x-ratelimit-reset-tokens", self.reset_tokens),
+        ];
+
+        for (name, value) in values {
+            if let Some(value) = value {
+                headers.insert(name, HeaderValue::from(value));
+            }
+        }
+    }
+}
+
+/// Holds one of a caller's concurrent stream slots.
+///
+/// The slot is released when the permit is dropped.
+#[derive(Debug)]
+pub struct StreamPermit {
+    active_streams: Arc<AtomicU32>,
+}
+
+impl Drop for StreamPermit {
+    fn drop(&mut self) {
+        self.active_streams.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Result of a successful admission check.
+#[derive(Debug)]
+pub struct Admission {
+    pub status: RateLimitStatus,
+    pub permit: Option<StreamPermit>,
+}
+
+impl RateLimiter {
+    /// Creates an empty rate limiter.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks whether a caller may start a new request and, if so,
+    /// consumes one request from its budget.
+    ///
+    /// # Arguments
+    ///
+    /// * `key_id` - Identity of the caller
+    /// * `limits` - The currently configured limits
+    /// * `stream` - Whether the request opens a stream that counts
+    ///   towards the concurrent stream limit
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Admission>` - The caller's limit status, and a stream permit
+    ///   for streaming requests
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::RateLimited` if any configured limit is exhausted
+    pub fn check(&self, key_id: &str, limits: &LimitsConfig, stream: bool) -> Result<Admission> {
+        let now = Instant::now();
+        let mut callers = self.callers.lock().unwrap_or_else(|e| e.into_inner());
+
+        if callers.len() > PRUNE_THRESHOLD {
+            callers.retain(|_, caller| {
+                caller.active_streams.load(Ordering::SeqCst) > 0
+                    || now.saturating_duration_since(caller.requests.updated) < IDLE_TIMEOUT
+            });
+        }
+
+        let caller = callers.entry(key_id.to_string()).or_default();
+        let mut status = RateLimitStatus::default();
+
+        if let Some(limit) = limits.requests_per_minute {
+            let level = caller.requests.refill(limit, now);
+            status.limit_requests = Some(limit);
+            status.remaining_requests = Some(level.max(0.0) as u32);
+            status.reset_requests = Some(caller.requests.seconds_until(limit as f64, limit));
+            if level < 1.0 {
+                let retry_after = caller.requests.seconds_until(1.0, limit).max(1);
+                return Err(rate_limited("Request rate limit exceeded", retry_after, status));
+            }
+        }
+
+        if let Some(limit) = limits.tokens_per_minute {
+            let level = caller.tokens.refill(limit, now);
+            status.limit_tokens = Some(limit);
+            status.remaining_tokens = Some(level.max(0.0) as u32);
+            status.reset_tokens = Some(caller.tokens.seconds_until(limit as f64, limit));
+            if level <= 0.0 {
+                let retry_after = caller.tokens.seconds_until(1.0, limit).max(1);
+                return Err(rate_limited("Token rate limit exceeded", retry_after, status));
+            }
+        }
+
+        let permit = if stream {
+            if let Some(limit) = limits.max_concurrent_streams {
+                if caller.active_streams.load(Ordering::SeqCst) >= limit {
+                    return Err(rate_limited(
+                        &format!("Too many concurrent streams (limit {})", limit),
+                        1,
+                        status,
+                    ));
+                }
+            }
+            caller.active_streams.fetch_add(1, Ordering::SeqCst);
+            Some(StreamPermit {
+                active_streams: caller.active_streams.clone(),
+            })
+        } else {
+            None
+        };
+
+        if limits.requests_per_minute.is_some() {
+            caller.requests.take(1.0);
+            status.remaining_requests = status.remaining_requests.map(|r| r.saturating_sub(1));
+        }
+
+        Ok(Admission { status, permit })
+    }
+
+    /// Deducts consumed tokens from a caller's token budget.
+    ///
+    /// Token usage is only known once a response completes, so this may
+    /// push the bucket below zero and delay the caller's next request.
+    ///
+    /// # Arguments
+    ///
+    /// * `key_id` - Identity of the caller
+    /// * `limits` - The currently configured limits
+    /// * `tokens` - Number of input and output tokens consumed
+    pub fn record_tokens(&self, key_id: &str, limits: &LimitsConfig, tokens: u32) {
+        let Some(limit) = limits.tokens_per_minute else {
+            return;
+        };
+
+        let mut callers = self.callers.lock().unwrap_or_else(|e| e.into_inner());
+        let caller = callers.entry(key_id.to_string()).or_default();
+        caller.tokens.refill(limit, Instant::now());
+        caller.tokens.take(tokens as f64);
+    }
+}
+
+fn rate_limited(message: &str, retry_after: u64, status: RateLimitStatus) -> ApiError {
+    ApiError::RateLimited {
+        message: message.to_string(),
+        retry_after,
+        status,
+    }
+}
+
+/// Rate limits last reported by Anthropic for each upstream API key.
+///
+/// Keys are tracked by the same hashed identity as callers, since callers
+/// authenticate with their own Anthropic API key.
+#[derive(Debug, Default)]
+pub struct UpstreamLimits {
+    keys: Mutex<HashMap<String, UpstreamKeyState>>,
+}
+
+/// Latest rate limit report for a single upstream key.
+#[derive(Debug, Clone)]
+struct UpstreamKeyState {
+    limits: AnthropicRateLimits,
+    received: DateTime<Utc>,
+}
+
+impl UpstreamKeyState {
+    /// Returns when the key may be used again, if it is held back now.
+    fn blocked_until(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.limits.blocked_until(self.received).filter(|until| *until > now)
+    }
+}
+
+impl UpstreamLimits {
+    /// Creates an empty tracker.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the rate limits reported on an upstream response.
+    ///
+    /// # Arguments
+    ///
+    /// * `key_id` - Identity of the upstream API key
+    /// * `limits` - The limits parsed from the response headers
+    pub fn record(&self, key_id: &str, limits: AnthropicRateLimits) {
+        let now = Utc::now();
+        let mut keys = self.keys.lock().unwrap_or_else(|e| e.into_inner());
+
+        if keys.len() > PRUNE_THRESHOLD {
+            keys.retain(|_, key| key.blocked_until(now).is_some());
+        }
+
+        let key = UpstreamKeyState { limits, received: now };
+        if let Some(until) = key.blocked_until(now) {
+            tracing::warn!(key_id, until = %until, "Anthropic rate limit exhausted, backing off");
+        }
+        keys.insert(key_id.to_string(), key);
+    }
+
+    /// Checks whether a request may be sent with an upstream key.
+    ///
+    /// # Arguments
+    ///
+    /// * `key_id` - Identity of the upstream API key
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::RateLimited` while one of the key's Anthropic rate
+    /// limits is exhausted, with `retry_after` set to the time until it
+    /// resets
+    pub fn check(&self, key_id: &str) -> Result<()> {
+        let now = Utc::now();
+        let keys = self.keys.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(until) = keys.get(key_id).and_then(|key| key.blocked_until(now)) else {
+            return Ok(());
+        };
+
+        let retry_after = ((until - now).num_milliseconds() as u64).div_ceil(1000).max(1);
+        Err(rate_limited(
+            "Anthropic rate limit exhausted for this API key",
+            retry_after,
+            RateLimitStatus::default(),
+        ))
+    }
+}
@@ -0,0 +1,199 @@
+//! Configuration hot-reload.
+//!
+//! Watches the config file for changes, and listens for `SIGHUP` on Unix,
+//! reloading the configuration when either fires. A reloaded configuration
+//! is validated before it is swapped into the application state; if loading
+//! or validation fails the previous configuration stays in effect. Settings
+//! only read at startup keep their running values until a restart.
+//!
+//! Requests already in flight, including open streams, keep the
+//! configuration snapshot they started with. When TLS is enabled, every
+//! reload also reloads the certificate and key.
+
+use crate::{config::Config, handlers::AppState, tls};
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+/// How often the config file's modification time is checked.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Spawns the background task that reloads configuration.
+///
+/// # Arguments
+///
+/// * `state` - Application state whose configuration is replaced
+/// * `path` - The config file to watch
+/// * `load` - Loads and validates the full layered configuration
+pub fn spawn<F>(state: Arc<AppState>, path: PathBuf, load: F)
+where
+    F: Fn() -> anyhow::Result<Config> + Send + Sync + 'static,
+{
+    tokio::spawn(async move {
+        let mut last_modified = modified(&path);
+        let mut poll = tokio::time::interval(POLL_INTERVAL);
+        let mut hangup = hangup_signal();
+
+        loop {
+            let trigger = tokio::select! {
+                _ = poll.tick() => {
+                    let current = modified(&path);
+                    if current == last_modified {
+                        continue;
+                    }
+                    last_modified = current;
+                    "file change"
+                }
+                _ = recv_hangup(&mut hangup) => "SIGHUP",
+            };
+
+            reload(&state, &load, trigger);
+        }
+    });
+}
+
+/// Loads a new configuration and swaps it in if it is valid.
+fn reload<F>(state: &AppState, load: &F, trigger: &str)
+where
+    F: Fn() -> anyhow::Result<Config>,
+{
+    let new_config = match load() {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::error!("Config reload ({}) failed, keeping previous config: {:#}", trigger, e);
+            return;
+        }
+    };
+
+    // Certificates may have been renewed in place, so reload them even if
+    // the configuration is unchanged
+    if let (Some(tls), Some(tls_config)) = (&state.tls, &new_config.server.tls) {
+        tls::reload(tls.clone(), tls_config.clone());
+    }
+
+    let old_config = state.config();
+    let mut applied = new_config.clone();
+    keep_startup_settings(&old_config, &mut applied, state.tls.is_some());
+
+    let needs_restart = changed_keys(&applied, &new_config);
+    if !needs_restart.is_empty() {
+        tracing::warn!(
+            "Config changes to {} only take effect after a restart",
+            needs_restart.join(", ")
+        );
+    }
+
+    let changed = changed_keys(&old_config, &applied);
+    if changed.is_empty() {
+        tracing::info!("Config reloaded ({}), no changes applied", trigger);
+        return;
+    }
+
+    state.replace_config(applied);
+    tracing::info!("Config reloaded ({}), changed keys: {}", trigger, changed.join(", "));
+}
+
+/// Restores the settings that are only read at startup to their running
+/// values, so that the application state never reports settings that are
+/// not in effect.
+///
+/// # Arguments
+///
+/// * `old` - The configuration currently in effect
+/// * `new` - The reloaded configuration, updated in place
+/// * `tls_enabled` - Whether the server is serving TLS, in which case the
+///   certificate paths are reloadable
+fn keep_startup_settings(old: &Config, new: &mut Config, tls_enabled: bool) {
+    let tls = new.server.tls.take();
+    new.server = old.server.clone();
+    if tls_enabled {
+        new.server.tls = tls;
+    }
+    new.logging = old.logging.clone();
+    new.budgets.store_path = old.budgets.store_path.clone();
+    new.ledger = old.ledger.clone();
+    new.conversations = old.conversations.clone();
+    new.cassettes = old.cassettes.clone();
+    new.response_cache = old.response_cache.clone();
+    new.telemetry = old.telemetry.clone();
+}
+
+/// Lists the dotted paths of every setting that differs between two
+/// configurations. Secrets are compared but never logged by value.
+fn changed_keys(old: &Config, new: &Config) -> Vec<String> {
+    let old = serde_json::to_value(old).unwrap_or_default();
+    let new = serde_json::to_value(new).unwrap_or_default();
+    let mut changed = Vec::new();
+    diff("", &old, &new, &mut changed);
+    changed
+}
+
+fn diff(prefix: &str, old: &serde_json::Value, new: &serde_json::Value, changed: &mut Vec<String>) {
+    match (old, new) {
+        (serde_json::Value::Object(old), serde_json::Value::Object(new)) => {
+            let mut keys: Vec<&String> = old.keys().chain(new.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let path = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                let null = serde_json::Value::Null;
+                diff(
+                    &path,
+                    old.get(key).unwrap_or(&null),
+                    new.get(key).unwrap_or(&null),
+                    changed,
+                );
+            }
+        }
+        (old, new) if old != new => changed.push(prefix.to_string()),
+        _ => {}
+    }
+}
+
+fn modified(path: &PathBuf) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(unix)]
+type HangupSignal = Option<tokio::signal::unix::Signal>;
+
+#[cfg(not(unix))]
+type HangupSignal = ();
+
+#[cfg(unix)]
+fn hangup_signal() -> HangupSignal {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    signal(SignalKind::hangup())
+        .map_err(|e| tracing::warn!("Failed to install SIGHUP handler: {}", e))
+        .ok()
+}
+
+#[cfg(not(unix))]
+fn hangup_signal() -> HangupSignal {}
+
+#[cfg(unix)]
+async fn recv_hangup(signal: &mut HangupSignal) {
+    match signal {
+        Some(stream) => {
+            if stream.recv().await.is_none() {
+                // The signal stream has ended and would return `None`
+                // immediately from now on; stop listening to it.
+                *signal = None;
+                std::future::pending::<()>().await;
+            }
+        }
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(not(unix))]
+async fn recv_hangup(_signal: &mut HangupSignal) {
+    std::future::pending().await
+}
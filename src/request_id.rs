@@ -0,0 +1,53 @@
+//! Request ids.
+//!
+//! Every request is assigned an id, taken from the caller's `x-request-id`
+//! header when it holds a usable value and generated otherwise. The id is
+//! recorded on the request's log span, echoed in the `x-request-id`
+//! response header, and included in chat responses, so a client request
+//! can be matched with server logs and the upstream Anthropic request.
+
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+
+/// Header carrying the request id, on both requests and responses.
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest caller-supplied request id that is accepted.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// The id of the request being served, stored in request extensions.
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+/// Middleware assigning a request id to every request.
+///
+/// A caller-supplied id is kept if it is at most 128 characters of ASCII
+/// letters, digits, `-`, `_`, `.` and `:`; otherwise a UUID is generated.
+pub async fn assign(mut request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| is_valid(value))
+        .map(String::from)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+    request.extensions_mut().insert(RequestId(id.clone()));
+    let mut response = next.run(request).await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+}
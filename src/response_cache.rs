@@ -0,0 +1,282 @@
+//! Exact-match response cache.
+//!
+//! When `[response_cache] enabled` is set, successful Anthropic responses
+//! are cached under the caller's key id and the fingerprint of the final
+//! upstream request, built as a non-streaming request so that streaming and
+//! non-streaming callers share entries. A later request from the same
+//! caller with the same model, messages, system prompt and parameters is
+//! answered from the cache without calling Anthropic, and is not charged.
+//! Entries are never shared between callers, since a hit is served without
+//! the caller's API key reaching Anthropic.
+//!
+//! Entries live in an in-memory LRU and, with `dir` set, also on disk, so
+//! that they survive restarts and can be shared between instances. Disk
+//! reads and writes run on the blocking thread pool. Entries older than
+//! `ttl_secs` are never served, and expired files are deleted.
+
+use crate::{clients::anthropic::AnthropicResponse, config::ResponseCacheConfig};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Header with which a request skips the cache lookup; its response still
+/// replaces the cached one.
+pub const BYPASS_HEADER: &str = "X-DeepClaude-Cache";
+
+/// How often the disk cache is swept for expired entries.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// A cached response and when it was stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    stored: DateTime<Utc>,
+    response: AnthropicResponse,
+}
+
+/// In-memory entries, evicted least recently used first.
+#[derive(Debug, Default)]
+struct Lru {
+    entries: HashMap<String, (CacheEntry, u64)>,
+    /// Keys by the tick of their last use
+    order: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+/// Cache of Anthropic responses; does nothing when disabled.
+#[derive(Debug)]
+pub struct ResponseCache {
+    enabled: bool,
+    capacity: usize,
+    ttl: chrono::Duration,
+    dir: Option<PathBuf>,
+    lru: Mutex<Lru>,
+    /// When the disk cache was last swept for expired entries
+    pruned: Mutex<Option<Instant>>,
+}
+
+impl ResponseCache {
+    /// Creates a cache from configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Response cache settings
+    pub fn new(config: &ResponseCacheConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+            capacity: config.capacity,
+            ttl: chrono::Duration::seconds(config.ttl_secs as i64),
+            dir: config.dir.as_ref().map(PathBuf::from),
+            lru: Mutex::new(Lru::default()),
+            pruned: Mutex::new(None),
+        }
+    }
+
+    /// Returns true if responses are cached.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Looks up the cached response for a request.
+    ///
+    /// Entries found on disk are brought into memory, and expired ones are
+    /// deleted.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - Cache key of the request, from [`key`]
+    ///
+    /// # Returns
+    ///
+    /// * `Option<AnthropicResponse>` - The cached response, if there is one
+    ///   younger than the TTL
+    pub async fn get(&self, key: &str) -> Option<AnthropicResponse> {
+        if !self.enabled {
+            return None;
+        }
+
+        if let Some(response) = self.get_in_memory(key) {
+            return Some(response);
+        }
+
+        let path = self.path(key)?;
+        let ttl = self.ttl;
+        let entry = tokio::task::spawn_blocking(move || load(&path, ttl))
+            .await
+            .ok()
+            .flatten()?;
+        let response = entry.response.clone();
+        self.lru().insert(key.to_string(), entry, self.capacity);
+        Some(response)
+    }
+
+    /// Caches a response.
+    ///
+    /// The entry is written to disk in the background, and disk write
+    /// failures are logged rather than returned so that caching never fails
+    /// the request. Expired entries are swept from disk at most once every
+    /// `PRUNE_INTERVAL`.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - Cache key of the request, from [`key`]
+    /// * `response` - The complete response
+    pub fn put(&self, key: &str, response: &AnthropicResponse) {
+        if !self.enabled {
+            return;
+        }
+
+        let entry = CacheEntry {
+            stored: Utc::now(),
+            response: response.clone(),
+        };
+        if let Some(path) = self.path(key) {
+            let entry = entry.clone();
+            let prune = self.prune_due().then_some(self.ttl);
+            tokio::task::spawn_blocking(move || {
+                store(&path, &entry);
+                if let (Some(ttl), Some(dir)) = (prune, path.parent()) {
+                    prune_expired(dir, ttl);
+                }
+            });
+        }
+        self.lru().insert(key.to_string(), entry, self.capacity);
+    }
+
+    /// Looks up a fresh entry in memory, dropping it if it has expired.
+    fn get_in_memory(&self, key: &str) -> Option<AnthropicResponse> {
+        let mut lru = self.lru();
+        let (entry, _) = lru.entries.get(key)?;
+        if !is_fresh(entry, self.ttl) {
+            lru.remove(key);
+            return None;
+        }
+        let response = entry.response.clone();
+        lru.touch(key);
+        Some(response)
+    }
+
+    fn path(&self, key: &str) -> Option<PathBuf> {
+        self.dir.as_ref().map(|dir| dir.join(format!("{}.json", key)))
+    }
+
+    /// Returns true, once every `PRUNE_INTERVAL`, when the disk cache
+    /// should be swept for expired entries.
+    fn prune_due(&self) -> bool {
+        let mut pruned = self.pruned.lock().unwrap_or_else(|e| e.into_inner());
+        if pruned.is_some_and(|at| at.elapsed() < PRUNE_INTERVAL) {
+            return false;
+        }
+        *pruned = Some(Instant::now());
+        true
+    }
+
+    fn lru(&self) -> std::sync::MutexGuard<'_, Lru> {
+        self.lru.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Returns the cache key of a request made by a caller.
+///
+/// # Arguments
+///
+/// * `key_id` - Identity of the caller
+/// * `fingerprint` - Fingerprint of the upstream request
+pub fn key(key_id: &str, fingerprint: &str) -> String {
+    format!("{}-{}", key_id, fingerprint)
+}
+
+fn is_fresh(entry: &CacheEntry, ttl: chrono::Duration) -> bool {
+    Utc::now() - entry.stored < ttl
+}
+
+/// Reads an entry from disk; missing, unreadable and expired entries are
+/// misses, and expired ones are deleted.
+fn load(path: &Path, ttl: chrono::Duration) -> Option<CacheEntry> {
+    let bytes = std::fs::read(path).ok()?;
+    let entry: CacheEntry = serde_json::from_slice(&bytes)
+        .inspect_err(|e| tracing::warn!("Invalid cached response {}: {}", path.display(), e))
+        .ok()?;
+    if !is_fresh(&entry, ttl) {
+        let _ = std::fs::remove_file(path);
+        return None;
+    }
+    Some(entry)
+}
+
+/// Writes an entry to disk, logging failures.
+fn store(path: &Path, entry: &CacheEntry) {
+    let result = std::fs::create_dir_all(path.parent().unwrap_or(path))
+        .map_err(anyhow::Error::from)
+        .and_then(|_| Ok(serde_json::to_vec(entry)?))
+        .and_then(|bytes| Ok(std::fs::write(path, bytes)?));
+    if let Err(e) = result {
+        tracing::warn!("Failed to write cached response {}: {}", path.display(), e);
+    }
+}
+
+/// Deletes cached responses last written longer than `ttl` ago.
+fn prune_expired(dir: &Path, ttl: chrono::Duration) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let ttl = ttl.to_std().unwrap_or(Duration::MAX);
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let expired = path.extension().is_some_and(|ext| ext == "json")
+            && entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| modified.elapsed().is_ok_and(|age| age >= ttl));
+        if expired && std::fs::remove_file(&path).is_ok() {
+            removed += 1;
+        }
+    }
+    if removed > 0 {
+        tracing::debug!(removed, "Pruned expired cached responses");
+    }
+}
+
+impl Lru {
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    /// Marks an entry as just used.
+    fn touch(&mut self, key: &str) {
+        let tick = self.next_tick();
+        if let Some((_, used)) = self.entries.get_mut(key) {
+            self.order.remove(used);
+            *used = tick;
+            self.order.insert(tick, key.to_string());
+        }
+    }
+
+    /// Inserts an entry, evicting the least recently used ones beyond
+    /// `capacity`.
+    fn insert(&mut self, key: String, entry: CacheEntry, capacity: usize) {
+        self.remove(&key);
+        let tick = self.next_tick();
+        self.order.insert(tick, key.clone());
+        self.entries.insert(key, (entry, tick));
+
+        while self.entries.len() > capacity {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some((_, used)) = self.entries.remove(key) {
+            self.order.remove(&used);
+        }
+    }
+}
@@ -0,0 +1,565 @@
+//! Typed client for the DeepClaude HTTP API.
+//!
+//! Enabled with the `sdk` feature. Requests and responses use the same
+//! types as the server ([`ApiRequest`], [`ApiResponse`], [`StreamEvent`]),
+//! so client and server cannot drift apart.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use deepclaude::sdk::{ChatRequest, DeepClaudeClient};
+//! use deepclaude::models::StreamEvent;
+//! use futures::StreamExt;
+//!
+//! async fn example() -> Result<(), deepclaude::sdk::SdkError> {
+//!     let client = DeepClaudeClient::new("http://127.0.0.1:1337", "sk-ant-...");
+//!     let request = ChatRequest::new()
+//!         .system("Answer briefly.")
+//!         .user("Why is the sky blue?")
+//!         .thinking_budget(4000)
+//!         .build();
+//!
+//!     // Non-streaming request
+//!     let response = client.chat(request.clone()).await?;
+//!     println!("{}", response.combined_usage.total_cost);
+//!
+//!     // Streaming request
+//!     let mut events = client.chat_stream(request).await?;
+//!     while let Some(event) = events.next().await {
+//!         if let StreamEvent::Content { content } = event? {
+//!             print!("{}", content[0].text);
+//!         }
+//!     }
+//!     Ok(())
+//! }
+//! ```
+
+use crate::{
+    error::ErrorResponse,
+    models::{
+        ApiConfig, ApiRequest, ApiResponse, ContentBlock, Conversation, ConversationSummary,
+        CreateConversationRequest, Message, MessageContent, Role, StreamEvent, TokenCountResponse,
+    },
+    sse::SseBuffer,
+};
+use futures::{Stream, StreamExt};
+use std::pin::Pin;
+
+/// Header carrying the caller's Anthropic API key.
+const API_TOKEN_HEADER: &str = "X-Anthropic-API-Token";
+
+/// Header selecting the project a request is billed to.
+const PROJECT_HEADER: &str = "X-DeepClaude-Project";
+
+/// Events of a streamed response.
+pub type ChatEventStream = Pin<Box<dyn Stream<Item = Result<StreamEvent, SdkError>> + Send>>;
+
+/// Errors returned by [`DeepClaudeClient`].
+#[derive(Debug, thiserror::Error)]
+pub enum SdkError {
+    #[error("Request failed: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("DeepClaude API error ({status}): {message}")]
+    Api {
+        status: u16,
+        message: String,
+        type_: String,
+        code: Option<String>,
+    },
+
+    #[error("Stream error ({code}): {message}")]
+    Stream {
+        message: String,
+        code: u16,
+        error_type: Option<String>,
+    },
+
+    #[error("Failed to parse response: {0}")]
+    Decode(#[from] serde_json::Error),
+}
+
+/// Client for a DeepClaude server.
+#[derive(Debug, Clone)]
+pub struct DeepClaudeClient {
+    client: reqwest::Client,
+    base_url: String,
+    api_token: String,
+    project: Option<String>,
+}
+
+impl DeepClaudeClient {
+    /// Creates a client for a DeepClaude server.
+    ///
+    /// # Arguments
+    ///
+    /// * `base_url` - URL of the server, e.g. `http://127.0.0.1:1337`
+    /// * `api_token` - Anthropic API key to authenticate with
+    pub fn new(base_url: impl Into<String>, api_token: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url: base_url.into(),
+            api_token: api_token.into(),
+            project: None,
+        }
+    }
+
+    /// Bills requests to a project, for per-project budgets and usage.
+    ///
+    /// # Arguments
+    ///
+    /// * `project` - The project name
+    ///
+    /// # Returns
+    ///
+    /// The client configured with the given project
+    pub fn with_project(mut self, project: impl Into<String>) -> Self {
+        self.project = Some(project.into());
+        self
+    }
+
+    /// Sends requests with a preconfigured HTTP client, e.g. one with
+    /// timeouts or a proxy.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - The HTTP client to use
+    ///
+    /// # Returns
+    ///
+    /// The client configured with the given HTTP client
+    pub fn with_http_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Sends a chat request and waits for the complete response.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The chat request; `stream` is ignored
+    ///
+    /// # Returns
+    ///
+    /// * `Result<ApiResponse, SdkError>` - The response
+    ///
+    /// # Errors
+    ///
+    /// Returns `SdkError::Api` if the server rejects the request, and
+    /// `SdkError::Http` or `SdkError::Decode` if it cannot be reached or
+    /// its response cannot be parsed
+    pub async fn chat(&self, mut request: ApiRequest) -> Result<ApiResponse, SdkError> {
+        request.stream = false;
+        let response = self.send(&request).await?;
+        Ok(serde_json::from_slice(&response.bytes().await?)?)
+    }
+
+    /// Sends a chat request and streams the response events.
+    ///
+    /// Error events sent by the server end the stream with
+    /// `SdkError::Stream`.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The chat request; `stream` is ignored
+    ///
+    /// # Returns
+    ///
+    /// * `Result<ChatEventStream, SdkError>` - The events, starting with
+    ///   `StreamEvent::Start`
+    ///
+    /// # Errors
+    ///
+    /// Returns `SdkError::Api` if the server rejects the request, and
+    /// `SdkError::Http` if it cannot be reached
+    pub async fn chat_stream(&self, mut request: ApiRequest) -> Result<ChatEventStream, SdkError> {
+        request.stream = true;
+        let response = self.send(&request).await?;
+        let mut body = response.bytes_stream();
+
+        Ok(Box::pin(async_stream::try_stream! {
+            let mut buffer = SseBuffer::default();
+            while let Some(chunk) = body.next().await {
+                buffer.push(&chunk?);
+
+                while let Some(block) = buffer.next_event() {
+                    // Keep-alive comments and other non-data lines are skipped
+                    let Some(json) = block.lines().find_map(|line| line.strip_prefix("data: ")) else {
+                        continue;
+                    };
+                    match serde_json::from_str::<StreamEvent>(json)? {
+                        StreamEvent::Error { message, code, error_type } => {
+                            Err(SdkError::Stream { message, code, error_type })?;
+                        }
+                        event => yield event,
+                    }
+                }
+            }
+        }))
+    }
+
+    /// Sends a streamed chat request and collects it into one response.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The chat request; `stream` is ignored
+    ///
+    /// # Returns
+    ///
+    /// * `Result<ApiResponse, SdkError>` - The response, as [`collect`]
+    ///   assembles it
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from [`DeepClaudeClient::chat_stream`] or the
+    /// stream itself
+    pub async fn chat_streamed(&self, request: ApiRequest) -> Result<ApiResponse, SdkError> {
+        collect(self.chat_stream(request).await?).await
+    }
+
+    /// Counts the input tokens of a chat request without sending it.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The chat request to count
+    ///
+    /// # Returns
+    ///
+    /// * `Result<TokenCountResponse, SdkError>` - The input token count and
+    ///   the request's worst-case cost
+    ///
+    /// # Errors
+    ///
+    /// Returns `SdkError::Api` if the server rejects the request
+    pub async fn count_tokens(&self, request: &ApiRequest) -> Result<TokenCountResponse, SdkError> {
+        let builder = self
+            .request(reqwest::Method::POST, "v1/count_tokens")
+            .json(request);
+        Ok(self.send_request(builder).await?.json().await?)
+    }
+
+    /// Creates a conversation stored on the server.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - Title, system prompt and initial messages
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Conversation, SdkError>` - The new conversation; continue it
+    ///   with [`ChatRequest::conversation`]
+    ///
+    /// # Errors
+    ///
+    /// Returns `SdkError::Api` if the server rejects the request, e.g.
+    /// because conversations are disabled
+    pub async fn create_conversation(
+        &self,
+        request: &CreateConversationRequest,
+    ) -> Result<Conversation, SdkError> {
+        let builder = self
+            .request(reqwest::Method::POST, "conversations")
+            .json(request);
+        Ok(self.send_request(builder).await?.json().await?)
+    }
+
+    /// Lists the conversations stored for this client's API key.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SdkError::Api` if the server rejects the request
+    pub async fn list_conversations(&self) -> Result<Vec<ConversationSummary>, SdkError> {
+        let builder = self.request(reqwest::Method::GET, "conversations");
+        Ok(self.send_request(builder).await?.json().await?)
+    }
+
+    /// Fetches a stored conversation with all its messages.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The conversation id
+    ///
+    /// # Errors
+    ///
+    /// Returns `SdkError::Api` with status 404 if there is no such
+    /// conversation for this client's API key
+    pub async fn get_conversation(&self, id: &str) -> Result<Conversation, SdkError> {
+        let builder = self.request(reqwest::Method::GET, &format!("conversations/{}", id));
+        Ok(self.send_request(builder).await?.json().await?)
+    }
+
+    /// Deletes a stored conversation.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The conversation id
+    ///
+    /// # Errors
+    ///
+    /// Returns `SdkError::Api` with status 404 if there is no such
+    /// conversation for this client's API key
+    pub async fn delete_conversation(&self, id: &str) -> Result<(), SdkError> {
+        let builder = self.request(reqwest::Method::DELETE, &format!("conversations/{}", id));
+        self.send_request(builder).await?;
+        Ok(())
+    }
+
+    /// Posts a request to the chat endpoint.
+    async fn send(&self, request: &ApiRequest) -> Result<reqwest::Response, SdkError> {
+        self.send_request(self.request(reqwest::Method::POST, "").json(request))
+            .await
+    }
+
+    /// Starts a request to a path under the base URL, with the caller's
+    /// credentials.
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let url = if path.is_empty() {
+            self.base_url.clone()
+        } else {
+            format!("{}/{}", self.base_url.trim_end_matches('/'), path)
+        };
+        let mut builder = self
+            .client
+            .request(method, url)
+            .header(API_TOKEN_HEADER, &self.api_token);
+        if let Some(project) = &self.project {
+            builder = builder.header(PROJECT_HEADER, project);
+        }
+        builder
+    }
+
+    /// Sends a request, turning error responses into `SdkError::Api`.
+    async fn send_request(
+        &self,
+        builder: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, SdkError> {
+        let response = builder.send().await?;
+        let status = response.status().as_u16();
+        if response.status().is_success() {
+            return Ok(response);
+        }
+
+        let body = response.bytes().await?;
+        Err(match serde_json::from_slice::<ErrorResponse>(&body) {
+            Ok(ErrorResponse { error }) => SdkError::Api {
+                status,
+                message: error.message,
+                type_: error.type_,
+                code: error.code,
+            },
+            Err(_) => SdkError::Api {
+                status,
+                message: String::from_utf8_lossy(&body).into_owned(),
+                type_: "unknown".to_string(),
+                code: None,
+            },
+        })
+    }
+}
+
+/// Assembles the events of a streamed response into a complete response.
+///
+/// Thinking, signature and text deltas are merged into whole content
+/// blocks, and the final usage report becomes the response usage.
+///
+/// # Arguments
+///
+/// * `events` - The events of a streamed response
+///
+/// # Returns
+///
+/// * `Result<ApiResponse, SdkError>` - The assembled response
+///
+/// # Errors
+///
+/// Returns the first error in the stream
+pub async fn collect(mut events: ChatEventStream) -> Result<ApiResponse, SdkError> {
+    let mut response = ApiResponse::new("");
+    response.content.clear();
+
+    while let Some(event) = events.next().await {
+        match event? {
+            StreamEvent::Start {
+                created,
+                anthropic_response,
+                context,
+                fallback,
+                ..
+            } => {
+                response.created = created;
+                response.anthropic_response = anthropic_response;
+                response.context = context.map(|context| *context);
+                response.fallback = fallback.map(|fallback| *fallback);
+            }
+            StreamEvent::Content { content } => {
+                for delta in content {
+                    merge_delta(&mut response.content, delta);
+                }
+            }
+            StreamEvent::Usage { usage } => response.combined_usage = usage,
+            StreamEvent::MessageStop | StreamEvent::Done => {}
+            StreamEvent::Error {
+                message,
+                code,
+                error_type,
+            } => {
+                return Err(SdkError::Stream {
+                    message,
+                    code,
+                    error_type,
+                });
+            }
+        }
+    }
+
+    Ok(response)
+}
+
+/// Adds a streamed content delta to the blocks assembled so far.
+fn merge_delta(blocks: &mut Vec<ContentBlock>, delta: ContentBlock) {
+    let last = blocks.last_mut();
+    match delta.content_type.as_str() {
+        "thinking_delta" => match last.filter(|block| block.content_type == "thinking") {
+            Some(block) => block
+                .thinking
+                .get_or_insert_with(String::new)
+                .push_str(delta.thinking.as_deref().unwrap_or_default()),
+            None => blocks.push(ContentBlock {
+                content_type: "thinking".to_string(),
+                text: String::new(),
+                ..delta
+            }),
+        },
+        "signature_delta" => {
+            if let Some(block) = last.filter(|block| block.content_type == "thinking") {
+                block.signature = delta.signature;
+            }
+        }
+        "text_delta" => match last.filter(|block| block.content_type == "text") {
+            Some(block) => block.text.push_str(&delta.text),
+            None => blocks.push(ContentBlock::text(delta.text)),
+        },
+        _ => blocks.push(delta),
+    }
+}
+
+/// Builder for chat requests.
+///
+/// Model parameters are passed through to Anthropic in the request's
+/// `anthropic_config.body`.
+#[derive(Debug, Clone, Default)]
+pub struct ChatRequest {
+    system: Option<MessageContent>,
+    messages: Vec<Message>,
+    verbose: bool,
+    config: ApiConfig,
+    conversation_id: Option<String>,
+    auto_cache: Option<bool>,
+}
+
+impl ChatRequest {
+    /// Starts an empty request.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the system prompt, as text or as content blocks carrying
+    /// `cache_control`.
+    pub fn system(mut self, system: impl Into<MessageContent>) -> Self {
+        self.system = Some(system.into());
+        self
+    }
+
+    /// Appends a message to the conversation.
+    pub fn message(mut self, role: Role, content: impl Into<MessageContent>) -> Self {
+        self.messages.push(Message {
+            role,
+            content: content.into(),
+        });
+        self
+    }
+
+    /// Appends a user message to the conversation.
+    pub fn user(self, content: impl Into<MessageContent>) -> Self {
+        self.message(Role::User, content)
+    }
+
+    /// Appends an assistant message to the conversation.
+    pub fn assistant(self, content: impl Into<MessageContent>) -> Self {
+        self.message(Role::Assistant, content)
+    }
+
+    /// Selects the Claude model.
+    pub fn model(self, model: impl Into<String>) -> Self {
+        self.body("model", model.into())
+    }
+
+    /// Sets the maximum number of tokens to generate.
+    pub fn max_tokens(self, max_tokens: u32) -> Self {
+        self.body("max_tokens", max_tokens)
+    }
+
+    /// Sets the extended thinking budget in tokens.
+    pub fn thinking_budget(self, budget_tokens: u32) -> Self {
+        self.body(
+            "thinking",
+            serde_json::json!({ "type": "enabled", "budget_tokens": budget_tokens }),
+        )
+    }
+
+    /// Sets any other Anthropic request parameter.
+    pub fn body(mut self, key: &str, value: impl Into<serde_json::Value>) -> Self {
+        if !self.config.body.is_object() {
+            self.config.body = serde_json::Value::Object(Default::default());
+        }
+        if let Some(body) = self.config.body.as_object_mut() {
+            body.insert(key.to_string(), value.into());
+        }
+        self
+    }
+
+    /// Adds a header to send to Anthropic, e.g. `anthropic-beta`.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.config.headers.insert(name.into(), value.into());
+        self
+    }
+
+    /// Continues a conversation stored on the server; the messages added
+    /// to this request are its new turn.
+    pub fn conversation(mut self, id: impl Into<String>) -> Self {
+        self.conversation_id = Some(id.into());
+        self
+    }
+
+    /// Turns automatic prompt cache breakpoints on or off for this request,
+    /// overriding the server's `prompt_cache.auto` setting.
+    pub fn auto_cache(mut self, auto_cache: bool) -> Self {
+        self.auto_cache = Some(auto_cache);
+        self
+    }
+
+    /// Requests upstream response details in the response.
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Builds the request.
+    pub fn build(self) -> ApiRequest {
+        ApiRequest {
+            stream: false,
+            verbose: self.verbose,
+            system: self.system,
+            messages: self.messages,
+            anthropic_config: self.config,
+            conversation_id: self.conversation_id,
+            auto_cache: self.auto_cache,
+        }
+    }
+}
+
+impl From<ChatRequest> for ApiRequest {
+    fn from(request: ChatRequest) -> Self {
+        request.build()
+    }
+}
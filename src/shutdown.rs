@@ -0,0 +1,151 @@
+//! Graceful shutdown.
+//!
+//! On `SIGTERM` or `SIGINT` the server stops accepting connections and
+//! reports itself not ready, while requests in flight, including open
+//! streams, continue. Streams still open once `server.shutdown_timeout_secs`
+//! has passed are sent a final `error` event and closed, so clients learn
+//! the response was cut short rather than seeing the connection drop.
+
+use crate::handlers::AppState;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::watch;
+
+/// How long to wait for connections to close after streams are ended,
+/// before exiting regardless.
+const CLOSE_GRACE: Duration = Duration::from_secs(5);
+
+/// Error type sent to streams ended by a shutdown.
+pub const SHUTTING_DOWN: &str = "server_shutting_down";
+
+/// Progress of a shutdown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Phase {
+    Running,
+    /// No new connections; in-flight requests may finish
+    Draining,
+    /// The drain deadline has passed; open streams must end
+    Closing,
+}
+
+/// Shutdown state shared between the signal handler and request handlers.
+#[derive(Debug)]
+pub struct Shutdown {
+    phase: watch::Sender<Phase>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            phase: watch::Sender::new(Phase::Running),
+        }
+    }
+}
+
+impl Shutdown {
+    /// Creates the shutdown state of a running server.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns true once a shutdown has started.
+    pub fn is_draining(&self) -> bool {
+        *self.phase.borrow() >= Phase::Draining
+    }
+
+    /// Completes when a shutdown starts.
+    pub async fn draining(&self) {
+        self.reached(Phase::Draining).await
+    }
+
+    /// Completes when open streams must be ended.
+    pub async fn closing(&self) {
+        self.reached(Phase::Closing).await
+    }
+
+    async fn reached(&self, phase: Phase) {
+        let mut receiver = self.phase.subscribe();
+        // The sender lives as long as `self`, so this cannot fail
+        let _ = receiver.wait_for(|current| *current >= phase).await;
+    }
+
+    fn advance(&self, phase: Phase) {
+        self.phase.send_if_modified(|current| {
+            let advanced = *current < phase;
+            if advanced {
+                *current = phase;
+            }
+            advanced
+        });
+    }
+}
+
+/// Spawns the task that drives a shutdown when a signal arrives.
+///
+/// # Arguments
+///
+/// * `state` - Application state holding the shutdown state
+/// * `timeout` - How long open streams may continue after the signal
+pub fn spawn(state: Arc<AppState>, timeout: Duration) {
+    tokio::spawn(async move {
+        let signal = wait_for_signal().await;
+        tracing::info!(
+            signal,
+            timeout_secs = timeout.as_secs(),
+            "Shutting down, draining in-flight requests"
+        );
+        state.shutdown.advance(Phase::Draining);
+
+        tokio::time::sleep(timeout).await;
+        tracing::warn!("Shutdown deadline reached, ending open streams");
+        state.shutdown.advance(Phase::Closing);
+    });
+}
+
+/// Completes once the server should stop waiting for connections to close.
+///
+/// Used to bound a graceful shutdown: connections that are still open a
+/// short while after streams were told to end, such as non-streaming
+/// requests waiting on Anthropic, are abandoned.
+///
+/// # Arguments
+///
+/// * `state` - Application state holding the shutdown state
+pub async fn deadline(state: Arc<AppState>) {
+    state.shutdown.closing().await;
+    tokio::time::sleep(CLOSE_GRACE).await;
+    tracing::warn!("Connections still open after shutdown deadline, exiting");
+}
+
+/// Waits for `SIGTERM` or `SIGINT`, returning the name of the signal.
+#[cfg(unix)]
+async fn wait_for_signal() -> &'static str {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(signal) => Some(signal),
+        Err(e) => {
+            tracing::warn!("Failed to install SIGTERM handler: {}", e);
+            None
+        }
+    };
+    let terminated = async {
+        match terminate.as_mut() {
+            Some(signal) => {
+                signal.recv().await;
+            }
+            None => std::future::pending().await,
+        }
+    };
+
+    tokio::select! {
+        _ = terminated => "SIGTERM",
+        _ = tokio::signal::ctrl_c() => "SIGINT",
+    }
+}
+
+/// Waits for Ctrl+C, returning the name of the signal.
+#[cfg(not(unix))]
+async fn wait_for_signal() -> &'static str {
+    let _ = tokio::signal::ctrl_c().await;
+    "SIGINT"
+}
@@ -0,0 +1,32 @@
+//! Incremental parsing of Server-Sent Events streams.
+//!
+//! Network chunks can end anywhere: inside an event, or inside a UTF-8
+//! character. [`SseBuffer`] keeps the raw bytes and only decodes an event
+//! once the blank line ending it has arrived, so characters split across
+//! chunks are never mangled.
+
+/// Bytes of a Server-Sent Events stream received so far.
+#[derive(Debug, Default)]
+pub(crate) struct SseBuffer {
+    bytes: Vec<u8>,
+}
+
+impl SseBuffer {
+    /// Appends a chunk received from the network.
+    pub fn push(&mut self, chunk: &[u8]) {
+        self.bytes.extend_from_slice(chunk);
+    }
+
+    /// Removes the next complete event from the buffer.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<String>` - The text of the event, without the blank line
+    ///   ending it, or `None` until a complete event has been received
+    pub fn next_event(&mut self) -> Option<String> {
+        let end = self.bytes.windows(2).position(|pair| pair == b"\n\n")?;
+        let event = String::from_utf8_lossy(&self.bytes[..end]).into_owned();
+        self.bytes.drain(..end + 2);
+        Some(event)
+    }
+}
@@ -0,0 +1,235 @@
+//! OpenTelemetry trace export.
+//!
+//! When `telemetry.otlp_endpoint` is configured, `tracing` spans are
+//! exported over OTLP/HTTP. Chat requests are traced with three nested
+//! spans carrying the OpenTelemetry GenAI semantic conventions:
+//! - `chat {model}` covers the request from admission to the end of the
+//!   response, including a streamed body
+//! - `stream` covers the lifecycle of a streamed response
+//! - `anthropic.messages` is the client span for the upstream call
+//!
+//! W3C trace context (`traceparent`/`tracestate`) is extracted from
+//! incoming requests and injected into outbound Anthropic requests, so the
+//! server joins the caller's trace and Anthropic calls appear within it.
+
+use crate::{
+    budget::DEFAULT_THINKING_BUDGET,
+    clients::anthropic::{default_max_tokens, DEFAULT_MODEL},
+    config::TelemetryConfig,
+    models::ApiRequest,
+};
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use futures::Stream;
+use opentelemetry::{
+    propagation::{Extractor, Injector},
+    trace::TracerProvider as _,
+    Context,
+};
+use std::{
+    pin::Pin,
+    task::{Context as TaskContext, Poll},
+};
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    trace::{SdkTracer, SdkTracerProvider},
+    Resource,
+};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Creates the tracer provider exporting to the configured endpoint.
+///
+/// Also installs the W3C trace context propagator.
+///
+/// # Arguments
+///
+/// * `config` - Telemetry configuration
+///
+/// # Returns
+///
+/// * `anyhow::Result<Option<SdkTracerProvider>>` - The provider, or `None`
+///   when no endpoint is configured. It must be kept alive, and shut down
+///   on exit to flush pending spans.
+///
+/// # Errors
+///
+/// Returns an error if the OTLP exporter cannot be built
+pub fn tracer_provider(config: &TelemetryConfig) -> anyhow::Result<Option<SdkTracerProvider>> {
+    let Some(endpoint) = &config.otlp_endpoint else {
+        return Ok(None);
+    };
+
+    use opentelemetry_otlp::WithExportConfig;
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(config.service_name.clone())
+                .build(),
+        )
+        .build();
+
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+    Ok(Some(provider))
+}
+
+/// Creates the `tracing` layer that turns spans into OpenTelemetry spans.
+pub fn layer<S>(provider: &SdkTracerProvider) -> OpenTelemetryLayer<S, SdkTracer>
+where
+    S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+    tracing_opentelemetry::layer().with_tracer(provider.tracer("deepclaude"))
+}
+
+/// Makes a span a child of the trace context in incoming request headers.
+///
+/// Does nothing when the headers carry no trace context or export is
+/// disabled.
+pub fn set_remote_parent(span: &tracing::Span, headers: &HeaderMap) {
+    let parent = opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(headers))
+    });
+    let _ = span.set_parent(parent);
+}
+
+/// Adds the current span's trace context to outbound request headers.
+pub fn inject_context(headers: &mut HeaderMap) {
+    let context: Context = tracing::Span::current().context();
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(headers))
+    });
+}
+
+/// Creates the span covering a chat request.
+///
+/// Response attributes are declared empty and filled in with
+/// [`record_response`] once the upstream response is known.
+pub fn chat_span(request: &ApiRequest) -> tracing::Span {
+    let model = request.model().unwrap_or(DEFAULT_MODEL);
+    tracing::info_span!(
+        "chat",
+        otel.name = %format!("chat {}", model),
+        otel.status_code = tracing::field::Empty,
+        gen_ai.operation.name = "chat",
+        gen_ai.system = "anthropic",
+        gen_ai.request.model = %model,
+        gen_ai.request.max_tokens = request.max_tokens().unwrap_or_else(|| default_max_tokens(model)),
+        gen_ai.request.thinking_budget_tokens = request.thinking_budget().unwrap_or(DEFAULT_THINKING_BUDGET),
+        gen_ai.response.model = tracing::field::Empty,
+        gen_ai.response.finish_reasons = tracing::field::Empty,
+        gen_ai.usage.input_tokens = tracing::field::Empty,
+        gen_ai.usage.output_tokens = tracing::field::Empty,
+        error.type = tracing::field::Empty,
+        deepclaude.stream = request.stream,
+        deepclaude.cost_usd = tracing::field::Empty,
+    )
+}
+
+/// Creates the client span for an upstream Anthropic call.
+///
+/// # Arguments
+///
+/// * `model` - The model requested upstream
+/// * `url` - The messages endpoint being called
+pub fn upstream_span(model: &str, url: &str) -> tracing::Span {
+    tracing::info_span!(
+        "anthropic.messages",
+        otel.kind = "client",
+        otel.status_code = tracing::field::Empty,
+        gen_ai.operation.name = "chat",
+        gen_ai.system = "anthropic",
+        gen_ai.request.model = %model,
+        http.request.method = "POST",
+        url.full = %url,
+        http.response.status_code = tracing::field::Empty,
+        error.type = tracing::field::Empty,
+    )
+}
+
+/// Records the outcome of a chat on its span.
+///
+/// # Arguments
+///
+/// * `span` - The chat span
+/// * `model` - The model that served the request
+/// * `input_tokens` - Input tokens used
+/// * `output_tokens` - Output tokens generated
+/// * `stop_reason` - Why the model stopped generating, if known
+/// * `cost` - Cost of the request in dollars
+pub fn record_response(
+    span: &tracing::Span,
+    model: &str,
+    input_tokens: u32,
+    output_tokens: u32,
+    stop_reason: Option<&str>,
+    cost: f64,
+) {
+    span.record("gen_ai.response.model", model);
+    span.record("gen_ai.usage.input_tokens", input_tokens);
+    span.record("gen_ai.usage.output_tokens", output_tokens);
+    if let Some(stop_reason) = stop_reason {
+        span.record("gen_ai.response.finish_reasons", stop_reason);
+    }
+    span.record("deepclaude.cost_usd", cost);
+}
+
+/// Marks a span as failed with the given error type.
+pub fn record_error(span: &tracing::Span, error_type: &str) {
+    span.record("otel.status_code", "ERROR");
+    span.record("error.type", error_type);
+}
+
+/// Stream adapter that enters a span each time the stream is polled.
+///
+/// `tracing`'s `Instrument` only covers futures; streams that outlive the
+/// call that created them need this to keep their work inside a span.
+pub struct InSpan<S> {
+    inner: S,
+    span: tracing::Span,
+}
+
+impl<S> InSpan<S> {
+    /// Wraps a stream so it is polled inside `span`.
+    pub fn new(inner: S, span: tracing::Span) -> Self {
+        Self { inner, span }
+    }
+}
+
+impl<S: Stream + Unpin> Stream for InSpan<S> {
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let _entered = this.span.enter();
+        Pin::new(&mut this.inner).poll_next(cx)
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
@@ -0,0 +1,60 @@
+//! TLS termination.
+//!
+//! When `[server.tls]` is configured the server serves HTTPS with rustls.
+//! The certificate and key are loaded at startup and loaded again on every
+//! config reload, so a renewed certificate takes effect on `SIGHUP` without
+//! dropping connections.
+
+use crate::config::TlsConfig;
+use axum_server::tls_rustls::RustlsConfig;
+
+/// Loads the configured certificate and key.
+///
+/// # Arguments
+///
+/// * `config` - TLS configuration
+///
+/// # Returns
+///
+/// * `anyhow::Result<RustlsConfig>` - The server TLS configuration, which can
+///   be reloaded in place with [`reload`]
+///
+/// # Errors
+///
+/// Returns an error if the certificate or key cannot be read or parsed
+pub async fn load(config: &TlsConfig) -> anyhow::Result<RustlsConfig> {
+    // Only one provider is compiled in; installing it again is a no-op
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    RustlsConfig::from_pem_file(&config.cert_path, &config.key_path)
+        .await
+        .map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to load TLS certificate {} and key {}: {}",
+                config.cert_path,
+                config.key_path,
+                e
+            )
+        })
+}
+
+/// Reloads the certificate and key in the background.
+///
+/// If they cannot be loaded the current certificate stays in use.
+///
+/// # Arguments
+///
+/// * `tls` - The server TLS configuration to update
+/// * `config` - TLS configuration naming the files to load
+pub fn reload(tls: RustlsConfig, config: TlsConfig) {
+    tokio::spawn(async move {
+        match tls.reload_from_pem_file(&config.cert_path, &config.key_path).await {
+            Ok(()) => tracing::info!(cert_path = %config.cert_path, "TLS certificate reloaded"),
+            Err(e) => tracing::error!(
+                cert_path = %config.cert_path,
+                "TLS certificate reload failed, keeping previous certificate: {}",
+                e
+            ),
+        }
+    });
+}
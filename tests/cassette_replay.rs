@@ -0,0 +1,153 @@
+//! Offline tests of the chat handlers, replaying upstream exchanges
+//! recorded in `tests/fixtures/cassettes`.
+//!
+//! The cassettes were recorded against `mock-anthropic` with
+//! `[cassettes] mode = "record"`; a request only finds its cassette if the
+//! body sent upstream is unchanged, so changes to request building show up
+//! here as `cassette_not_found` errors.
+
+mod common;
+
+use common::{chat_request, post_chat, serve, sse_events, streamed_text};
+use deepclaude::config::{CassetteConfig, CassetteMode, Config};
+
+async fn replay_server() -> String {
+    serve(Config {
+        cassettes: CassetteConfig {
+            mode: CassetteMode::Replay,
+            dir: concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/cassettes").to_string(),
+        },
+        ..Config::default()
+    })
+    .await
+}
+
+#[tokio::test]
+async fn chat_replays_recorded_response() {
+    let base_url = replay_server().await;
+
+    let response = post_chat(&base_url, &chat_request("What is a cassette?", false)).await;
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+
+    let content = body["content"].as_array().unwrap();
+    assert_eq!(content[0]["type"], "thinking");
+    assert!(content[0]["signature"].is_string());
+    assert_eq!(content[1]["type"], "text");
+    assert_eq!(content[1]["text"], "Mock response to: What is a cassette?");
+
+    let usage = &body["combined_usage"]["anthropic_usage"];
+    assert!(usage["input_tokens"].as_u64().unwrap() > 0);
+    assert!(usage["output_tokens"].as_u64().unwrap() > 0);
+    assert!(body["combined_usage"]["total_cost"]
+        .as_str()
+        .unwrap()
+        .starts_with('$'));
+    assert!(body["combined_usage"]["cost_usd"].as_f64().unwrap() > 0.0);
+}
+
+#[tokio::test]
+async fn chat_stream_replays_recorded_events() {
+    let base_url = replay_server().await;
+
+    let response = post_chat(&base_url, &chat_request("Stream a cassette", true)).await;
+    assert_eq!(response.status(), 200);
+    let events = sse_events(response).await;
+
+    let names = events
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names.first(), Some(&"start"));
+    assert_eq!(&names[names.len() - 3..], ["usage", "message_stop", "done"]);
+    assert!(events
+        .iter()
+        .any(|(_, data)| data["content"][0]["type"] == "thinking_delta"));
+    assert!(events
+        .iter()
+        .any(|(_, data)| data["content"][0]["type"] == "signature_delta"));
+    assert_eq!(
+        streamed_text(&events),
+        "Mock response to: Stream a cassette"
+    );
+
+    let (_, usage) = &events[names.len() - 3];
+    assert!(
+        usage["usage"]["anthropic_usage"]["output_tokens"]
+            .as_u64()
+            .unwrap()
+            > 0
+    );
+}
+
+#[tokio::test]
+async fn chat_stream_reports_recorded_error_event() {
+    let base_url = replay_server().await;
+
+    let response = post_chat(
+        &base_url,
+        &chat_request("Fail midway [mock:stream_error]", true),
+    )
+    .await;
+    assert_eq!(response.status(), 200);
+    let events = sse_events(response).await;
+
+    let (name, error) = events.last().unwrap();
+    assert_eq!(name, "error");
+    assert!(error["message"].as_str().unwrap().contains("Overloaded"));
+    assert!(events.iter().all(|(name, _)| name != "done"));
+}
+
+#[tokio::test]
+async fn chat_returns_recorded_upstream_error() {
+    let base_url = replay_server().await;
+
+    let response = post_chat(
+        &base_url,
+        &chat_request("Too busy [mock:overloaded]", false),
+    )
+    .await;
+    assert_eq!(response.status(), 529);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["type"], "anthropic_overloaded_error");
+    assert_eq!(body["error"]["code"], "529");
+}
+
+#[tokio::test]
+async fn chat_without_cassette_fails_offline() {
+    let base_url = replay_server().await;
+
+    let response = post_chat(&base_url, &chat_request("Never recorded", false)).await;
+    assert!(!response.status().is_success());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["error"]["type"]
+        .as_str()
+        .unwrap()
+        .ends_with("cassette_not_found"));
+}
+
+#[tokio::test]
+async fn cors_exposes_response_headers() {
+    let base_url = replay_server().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/", base_url))
+        .header("Origin", "http://localhost:3000")
+        .header("X-Anthropic-API-Token", common::TEST_TOKEN)
+        .json(&chat_request("What is a cassette?", false))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let exposed = response.headers()["access-control-expose-headers"]
+        .to_str()
+        .unwrap();
+    for header in [
+        "x-request-id",
+        "retry-after",
+        "x-budget-remaining-usd",
+        "anthropic-ratelimit-tokens-remaining",
+    ] {
+        assert!(exposed.contains(header), "{} is not exposed", header);
+    }
+}
@@ -0,0 +1,74 @@
+//! Helpers shared by the integration tests: serving the API on a local
+//! port and calling it.
+
+#![allow(dead_code)]
+
+use deepclaude::{config::Config, router, AppState};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+/// Token sent by test callers; never reaches Anthropic.
+pub const TEST_TOKEN: &str = "test-token";
+
+/// Serves the API with a configuration on an ephemeral local port.
+///
+/// # Returns
+///
+/// * `String` - The base URL of the server
+pub async fn serve(config: Config) -> String {
+    let state = Arc::new(AppState::new(config).expect("application state"));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind test server");
+    let addr = listener.local_addr().expect("test server address");
+    tokio::spawn(async move {
+        axum::serve(listener, router(state).into_make_service())
+            .await
+            .expect("serve test server");
+    });
+    format!("http://{}", addr)
+}
+
+/// Builds a chat request body with a single user message.
+pub fn chat_request(prompt: &str, stream: bool) -> Value {
+    json!({
+        "stream": stream,
+        "messages": [{"role": "user", "content": prompt}],
+    })
+}
+
+/// Posts a chat request as `TEST_TOKEN`.
+pub async fn post_chat(base_url: &str, body: &Value) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/", base_url))
+        .header("X-Anthropic-API-Token", TEST_TOKEN)
+        .json(body)
+        .send()
+        .await
+        .expect("chat request")
+}
+
+/// Reads a Server-Sent Events response into its event names and data.
+pub async fn sse_events(response: reqwest::Response) -> Vec<(String, Value)> {
+    let body = response.text().await.expect("stream body");
+    body.split("\n\n")
+        .filter_map(|event| {
+            let name = event
+                .lines()
+                .find_map(|line| line.strip_prefix("event: "))?;
+            let data = event.lines().find_map(|line| line.strip_prefix("data: "))?;
+            Some((name.to_string(), serde_json::from_str(data).ok()?))
+        })
+        .collect()
+}
+
+/// Concatenates the `text_delta` content of streamed events.
+pub fn streamed_text(events: &[(String, Value)]) -> String {
+    events
+        .iter()
+        .filter(|(name, _)| name == "content")
+        .flat_map(|(_, data)| data["content"].as_array().cloned().unwrap_or_default())
+        .filter(|block| block["type"] == "text_delta")
+        .filter_map(|block| block["text"].as_str().map(String::from))
+        .collect()
+}
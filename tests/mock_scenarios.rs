@@ -0,0 +1,508 @@
+//! Tests of the chat handlers against `mock-anthropic`, including its
+//! scripted failure scenarios.
+//!
+//! Each test starts its own mock on a free port and points deepclaude at it
+//! with `[anthropic] base_url`.
+
+mod common;
+
+use common::{chat_request, post_chat, serve, sse_events, streamed_text};
+use deepclaude::config::{
+    AdminConfig, AnthropicConfig, BudgetLimit, BudgetsConfig, CassetteConfig, CassetteMode, Config,
+    ConversationsConfig, FallbackConfig, FallbackStep, LedgerConfig, ResponseCacheConfig,
+};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
+
+/// Model the chat requests ask for.
+const MODEL: &str = "claude-3-7-sonnet-20250219";
+
+/// A running `mock-anthropic`, killed when dropped.
+struct Mock {
+    child: Child,
+    base_url: String,
+}
+
+impl Mock {
+    /// Starts the mock and waits until it accepts connections.
+    async fn start(args: &[&str]) -> Self {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("free port")
+            .port();
+        let child = Command::new(env!("CARGO_BIN_EXE_mock-anthropic"))
+            .args(["--port", &port.to_string()])
+            .args(args)
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("start mock-anthropic");
+        let mock = Self {
+            child,
+            base_url: format!("http://127.0.0.1:{}", port),
+        };
+
+        for _ in 0..100 {
+            if tokio::net::TcpStream::connect(("127.0.0.1", port))
+                .await
+                .is_ok()
+            {
+                return mock;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("mock-anthropic did not start on port {}", port);
+    }
+
+    /// Serves deepclaude with Anthropic requests sent to this mock.
+    async fn serve(&self, config: Config) -> String {
+        serve(Config {
+            anthropic: AnthropicConfig {
+                base_url: self.base_url.clone(),
+            },
+            ..config
+        })
+        .await
+    }
+}
+
+impl Drop for Mock {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Builds a chat request for `MODEL` with extended thinking.
+fn thinking_request(prompt: &str, stream: bool) -> serde_json::Value {
+    let mut request = chat_request(prompt, stream);
+    request["anthropic_config"] = json!({
+        "body": {
+            "model": MODEL,
+            "max_tokens": 4096,
+            "thinking": {"type": "enabled", "budget_tokens": 2048},
+        },
+    });
+    request
+}
+
+#[tokio::test]
+async fn overloaded_upstream_returns_anthropic_error() {
+    let mock = Mock::start(&[]).await;
+    let base_url = mock.serve(Config::default()).await;
+
+    let response = post_chat(&base_url, &chat_request("Busy [mock:overloaded]", false)).await;
+    assert_eq!(response.status(), 529);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["type"], "anthropic_overloaded_error");
+    assert_eq!(body["error"]["code"], "529");
+}
+
+#[tokio::test]
+async fn stream_error_ends_stream_with_error_event() {
+    let mock = Mock::start(&[]).await;
+    let base_url = mock.serve(Config::default()).await;
+
+    let response = post_chat(&base_url, &chat_request("Fail [mock:stream_error]", true)).await;
+    assert_eq!(response.status(), 200);
+    let events = sse_events(response).await;
+
+    assert_eq!(events.first().map(|(name, _)| name.as_str()), Some("start"));
+    let (name, error) = events.last().unwrap();
+    assert_eq!(name, "error");
+    assert!(error["message"].as_str().unwrap().contains("Overloaded"));
+    assert!(events.iter().all(|(name, _)| name != "done"));
+}
+
+#[tokio::test]
+async fn failed_stream_is_charged_to_budgets() {
+    let mock = Mock::start(&[]).await;
+    let base_url = mock
+        .serve(Config {
+            budgets: BudgetsConfig {
+                default_key: Some(BudgetLimit {
+                    daily_usd: Some(10.0),
+                    monthly_usd: None,
+                }),
+                ..BudgetsConfig::default()
+            },
+            ..Config::default()
+        })
+        .await;
+
+    let response = post_chat(&base_url, &chat_request("Fail [mock:stream_error]", true)).await;
+    assert_eq!(response.headers()["x-budget-remaining-usd"], "10.0000");
+    let events = sse_events(response).await;
+    assert_eq!(events.last().map(|(name, _)| name.as_str()), Some("error"));
+
+    // The stream's input was billed, so less than the full budget remains
+    let response = post_chat(&base_url, &chat_request("Hello [mock:text]", false)).await;
+    let remaining: f64 = response.headers()["x-budget-remaining-usd"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(remaining < 10.0);
+}
+
+#[tokio::test]
+async fn oversized_max_tokens_is_checked_against_budgets() {
+    let mock = Mock::start(&[]).await;
+    let base_url = mock
+        .serve(Config {
+            budgets: BudgetsConfig {
+                default_key: Some(BudgetLimit {
+                    daily_usd: Some(10.0),
+                    monthly_usd: None,
+                }),
+                ..BudgetsConfig::default()
+            },
+            ..Config::default()
+        })
+        .await;
+
+    // 2^32 + 100 must not wrap around to a cheap 100 tokens
+    let mut request = thinking_request("Hello [mock:text]", false);
+    request["anthropic_config"]["body"]["max_tokens"] = json!(4_294_967_396_u64);
+    let response = post_chat(&base_url, &request).await;
+    assert_eq!(response.status(), 402);
+}
+
+#[tokio::test]
+async fn stream_keeps_characters_split_across_chunks() {
+    let mock = Mock::start(&[]).await;
+    let base_url = mock.serve(Config::default()).await;
+
+    // The mock splits streams every 48 bytes, inside some of these
+    // multi-byte characters
+    let prompt = "Grüße, 日本語, ünïcödé ✓ ".repeat(8);
+    let response = post_chat(&base_url, &chat_request(&prompt, true)).await;
+    assert_eq!(response.status(), 200);
+    let events = sse_events(response).await;
+
+    let text = streamed_text(&events);
+    assert!(!text.contains('\u{FFFD}'));
+    assert_eq!(text, format!("Mock response to: {}", prompt));
+}
+
+#[tokio::test]
+async fn rate_limit_headers_are_forwarded() {
+    let mock = Mock::start(&[]).await;
+    let base_url = mock.serve(Config::default()).await;
+
+    let response = post_chat(&base_url, &chat_request("Hello [mock:text]", false)).await;
+    assert_eq!(response.status(), 200);
+    let headers = response.headers();
+    assert_eq!(headers["anthropic-ratelimit-requests-limit"], "50");
+    assert_eq!(headers["anthropic-ratelimit-tokens-remaining"], "79000");
+    assert!(headers.contains_key("x-anthropic-request-id"));
+
+    let response = post_chat(&base_url, &chat_request("Again [mock:rate_limit]", false)).await;
+    assert_eq!(response.status(), 429);
+    let headers = response.headers();
+    assert_eq!(headers["retry-after"], "5");
+    assert_eq!(headers["anthropic-ratelimit-tokens-remaining"], "0");
+    assert_eq!(headers["anthropic-ratelimit-requests-limit"], "50");
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["type"], "anthropic_rate_limit_error");
+    assert_eq!(body["error"]["code"], "429");
+}
+
+#[tokio::test]
+async fn rate_limit_headers_are_forwarded_on_stream_errors() {
+    let mock = Mock::start(&[]).await;
+    let base_url = mock.serve(Config::default()).await;
+
+    let response = post_chat(&base_url, &chat_request("Hi [mock:rate_limit]", true)).await;
+    let headers = response.headers();
+    assert_eq!(headers["retry-after"], "5");
+    assert_eq!(headers["anthropic-ratelimit-tokens-remaining"], "0");
+    let events = sse_events(response).await;
+    assert_eq!(events.last().map(|(name, _)| name.as_str()), Some("error"));
+}
+
+#[tokio::test]
+async fn overloaded_model_falls_back() {
+    let mock = Mock::start(&["--overloaded-model", MODEL]).await;
+    let base_url = mock
+        .serve(Config {
+            fallbacks: FallbackConfig {
+                chains: HashMap::from([(
+                    MODEL.to_string(),
+                    vec![FallbackStep {
+                        model: Some("claude-3-5-haiku-20241022".to_string()),
+                        thinking: false,
+                    }],
+                )]),
+            },
+            ..Config::default()
+        })
+        .await;
+
+    let response = post_chat(&base_url, &thinking_request("Hello", false)).await;
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let fallback = &body["fallback"];
+    assert_eq!(fallback["requested_model"], MODEL);
+    assert_eq!(fallback["model"], "claude-3-5-haiku-20241022");
+    assert_eq!(fallback["thinking"], false);
+    assert_eq!(fallback["errors"], json!(["overloaded_error (529)"]));
+
+    let response = post_chat(&base_url, &thinking_request("Hello", true)).await;
+    assert_eq!(response.status(), 200);
+    let events = sse_events(response).await;
+    let (name, start) = &events[0];
+    assert_eq!(name, "start");
+    assert_eq!(start["fallback"]["model"], "claude-3-5-haiku-20241022");
+    assert_eq!(streamed_text(&events), "Mock response to: Hello");
+}
+
+#[tokio::test]
+async fn fallback_fits_output_and_thinking_budget_to_model() {
+    let mock = Mock::start(&["--overloaded-model", MODEL]).await;
+    let dir = std::env::temp_dir().join(format!("deepclaude-fallback-{}", std::process::id()));
+    let base_url = mock
+        .serve(Config {
+            fallbacks: FallbackConfig {
+                chains: HashMap::from([(
+                    MODEL.to_string(),
+                    vec![FallbackStep {
+                        model: Some("claude-3-haiku-20240307".to_string()),
+                        thinking: true,
+                    }],
+                )]),
+            },
+            // Recording captures the request sent to the fallback model
+            cassettes: CassetteConfig {
+                mode: CassetteMode::Record,
+                dir: dir.display().to_string(),
+            },
+            ..Config::default()
+        })
+        .await;
+
+    let response = post_chat(&base_url, &thinking_request("Hello", false)).await;
+    assert_eq!(response.status(), 200);
+
+    let requests = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| {
+            let cassette: serde_json::Value =
+                serde_json::from_slice(&std::fs::read(entry.unwrap().path()).unwrap()).unwrap();
+            cassette["request"].clone()
+        })
+        .collect::<Vec<_>>();
+    let _ = std::fs::remove_dir_all(&dir);
+    let request = requests
+        .iter()
+        .find(|request| request["model"] == "claude-3-haiku-20240307")
+        .unwrap();
+    assert_eq!(request["max_tokens"], 4096);
+    assert_eq!(request["thinking"]["budget_tokens"], 2048);
+}
+
+#[tokio::test]
+async fn worst_case_cost_covers_fallback_models() {
+    let mock = Mock::start(&[]).await;
+    let base_url = mock
+        .serve(Config {
+            fallbacks: FallbackConfig {
+                chains: HashMap::from([(
+                    MODEL.to_string(),
+                    vec![FallbackStep {
+                        model: Some("claude-3-opus-20240229".to_string()),
+                        thinking: true,
+                    }],
+                )]),
+            },
+            ..Config::default()
+        })
+        .await;
+
+    let count: serde_json::Value = reqwest::Client::new()
+        .post(format!("{}/v1/count_tokens", base_url))
+        .header("X-Anthropic-API-Token", common::TEST_TOKEN)
+        .json(&thinking_request("Hello", false))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    // 4096 output tokens at Opus rather than Sonnet prices
+    assert_eq!(count["max_output_tokens"], 4096);
+    assert_eq!(count["worst_case_cost"], "$0.308");
+}
+
+#[tokio::test]
+async fn overloaded_model_without_fallback_fails() {
+    let mock = Mock::start(&["--overloaded-model", MODEL]).await;
+    let base_url = mock.serve(Config::default()).await;
+
+    let response = post_chat(&base_url, &thinking_request("Hello", false)).await;
+    assert!(!response.status().is_success());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["type"], "anthropic_overloaded_error");
+}
+
+#[tokio::test]
+async fn metrics_label_unreported_models_as_other() {
+    let mock = Mock::start(&[]).await;
+    let base_url = mock.serve(Config::default()).await;
+
+    let mut request = chat_request("Hello [mock:bad_request]", false);
+    request["anthropic_config"] = json!({"body": {"model": "made-up-model"}});
+    let response = post_chat(&base_url, &request).await;
+    assert_eq!(response.status(), 400);
+    let response = post_chat(&base_url, &thinking_request("Hello", false)).await;
+    assert_eq!(response.status(), 200);
+
+    let metrics = reqwest::get(format!("{}/metrics", base_url))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(!metrics.contains("made-up-model"));
+    assert!(!metrics.contains("key_id"));
+    assert!(metrics.contains(r#"model="other""#));
+    assert!(metrics.contains(&format!(r#"model="{}""#, MODEL)));
+}
+
+#[tokio::test]
+async fn ledger_export_escapes_formulas() {
+    let mock = Mock::start(&[]).await;
+    let path = std::env::temp_dir().join(format!("deepclaude-ledger-{}.jsonl", std::process::id()));
+    let base_url = mock
+        .serve(Config {
+            ledger: LedgerConfig {
+                path: Some(path.to_string_lossy().into_owned()),
+            },
+            admin: AdminConfig {
+                token: Some("admin-token".to_string()),
+            },
+            ..Config::default()
+        })
+        .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/", base_url))
+        .header("X-Anthropic-API-Token", common::TEST_TOKEN)
+        .header("X-DeepClaude-Project", "=HYPERLINK(\"http://example.com\")")
+        .json(&chat_request("Hello [mock:text]", false))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    // Records are written in the background
+    let mut export = String::new();
+    for _ in 0..50 {
+        export = reqwest::Client::new()
+            .get(format!("{}/admin/usage/export", base_url))
+            .bearer_auth("admin-token")
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        if export.lines().count() > 1 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let _ = std::fs::remove_file(&path);
+    assert!(export.contains(r#""'=HYPERLINK(""http://example.com"")""#));
+}
+
+#[tokio::test]
+async fn conversation_turns_are_saved() {
+    let mock = Mock::start(&[]).await;
+    let path = std::env::temp_dir().join(format!(
+        "deepclaude-conversations-{}.redb",
+        std::process::id()
+    ));
+    let base_url = mock
+        .serve(Config {
+            conversations: ConversationsConfig {
+                path: Some(path.display().to_string()),
+            },
+            ..Config::default()
+        })
+        .await;
+    let client = reqwest::Client::new();
+
+    let conversation: serde_json::Value = client
+        .post(format!("{}/conversations", base_url))
+        .header("X-Anthropic-API-Token", common::TEST_TOKEN)
+        .json(&json!({"title": "Test"}))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let id = conversation["id"].as_str().unwrap();
+
+    for (prompt, stream) in [("First [mock:text]", false), ("Second [mock:text]", true)] {
+        let mut request = chat_request(prompt, stream);
+        request["conversation_id"] = json!(id);
+        let response = post_chat(&base_url, &request).await;
+        assert_eq!(response.status(), 200);
+        response.bytes().await.unwrap();
+    }
+
+    let conversation: serde_json::Value = client
+        .get(format!("{}/conversations/{}", base_url, id))
+        .header("X-Anthropic-API-Token", common::TEST_TOKEN)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let _ = std::fs::remove_file(&path);
+    let messages = conversation["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 4);
+    assert_eq!(messages[3]["role"], "assistant");
+}
+
+#[tokio::test]
+async fn response_cache_is_not_shared_between_keys() {
+    let mock = Mock::start(&[]).await;
+    let base_url = mock
+        .serve(Config {
+            response_cache: ResponseCacheConfig {
+                enabled: true,
+                ..ResponseCacheConfig::default()
+            },
+            ..Config::default()
+        })
+        .await;
+    let cache_result = |token: &'static str| {
+        let base_url = base_url.clone();
+        async move {
+            let response = reqwest::Client::new()
+                .post(format!("{}/", base_url))
+                .header("X-Anthropic-API-Token", token)
+                .json(&chat_request("Cache me [mock:text]", false))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 200);
+            response.headers()["x-deepclaude-cache"]
+                .to_str()
+                .unwrap()
+                .to_string()
+        }
+    };
+
+    assert_eq!(cache_result("key-a").await, "miss");
+    assert_eq!(cache_result("key-a").await, "hit");
+    assert_eq!(cache_result("key-b").await, "miss");
+    assert_eq!(cache_result("key-b").await, "hit");
+}